@@ -0,0 +1,79 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use byteorder::{ByteOrder, LittleEndian};
+use find_folder::Search;
+
+use rs6502::{Assembler, CodeSegment, Cpu};
+
+use vm::{Options, VirtualMachine};
+
+/// The y position the ship has to reach to pass the level
+pub const FINISH_LINE: i32 = 0x8C;
+
+pub fn assemble<P>(path: P) -> Result<Vec<CodeSegment>, String>
+    where P: AsRef<Path>
+{
+    let path = path.as_ref();
+    let mut source = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut source))
+        .map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+
+    let mut assembler = Assembler::new();
+    assembler.assemble_string(source, 0xC000)
+        .map_err(|err| format!("Unable to assemble {}: {:?}", path.display(), err))
+}
+
+/// Assembles the level given with --level, or the level's own code, into the
+/// VM along with its symbols, and resets the CPU to run it
+pub fn load(vm: &mut VirtualMachine, options: &Options) -> Result<(), String> {
+    let level_file = match options.level {
+        Some(ref level_file) => level_file.clone(),
+        None => default_level_file()?,
+    };
+
+    let segments = assemble(&level_file)?;
+    vm.load_code_segments(segments);
+    vm.load_symbols(&level_file)
+        .map_err(|err| format!("Unable to read symbols from {}: {}", level_file.display(), err))?;
+    vm.cpu.reset();
+
+    Ok(())
+}
+
+/// The level code that ships with the source, found by searching up from the
+/// working directory
+fn default_level_file() -> Result<PathBuf, String> {
+    Search::Parents(3)
+        .for_folder("training-1")
+        .map(|folder| folder.join("level.asm"))
+        .map_err(|_| "Unable to find training-1/level.asm. Use --level to give the level file.".to_string())
+}
+
+pub fn init_cpu_mem(cpu: &mut Cpu, window_width: u32, ship_width: u32) {
+    cpu.flags.interrupt_disabled = false;
+
+    LittleEndian::write_u16(&mut cpu.memory[0..],
+                            window_width as u16 / 2 - (ship_width as u16 / 2));
+    cpu.memory[0x02] = 0xFF;
+    cpu.memory[0x03] = 0x01;
+    cpu.memory[0x05] = 0x05;
+    cpu.memory[0x06] = 0x00;
+}
+
+/// Reads the 16-bit ship position out of the zero page
+pub fn ship_position(memory: &[u8]) -> (i32, i32) {
+    let x = LittleEndian::read_u16(&memory[0x00..]);
+    let y = LittleEndian::read_u16(&memory[0x02..]);
+    (x as i32, y as i32)
+}
+
+/// Pulls the ship back so it can't go past a certain spot
+pub fn restrict_ship(cpu: &mut Cpu, ship_y: i32) {
+    if ship_y <= 0x190 && ship_y >= 0x100 && cpu.memory[0x04] != 0 {
+        cpu.memory[0x02] = 0x90;
+        cpu.memory[0x03] = 0x01;
+    }
+}
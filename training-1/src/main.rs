@@ -5,37 +5,54 @@ extern crate rs6502;
 extern crate sdl2;
 extern crate vm;
 
+mod level;
 mod ship;
+mod terminal;
 
-use std::path::Path;
-
-use byteorder::{ByteOrder, LittleEndian};
+use std::io::{self, Write};
+use std::process;
 
 use find_folder::Search;
 
 use sdl2::event::Event;
-use sdl2::keyboard::*;
 use sdl2::image::LoadTexture;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::{Renderer, TextureQuery};
 
-use rs6502::{Assembler, CodeSegment, Cpu};
-use vm::{Position, Text, GameCore};
+use vm::{GlyphAtlas, KeyAction, Options, Position, Text, GameCore};
 
 const FPS_STEP: u32 = 1000 / 60;
 
 fn main() {
-    let window_width = 1280;
-    let window_height = 720;
+    let options = Options::from_args();
+    if options.headless {
+        terminal::run(options);
+        return;
+    }
+
+    let config = match options.load_configuration() {
+        Ok(config) => config,
+        Err(err) => {
+            writeln!(io::stderr(), "{}", err).unwrap();
+            process::exit(1);
+        }
+    };
+    let (window_width, window_height) = config.get_window_size();
+    let (up_key, down_key, quit_key) = (config.get_key(KeyAction::Up),
+                                        config.get_key(KeyAction::Down),
+                                        config.get_key(KeyAction::Quit));
 
     let sdl_context = sdl2::init().unwrap();
     let ttf_context = sdl2::ttf::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
-    let window = video_subsystem.window("hakka", window_width, window_height)
-        .build()
-        .unwrap();
+    let mut window_builder = video_subsystem.window("hakka", window_width, window_height);
+    window_builder.resizable();
+    if options.fullscreen(&config) {
+        window_builder.fullscreen_desktop();
+    }
+    let window = window_builder.build().unwrap();
 
     let (window_width, _) = window.size();
 
@@ -44,38 +61,43 @@ fn main() {
         .build()
         .unwrap();
 
-    let local = Search::Parents(3).for_folder("training-1").unwrap();
     let assets = Search::KidsThenParents(3, 3).for_folder("assets").unwrap();
 
     let ship_texture = renderer.load_texture(&assets.join("ship.png")).unwrap();
     let ship_flame_texture = renderer.load_texture(&assets.join("ship-flame.png"))
         .unwrap();
 
-    let font = assets.join("FantasqueSansMono-Bold.ttf");
+    let font = match config.get_font_path() {
+        Some(path) => path.to_path_buf(),
+        None => assets.join("FantasqueSansMono-Bold.ttf"),
+    };
 
-    let finish_text = Text::new(&ttf_context,
-                                &mut renderer,
+    let mut finish_atlas = GlyphAtlas::new(&ttf_context, &renderer, &font, 56);
+    let mut win_atlas = GlyphAtlas::new(&ttf_context, &renderer, &font, 64);
+    let finish_text = Text::new(&finish_atlas,
                                 "FINISH",
                                 Position::HorizontalCenter((window_width / 2) as i32, 25),
-                                56,
-                                Color::RGBA(0, 0, 0, 255),
-                                font.to_str().unwrap());
-    let win_text = Text::new(&ttf_context,
-                             &mut renderer,
+                                Color::RGBA(0, 0, 0, 255));
+    let win_text = Text::new(&win_atlas,
                              "PASSED",
                              Position::HorizontalCenter((window_width / 2) as i32, 330),
-                             64,
-                             Color::RGBA(0, 0, 0, 255),
-                             font.to_str().unwrap());
+                             Color::RGBA(0, 0, 0, 255));
 
-    let mut game_core = GameCore::new(&ttf_context, &mut renderer, font.to_str().unwrap()); 
+    let mut game_core = GameCore::new(&ttf_context,
+                                      &mut renderer,
+                                      font.to_str().unwrap(),
+                                      video_subsystem.clipboard(),
+                                      config);
+    options.apply(&mut game_core);
+    game_core.vm.console.use_level_history("training-1");
 
     let TextureQuery { width: ship_width, .. } = ship_texture.query();
-    init_cpu_mem(&mut game_core.vm.cpu, &mut renderer, ship_width);
+    level::init_cpu_mem(&mut game_core.vm.cpu, window_width, ship_width);
 
-    let segments = assemble(local.join("level.asm"));
-    game_core.vm.load_code_segments(segments);
-    game_core.vm.cpu.reset();
+    if let Err(err) = level::load(&mut game_core.vm, &options) {
+        writeln!(io::stderr(), "{}", err).unwrap();
+        process::exit(1);
+    }
 
     let mut events = sdl_context.event_pump().unwrap();
 
@@ -94,27 +116,22 @@ fn main() {
             if !game_core.vm.console.visible {
                 match event {
                     Event::Quit { .. } => break 'running,
-                    Event::KeyUp { keycode, .. } => {
-                        match keycode {
-                            Some(Keycode::Up) |
-                            Some(Keycode::Down) => {
-                                game_core.vm.cpu.memory[0x04] = 0;
-                            }
-                            _ => (),
+                    Event::KeyUp { scancode: Some(scancode), .. } => {
+                        if scancode == up_key || scancode == down_key {
+                            game_core.vm.cpu.memory[0x04] = 0;
                         }
                     }
-                    Event::KeyDown { keycode, .. } => {
-                        match keycode {
-                            Some(Keycode::Escape) => break 'running,
-
-                            // Movement
-                            Some(Keycode::Up) => {
-                                game_core.vm.cpu.memory[0x04] = 38;
-                            }
-                            Some(Keycode::Down) => {
-                                game_core.vm.cpu.memory[0x04] = 40;
-                            }
-                            _ => (),
+                    Event::KeyDown { scancode: Some(scancode), .. } => {
+                        if scancode == quit_key {
+                            break 'running;
+                        }
+
+                        // Movement. The level reads the key codes of the
+                        // arrow keys, whichever keys are bound.
+                        if scancode == up_key {
+                            game_core.vm.cpu.memory[0x04] = 38;
+                        } else if scancode == down_key {
+                            game_core.vm.cpu.memory[0x04] = 40;
                         }
                     }
                     _ => (),
@@ -125,11 +142,7 @@ fn main() {
         if !level_complete {
             ship.process(&game_core.vm.cpu.memory[..]);
 
-            // Pull the ship back so it can't go past a certain spot
-            if ship.y <= 0x190 && ship.y >= 0x100 && game_core.vm.cpu.memory[0x04] != 0 {
-                game_core.vm.cpu.memory[0x02] = 0x90;
-                game_core.vm.cpu.memory[0x03] = 0x01;
-            }
+            level::restrict_ship(&mut game_core.vm.cpu, ship.y);
         }
 
         let now = sdl_context.timer().unwrap().ticks();
@@ -150,19 +163,19 @@ fn main() {
                 if !game_core.vm.cpu.flags.interrupt_disabled {
                     if level_complete {
                         draw_passed_background(&mut renderer);
-                        win_text.render(&mut renderer);
+                        win_text.render(&mut win_atlas, &mut renderer);
                     }
                     draw_finish_background(&mut renderer);
-                    finish_text.render(&mut renderer);
+                    finish_text.render(&mut finish_atlas, &mut renderer);
                     if game_core.vm.cpu.memory[0x07] > 0 {
                         ship.render_flame(&mut renderer);
                     }
                     ship.render(&mut renderer);
-                    if ship.y <= 0x8C {
+                    if ship.y <= level::FINISH_LINE {
                         level_complete = true;
                     }
                 }
-                game_core.vm.render(&mut renderer);
+                game_core.render(&mut renderer);
                 renderer.present();
                 last_fps = now;
             }
@@ -177,25 +190,6 @@ fn main() {
     }
 }
 
-fn assemble<P>(path: P) -> Vec<CodeSegment>
-    where P: AsRef<Path>
-{
-    let mut assembler = Assembler::new();
-    assembler.assemble_file(path, 0xC000).unwrap()
-}
-
-fn init_cpu_mem(cpu: &mut Cpu, renderer: &mut Renderer, ship_width: u32) {
-    cpu.flags.interrupt_disabled = false;
-
-    LittleEndian::write_u16(&mut cpu.memory[0..],
-                            renderer.window().unwrap().size().0 as u16 / 2 -
-                            (ship_width as u16 / 2));
-    cpu.memory[0x02] = 0xFF;
-    cpu.memory[0x03] = 0x01;
-    cpu.memory[0x05] = 0x05;
-    cpu.memory[0x06] = 0x00;
-}
-
 fn draw_text_background(renderer: &mut Renderer, color: Color, y: i32) {
     let width = renderer.window().unwrap().size().0;
     renderer.set_draw_color(color);
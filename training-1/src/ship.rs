@@ -4,6 +4,8 @@ use sdl2::render::{Renderer, Texture, TextureQuery};
 
 use vm::Position;
 
+use level;
+
 pub struct Ship {
     pub x: i32,
     pub y: i32,
@@ -38,14 +40,9 @@ impl Ship {
     }
 
     pub fn process(&mut self, memory: &[u8]) {
-        // println!("Mem: {:?}", &memory[0x00..0x05]);
-        let mut x: u16 = memory[0x00] as u16;
-        x |= (memory[0x01] as u16) << 8;
-        self.x = x as i32;
-
-        let mut y: u16 = memory[0x02] as u16;
-        y |= (memory[0x03] as u16) << 8;
-        self.y = y as i32;
+        let (x, y) = level::ship_position(memory);
+        self.x = x;
+        self.y = y;
     }
 
     pub fn render(&self, renderer: &mut Renderer) {
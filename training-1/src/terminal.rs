@@ -0,0 +1,297 @@
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use vm::{ArgKind, ArgSpec, Args, Command, CommandResult, CommandSystem, GameCore, Options, VirtualMachine};
+
+use level;
+
+const FPS_STEP: u64 = 1000 / 60;
+const REDRAW_STEP: u64 = 1000 / 10;
+
+// The playfield is rendered as if it were the SDL window, scaled down
+const WINDOW_WIDTH: u32 = 1280;
+const WINDOW_HEIGHT: u32 = 720;
+const SHIP_WIDTH: u32 = 113;
+const FINISH_HEIGHT: i32 = 120;
+
+const FIELD_COLUMNS: usize = 64;
+const FIELD_ROWS: usize = 24;
+const SIDE_COLUMNS: usize = 40;
+const CONSOLE_LINES: usize = 10;
+
+const SHIP: [&'static str; 5] = ["  A  ", " /#\\ ", " |#| ", "/|#|\\", "/_^_\\"];
+const FLAME: &'static str = " ' ' ";
+
+/// Runs training-1 inside a terminal. Console commands are read from stdin
+/// line by line, so the level can also be played by piping in a script.
+pub fn run(options: Options) {
+    let config = match options.load_configuration() {
+        Ok(config) => config,
+        Err(err) => {
+            writeln!(io::stderr(), "{}", err).unwrap();
+            process::exit(1);
+        }
+    };
+    let mut game_core = GameCore::headless(config);
+    options.apply(&mut game_core);
+    game_core.command_system.add_command(ThrustCommand);
+    game_core.vm.console.use_level_history("training-1");
+
+    level::init_cpu_mem(&mut game_core.vm.cpu, WINDOW_WIDTH, SHIP_WIDTH);
+
+    if let Err(err) = level::load(&mut game_core.vm, &options) {
+        writeln!(io::stderr(), "{}", err).unwrap();
+        process::exit(1);
+    }
+
+    // Reading stdin blocks, so it happens on its own thread
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+
+    let mut level_complete = false;
+    let mut last_fps = Instant::now();
+    let mut last_redraw = Instant::now();
+    let mut monitor_last = Instant::now();
+
+    // A submitted line runs on the next frame, as it does in the window. No
+    // more input is read until it has, so piped lines aren't lost.
+    let mut submitted = false;
+
+    redraw(&game_core, level_complete, true);
+
+    loop {
+        let input = if submitted { Err(TryRecvError::Empty) } else { receiver.try_recv() };
+        match input {
+            Ok(line) => {
+                // Any input stops a blocking command, just like ENTER does in the window
+                if game_core.is_blocked() {
+                    game_core.unblock();
+                    redraw(&game_core, level_complete, true);
+                } else {
+                    game_core.vm.console.submit(line);
+                    submitted = true;
+                }
+            }
+            Err(TryRecvError::Empty) => (),
+            Err(TryRecvError::Disconnected) => {
+                redraw(&game_core, level_complete, true);
+                println!("");
+                break;
+            }
+        }
+
+        if !level_complete {
+            let (_, ship_y) = level::ship_position(&game_core.vm.cpu.memory[..]);
+            level::restrict_ship(&mut game_core.vm.cpu, ship_y);
+        }
+
+        if last_fps.elapsed() < Duration::from_millis(FPS_STEP) {
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+        last_fps = Instant::now();
+        game_core.update();
+        if submitted {
+            submitted = false;
+            redraw(&game_core, level_complete, true);
+        }
+
+        let (_, ship_y) = level::ship_position(&game_core.vm.cpu.memory[..]);
+        if !game_core.vm.cpu.flags.interrupt_disabled && ship_y <= level::FINISH_LINE {
+            level_complete = true;
+        }
+
+        // Dump the CPU memory at 1 second intervals if the monitor is enabled
+        if monitor_last.elapsed() > Duration::from_secs(1) && game_core.vm.monitor.enabled {
+            game_core.vm.dump_memory();
+            monitor_last = Instant::now();
+        }
+
+        if last_redraw.elapsed() > Duration::from_millis(REDRAW_STEP) {
+            redraw(&game_core, level_complete, false);
+            last_redraw = Instant::now();
+        }
+    }
+}
+
+/// Draws the playfield, register and disassembly panes and the console.
+/// Periodic redraws leave the prompt line alone so that whatever the user is
+/// halfway through typing isn't wiped out.
+fn redraw(game_core: &GameCore, level_complete: bool, with_prompt: bool) {
+    let field = render_playfield(&game_core.vm, level_complete);
+    let side = render_side_panes(&game_core.vm);
+
+    let mut output = String::new();
+    if with_prompt {
+        output.push_str("\x1b[2J");
+    } else {
+        // Save the cursor so typing continues where it left off
+        output.push_str("\x1b7");
+    }
+    output.push_str("\x1b[H");
+
+    for (index, row) in field.iter().enumerate() {
+        let side_row = side.get(index).map(|row| &row[..]).unwrap_or("");
+        output.push_str(&format!("{}  {}\x1b[K\r\n", row, truncate(side_row, SIDE_COLUMNS)));
+    }
+
+    let lines = console_tail(game_core.vm.console.lines(), CONSOLE_LINES);
+    for index in 0..CONSOLE_LINES {
+        let line = lines.get(index).map(|line| &line[..]).unwrap_or("");
+        output.push_str(&format!("{}\x1b[K\r\n",
+                                 truncate(line, FIELD_COLUMNS + 2 + SIDE_COLUMNS)));
+    }
+
+    if with_prompt {
+        if game_core.is_blocked() {
+            output.push_str("Press ENTER to cancel ");
+        } else {
+            output.push_str("hakka> ");
+        }
+    } else {
+        output.push_str("\x1b8");
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    write!(stdout, "{}", output).unwrap();
+    stdout.flush().unwrap();
+}
+
+fn render_playfield(vm: &VirtualMachine, level_complete: bool) -> Vec<String> {
+    let mut grid = vec![vec![' '; FIELD_COLUMNS]; FIELD_ROWS];
+
+    // The finish line band across the top of the screen
+    let finish_rows = to_row(FINISH_HEIGHT);
+    for row in grid.iter_mut().take(finish_rows) {
+        for cell in row.iter_mut() {
+            *cell = '=';
+        }
+    }
+    put_text(&mut grid, finish_rows / 2, " FINISH ");
+
+    let (x, y) = level::ship_position(&vm.cpu.memory[..]);
+    let column = x as usize * FIELD_COLUMNS / WINDOW_WIDTH as usize;
+    let row = to_row(y);
+    for (offset, line) in SHIP.iter().enumerate() {
+        put_at(&mut grid, row + offset, column, line);
+    }
+    if vm.cpu.memory[0x07] > 0 {
+        put_at(&mut grid, row + SHIP.len(), column, FLAME);
+    }
+
+    if level_complete {
+        put_text(&mut grid, FIELD_ROWS / 2, " PASSED ");
+    }
+
+    let border = format!("+{}+", "-".repeat(FIELD_COLUMNS));
+    let mut rows = vec![border.clone()];
+    for row in grid {
+        rows.push(format!("|{}|", row.into_iter().collect::<String>()));
+    }
+    rows.push(border);
+    rows
+}
+
+fn render_side_panes(vm: &VirtualMachine) -> Vec<String> {
+    let registers = &vm.cpu.registers;
+    let mut rows = vec![
+        "Registers".to_string(),
+        format!("A: {:02X}  X: {:02X}  Y: {:02X}", registers.A, registers.X, registers.Y),
+        format!("PC: {:04X}  S: {:02X}", registers.PC, vm.cpu.stack.pointer),
+        format!("NV-BDIZC: {:08b}", vm.cpu.flags.to_u8()),
+        if vm.is_debugging() { "(stopped)".to_string() } else { "(running)".to_string() },
+        "".to_string(),
+        "Disassembly".to_string(),
+    ];
+
+    for line in vm.local_disassembly() {
+        rows.push(line.trim_end().to_string());
+    }
+
+    rows
+}
+
+/// Returns the last `count` lines of the console buffer. The buffer always
+/// ends with the empty line started by the last `writeln!`, which is skipped.
+fn console_tail(lines: &[String], count: usize) -> Vec<String> {
+    let mut lines = lines.to_vec();
+    if lines.last().map(|line| line.is_empty()).unwrap_or(false) {
+        lines.pop();
+    }
+    let start = if lines.len() > count { lines.len() - count } else { 0 };
+    lines[start..].to_vec()
+}
+
+fn to_row(y: i32) -> usize {
+    if y < 0 {
+        0
+    } else {
+        y as usize * FIELD_ROWS / WINDOW_HEIGHT as usize
+    }
+}
+
+fn put_at(grid: &mut Vec<Vec<char>>, row: usize, column: usize, text: &str) {
+    if row >= grid.len() {
+        return;
+    }
+    for (offset, c) in text.chars().enumerate() {
+        if column + offset < FIELD_COLUMNS {
+            grid[row][column + offset] = c;
+        }
+    }
+}
+
+fn put_text(grid: &mut Vec<Vec<char>>, row: usize, text: &str) {
+    let column = (FIELD_COLUMNS - text.len()) / 2;
+    put_at(grid, row, column, text);
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+/// Holds the ship's thrusters in a direction. The window version reads the
+/// arrow keys instead, which a line-based terminal can't provide.
+struct ThrustCommand;
+impl Command for ThrustCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let key = match args.word(0) {
+            Some("up") => 38,
+            Some("down") => 40,
+            _ => 0,
+        };
+        vm.cpu.memory[0x04] = key;
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["thrust", "t"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::optional("up|down|off", ArgKind::Enum(&["up", "down", "off"]))]
+    }
+
+    fn get_help(&self) -> &str {
+        "Holds the ship's thrusters in the given direction,
+         like holding the arrow keys in the game window.
+         With no direction, the thrusters are released."
+    }
+}
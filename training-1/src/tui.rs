@@ -0,0 +1,15 @@
+extern crate byteorder;
+extern crate find_folder;
+extern crate rs6502;
+extern crate vm;
+
+mod level;
+mod terminal;
+
+use vm::Options;
+
+/// The terminal version of training-1. It takes the same options as the
+/// window version, which also runs in the terminal with --headless.
+fn main() {
+    terminal::run(Options::from_args());
+}
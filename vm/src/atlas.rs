@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Renderer, Texture};
+use sdl2::surface::Surface;
+use sdl2::ttf::{Font, Sdl2TtfContext, STYLE_BOLD};
+
+const ATLAS_SIZE: u32 = 512;
+/// Space left between glyphs, so scaling doesn't bleed one into the next
+const GLYPH_SPACING: i32 = 1;
+
+/// Where a character is in the atlas, and how far it moves the pen
+#[derive(Clone, Copy)]
+struct Glyph {
+    rect: Option<Rect>,
+    advance: u32,
+}
+
+/// Finds room for glyphs in the atlas, in rows from left to right and top to
+/// bottom
+struct Packer {
+    /// Where the next glyph goes, and the height of the current row of glyphs
+    pen: (i32, i32),
+    row_height: u32,
+}
+
+impl Packer {
+    fn new() -> Packer {
+        Packer {
+            pen: (0, 0),
+            row_height: 0,
+        }
+    }
+
+    /// Finds room for a glyph of the given size, or None if it's too big for
+    /// the atlas. When the atlas is full, it starts again from the top, and
+    /// true is returned along with the room so the glyphs there are dropped.
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(Rect, bool)> {
+        if width > ATLAS_SIZE || height > ATLAS_SIZE {
+            return None;
+        }
+
+        if self.pen.0 + width as i32 > ATLAS_SIZE as i32 {
+            self.pen = (0, self.pen.1 + self.row_height as i32 + GLYPH_SPACING);
+            self.row_height = 0;
+        }
+        let full = self.pen.1 + height as i32 > ATLAS_SIZE as i32;
+        if full {
+            self.pen = (0, 0);
+            self.row_height = 0;
+        }
+
+        let rect = Rect::new(self.pen.0, self.pen.1, width, height);
+        self.pen.0 += width as i32 + GLYPH_SPACING;
+        self.row_height = ::std::cmp::max(self.row_height, height);
+        Some((rect, full))
+    }
+}
+
+/// Draws text from a texture of glyphs, each rendered by SDL_ttf once at
+/// most. Glyphs are added as they're first drawn, and only the part of the
+/// texture that changed is uploaded, before the next draw.
+pub struct GlyphAtlas<'a> {
+    font: Font<'a, 'static>,
+    size: u16,
+    /// The glyphs are rendered here first, then copied into the texture
+    surface: Surface<'static>,
+    texture: Texture,
+    glyphs: HashMap<char, Glyph>,
+    packer: Packer,
+    /// The part of the surface that hasn't been uploaded to the texture yet
+    dirty: Option<Rect>,
+}
+
+impl<'a> GlyphAtlas<'a> {
+    /// Loads a font, in bold, for drawing text of the given size
+    pub fn new<P>(ttf_context: &'a Sdl2TtfContext, renderer: &Renderer, path: P, size: u16) -> GlyphAtlas<'a>
+        where P: AsRef<Path>
+    {
+        let mut font = ttf_context.load_font(path.as_ref(), size).unwrap();
+        font.set_style(STYLE_BOLD);
+
+        let surface = Surface::new(ATLAS_SIZE, ATLAS_SIZE, PixelFormatEnum::RGBA8888).unwrap();
+        let mut texture = renderer.create_texture_static(PixelFormatEnum::RGBA8888, ATLAS_SIZE, ATLAS_SIZE)
+            .unwrap();
+        texture.set_blend_mode(BlendMode::Blend);
+
+        GlyphAtlas {
+            font: font,
+            size: size,
+            surface: surface,
+            texture: texture,
+            glyphs: HashMap::new(),
+            packer: Packer::new(),
+            dirty: None,
+        }
+    }
+
+    /// The point size of the font
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// The height of a line of text
+    pub fn line_height(&self) -> u32 {
+        self.font.height() as u32
+    }
+
+    /// Returns the width of a line of text, as it would be drawn
+    pub fn width_of(&self, text: &str) -> u32 {
+        text.chars()
+            .map(|c| match self.glyphs.get(&c) {
+                Some(glyph) => glyph.advance,
+                None => self.font.size_of_char(c).map(|(width, _)| width).unwrap_or(0),
+            })
+            .sum()
+    }
+
+    /// Draws a line of text with its top left corner at (x, y). Returns the
+    /// width of the text.
+    pub fn draw(&mut self, renderer: &mut Renderer, text: &str, x: i32, y: i32, color: Color) -> u32 {
+        let (r, g, b, a) = color.rgba();
+        self.texture.set_color_mod(r, g, b);
+        self.texture.set_alpha_mod(a);
+
+        let mut pen = x;
+        for c in text.chars() {
+            let glyph = self.glyph(c);
+            self.upload();
+            if let Some(rect) = glyph.rect {
+                renderer.copy(&self.texture, Some(rect), Some(Rect::new(pen, y, rect.width(), rect.height())))
+                    .unwrap();
+            }
+            pen += glyph.advance as i32;
+        }
+
+        (pen - x) as u32
+    }
+
+    /// Finds a glyph in the atlas, rendering it first if it isn't there
+    fn glyph(&mut self, c: char) -> Glyph {
+        if let Some(glyph) = self.glyphs.get(&c) {
+            return *glyph;
+        }
+
+        let rendered = if c.is_whitespace() {
+            None
+        } else {
+            self.font.render_char(c).blended(Color::RGBA(255, 255, 255, 255)).ok()
+        };
+        let advance = self.font.size_of_char(c).map(|(width, _)| width).unwrap_or(0);
+
+        let rect = match rendered {
+            Some(mut surface) => {
+                match self.packer.allocate(surface.width(), surface.height()) {
+                    Some((rect, full)) => {
+                        // The glyphs drawn from then on fill the atlas again
+                        if full {
+                            self.glyphs.clear();
+                        }
+                        // Copy the glyph as is, rather than blending it with
+                        // the empty atlas
+                        surface.set_blend_mode(BlendMode::None).unwrap();
+                        surface.blit(None, &mut self.surface, Some(rect)).unwrap();
+                        self.dirty = Some(match self.dirty {
+                            Some(dirty) => dirty.union(rect),
+                            None => rect,
+                        });
+                        Some(rect)
+                    }
+                    // A glyph too big for the atlas is left blank rather than drawn
+                    None => None,
+                }
+            }
+            None => None,
+        };
+
+        let glyph = Glyph {
+            rect: rect,
+            advance: advance,
+        };
+        self.glyphs.insert(c, glyph);
+        glyph
+    }
+
+    /// Copies the glyphs added since the last upload into the texture
+    fn upload(&mut self) {
+        if let Some(dirty) = self.dirty.take() {
+            let pitch = self.surface.pitch() as usize;
+            let offset = dirty.y() as usize * pitch + dirty.x() as usize * 4;
+            if let Some(pixels) = self.surface.without_lock() {
+                self.texture.update(Some(dirty), &pixels[offset..], pitch).unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::rect::Rect;
+
+    use super::{Packer, ATLAS_SIZE};
+
+    #[test]
+    fn packs_glyphs_in_rows() {
+        let mut packer = Packer::new();
+        assert_eq!(packer.allocate(10, 20), Some((Rect::new(0, 0, 10, 20), false)));
+        assert_eq!(packer.allocate(12, 18), Some((Rect::new(11, 0, 12, 18), false)));
+        // A glyph that doesn't fit on the row starts the next, below the tallest
+        assert_eq!(packer.allocate(ATLAS_SIZE - 10, 20), Some((Rect::new(0, 21, ATLAS_SIZE - 10, 20), false)));
+    }
+
+    #[test]
+    fn starts_again_when_the_atlas_is_full() {
+        let mut packer = Packer::new();
+        assert_eq!(packer.allocate(ATLAS_SIZE, ATLAS_SIZE - 10),
+                   Some((Rect::new(0, 0, ATLAS_SIZE, ATLAS_SIZE - 10), false)));
+        assert_eq!(packer.allocate(10, 20), Some((Rect::new(0, 0, 10, 20), true)));
+        assert_eq!(packer.allocate(10, 20), Some((Rect::new(11, 0, 10, 20), false)));
+    }
+
+    #[test]
+    fn skips_glyphs_too_big_for_the_atlas() {
+        let mut packer = Packer::new();
+        assert_eq!(packer.allocate(10, ATLAS_SIZE + 1), None);
+        assert_eq!(packer.allocate(ATLAS_SIZE + 1, 10), None);
+        // Nothing was taken up by them
+        assert_eq!(packer.allocate(10, 20), Some((Rect::new(0, 0, 10, 20), false)));
+    }
+}
@@ -0,0 +1,223 @@
+use std::env;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use config::{Configuration, CONFIG_FILE};
+use game_core::GameCore;
+
+/// The command line options every level binary takes
+#[derive(Debug, Default)]
+pub struct Options {
+    /// The assembly file to load in place of the level's own
+    pub level: Option<PathBuf>,
+    /// The configuration file to use in place of the user's config.json
+    pub config: Option<PathBuf>,
+    /// How many cycles the CPU runs each frame, for this session only
+    pub clock: Option<u32>,
+    /// Overrides the fullscreen setting for this session
+    pub fullscreen: Option<bool>,
+    /// Console scripts to run at startup, after hakkarc
+    pub scripts: Vec<PathBuf>,
+    /// Runs the level in the terminal rather than in a window
+    pub headless: bool,
+}
+
+impl Options {
+    /// Reads the options from the command line. Prints the usage and exits
+    /// if they can't be read, or if --help is given.
+    pub fn from_args() -> Options {
+        let mut args = env::args();
+        let program = args.next().unwrap_or_else(|| "hakka".into());
+
+        match Options::parse(args) {
+            Ok(Some(options)) => options,
+            Ok(None) => {
+                println!("{}", usage(&program));
+                process::exit(0);
+            }
+            Err(err) => {
+                writeln!(io::stderr(), "{}\n\n{}", err, usage(&program)).unwrap();
+                process::exit(2);
+            }
+        }
+    }
+
+    /// Parses the arguments that follow the program name. Returns None if
+    /// the usage was asked for.
+    pub fn parse<I>(args: I) -> Result<Option<Options>, String>
+        where I: IntoIterator<Item = String>
+    {
+        let mut options = Options::default();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // Values can be given as "--clock 300" or "--clock=300"
+            let (name, inline_value) = match arg.find('=') {
+                Some(index) if arg.starts_with("--") => (arg[..index].to_string(), Some(arg[index + 1..].to_string())),
+                _ => (arg.clone(), None),
+            };
+            let mut value = |what: &str| {
+                inline_value.clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{} needs {}", name, what))
+            };
+
+            match &name[..] {
+                "-h" | "--help" => return Ok(None),
+                "--level" => options.level = Some(PathBuf::from(value("a file")?)),
+                "--config" => options.config = Some(PathBuf::from(value("a file")?)),
+                "--script" => options.scripts.push(PathBuf::from(value("a file")?)),
+                "--clock" => {
+                    let clock = value("a number of cycles")?;
+                    match clock.parse::<u32>() {
+                        Ok(clock) if clock > 0 => options.clock = Some(clock),
+                        _ => return Err(format!("Expected a number of cycles for --clock, found {}", clock)),
+                    }
+                }
+                "--windowed" | "--fullscreen" | "--headless" if inline_value.is_some() => {
+                    return Err(format!("{} doesn't take a value", name));
+                }
+                "--windowed" => options.fullscreen = Some(false),
+                "--fullscreen" => options.fullscreen = Some(true),
+                "--headless" => options.headless = true,
+                _ => return Err(format!("Unknown option {}", arg)),
+            }
+        }
+
+        Ok(Some(options))
+    }
+
+    /// Loads the configuration file given with --config, or the user's
+    /// config.json
+    pub fn load_configuration(&self) -> Result<Configuration, String> {
+        match self.config {
+            Some(ref path) => load_config_file(path),
+            None => Configuration::load_or_default().map_err(|err| format!("Unable to load {}: {}", CONFIG_FILE, err)),
+        }
+    }
+
+    /// Whether the window should be fullscreen, from the options or else the
+    /// configuration
+    pub fn fullscreen(&self, config: &Configuration) -> bool {
+        self.fullscreen.unwrap_or_else(|| config.get_fullscreen())
+    }
+
+    /// Applies the options that change the game itself, rather than the
+    /// window. None of them are saved to the configuration.
+    pub fn apply(&self, game_core: &mut GameCore) {
+        if let Some(ref path) = self.config {
+            game_core.vm.console.set_config_file(path.clone());
+        }
+        if let Some(clock) = self.clock {
+            game_core.vm.set_clock_rate(clock);
+        }
+        for script in &self.scripts {
+            game_core.add_startup_script(script.clone());
+        }
+    }
+}
+
+/// Reads the file given with --config. Unlike the user's config.json it's
+/// never written to here, not even to upgrade it or replace it when it can't
+/// be read. A file that doesn't exist yet starts from the defaults, as long
+/// as the directory it would be saved in does.
+fn load_config_file(path: &Path) -> Result<Configuration, String> {
+    if path.exists() {
+        return Configuration::load(path).map_err(|err| format!("Unable to load {}: {}", path.display(), err));
+    }
+
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+    if !directory.is_dir() {
+        return Err(format!("Unable to use {}: {} is not a directory", path.display(), directory.display()));
+    }
+
+    Ok(Configuration::default())
+}
+
+pub fn usage(program: &str) -> String {
+    format!("Usage: {} [options]
+
+Options:
+    --level <file>     Loads the level code from an assembly file
+    --config <file>    Uses a configuration file other than config.json
+    --clock <cycles>   Runs the CPU for this many cycles each frame
+    --windowed         Opens in a window, whatever the configuration says
+    --fullscreen       Opens fullscreen, whatever the configuration says
+    --script <file>    Runs the console commands in a file at startup.
+                       Can be given more than once.
+    --headless         Plays in the terminal instead of a window
+    -h, --help         Shows this message",
+            program)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+
+    use super::Options;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_values_in_either_form() {
+        let options = parse(&["--clock", "300", "--script=a.txt", "--script", "b.txt", "--windowed"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.clock, Some(300));
+        assert_eq!(options.scripts, vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")]);
+        assert_eq!(options.fullscreen, Some(false));
+        assert!(!options.headless);
+
+        assert!(parse(&["--help"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn refuses_bad_options() {
+        assert!(parse(&["--clock", "0"]).is_err());
+        assert!(parse(&["--clock"]).is_err());
+        assert!(parse(&["--level"]).is_err());
+        assert!(parse(&["--nosuchoption"]).is_err());
+    }
+
+    #[test]
+    fn refuses_values_for_switches() {
+        assert!(parse(&["--headless=foo"]).is_err());
+        assert!(parse(&["--fullscreen=false"]).is_err());
+        assert!(parse(&["--windowed="]).is_err());
+    }
+
+    #[test]
+    fn refuses_a_config_file_in_a_missing_directory() {
+        let options = Options {
+            config: Some(env::temp_dir().join("hakka-no-such-directory").join("config.json")),
+            ..Options::default()
+        };
+        assert!(options.load_configuration().is_err());
+    }
+
+    #[test]
+    fn leaves_an_unreadable_config_file_alone() {
+        let path = env::temp_dir().join("hakka-cli-unreadable-config.json");
+        File::create(&path).unwrap().write_all(b"{ not json").unwrap();
+
+        let options = Options { config: Some(path.clone()), ..Options::default() };
+        assert!(options.load_configuration().is_err());
+
+        let mut contents = String::new();
+        File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "{ not json");
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(".bak");
+        assert!(!PathBuf::from(backup).exists());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,419 @@
+use vm::VirtualMachine;
+
+use super::{evaluate, Completion, FLAG_NAMES};
+
+/// The kinds of value a command argument can hold
+pub enum ArgKind {
+    /// An expression that evaluates to an address in the 64KB address space
+    Address,
+    /// An expression that evaluates to a single byte
+    Byte,
+    /// A start and an end address, given as two words
+    Range,
+    /// An expression of any value. A variadic expression is evaluated as a whole.
+    Expression,
+    /// A decimal number
+    Number,
+    /// A decimal TCP port, or "off"
+    Port,
+    /// One of a fixed set of words
+    Enum(&'static [&'static str]),
+    /// The name or first letter of a status flag
+    Flag,
+    /// A command name or alias
+    Command,
+    /// The name of a user defined alias
+    Alias,
+    /// Any word, e.g. a file name
+    Text,
+}
+
+impl ArgKind {
+    /// How many words a single value of this kind takes up
+    fn word_count(&self) -> usize {
+        match *self {
+            ArgKind::Range => 2,
+            _ => 1,
+        }
+    }
+
+    /// Whether the value is evaluated as an expression
+    fn is_expression(&self) -> bool {
+        match *self {
+            ArgKind::Address | ArgKind::Byte | ArgKind::Expression => true,
+            _ => false,
+        }
+    }
+
+    fn completion(&self) -> Completion {
+        match *self {
+            ArgKind::Address | ArgKind::Byte | ArgKind::Range | ArgKind::Expression => Completion::Expression,
+            ArgKind::Port => Completion::Words(vec!["off"]),
+            ArgKind::Enum(words) => Completion::Words(words.to_vec()),
+            ArgKind::Flag => Completion::Flags,
+            ArgKind::Command => Completion::Commands,
+            ArgKind::Alias => Completion::Aliases,
+            ArgKind::Number | ArgKind::Text => Completion::None,
+        }
+    }
+}
+
+/// Describes one argument of a command. Optional arguments must come after
+/// the required ones, and only the last argument can be variadic.
+pub struct ArgSpec {
+    name: &'static str,
+    kind: ArgKind,
+    optional: bool,
+    variadic: bool,
+}
+
+impl ArgSpec {
+    pub fn required(name: &'static str, kind: ArgKind) -> ArgSpec {
+        ArgSpec {
+            name: name,
+            kind: kind,
+            optional: false,
+            variadic: false,
+        }
+    }
+
+    pub fn optional(name: &'static str, kind: ArgKind) -> ArgSpec {
+        ArgSpec {
+            optional: true,
+            ..ArgSpec::required(name, kind)
+        }
+    }
+
+    /// Lets the argument take every remaining word
+    pub fn variadic(mut self) -> ArgSpec {
+        self.variadic = true;
+        self
+    }
+}
+
+/// A validated argument value
+enum Value {
+    Address(usize),
+    Byte(u8),
+    Range(usize, usize),
+    Number(i64),
+    Word(String),
+}
+
+/// The arguments of a command, validated against its specs. Values are looked
+/// up by the index of their spec.
+pub struct Args {
+    values: Vec<Vec<Value>>,
+}
+
+impl Args {
+    /// Returns true if the argument was given
+    pub fn has(&self, index: usize) -> bool {
+        self.values.get(index).map(|values| !values.is_empty()).unwrap_or(false)
+    }
+
+    pub fn address(&self, index: usize) -> Option<usize> {
+        match self.first(index) {
+            Some(&Value::Address(address)) => Some(address),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(&self, index: usize) -> Vec<u8> {
+        self.all(index)
+            .filter_map(|value| {
+                match *value {
+                    Value::Byte(byte) => Some(byte),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    pub fn range(&self, index: usize) -> Option<(usize, usize)> {
+        match self.first(index) {
+            Some(&Value::Range(start, end)) => Some((start, end)),
+            _ => None,
+        }
+    }
+
+    /// Returns an evaluated expression, a number or a port
+    pub fn number(&self, index: usize) -> Option<i64> {
+        match self.first(index) {
+            Some(&Value::Number(number)) => Some(number),
+            _ => None,
+        }
+    }
+
+    pub fn word(&self, index: usize) -> Option<&str> {
+        match self.first(index) {
+            Some(&Value::Word(ref word)) => Some(word),
+            _ => None,
+        }
+    }
+
+    /// Returns every word of a variadic argument
+    pub fn words(&self, index: usize) -> Vec<&str> {
+        self.all(index)
+            .filter_map(|value| {
+                match *value {
+                    Value::Word(ref word) => Some(&word[..]),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    fn first(&self, index: usize) -> Option<&Value> {
+        self.values.get(index).and_then(|values| values.first())
+    }
+
+    fn all<'a>(&'a self, index: usize) -> Box<Iterator<Item = &'a Value> + 'a> {
+        match self.values.get(index) {
+            Some(values) => Box::new(values.iter()),
+            None => Box::new(None.into_iter()),
+        }
+    }
+}
+
+/// Validates the words given to a command against its argument specs. The
+/// last argument, if it's an expression, is made of all of the remaining
+/// words, so `break Y_0 + 1` works like `print Y_0 + 1`. Expressions that
+/// come before other arguments need grouping into one word with parentheses
+/// or quotes.
+pub fn parse(specs: &[ArgSpec], words: &[String], vm: &VirtualMachine) -> Result<Args, String> {
+    let mut values = Vec::new();
+    let mut position = 0;
+
+    for (index, spec) in specs.iter().enumerate() {
+        let remaining = &words[position..];
+        let count = spec.kind.word_count();
+
+        if remaining.len() < count {
+            if spec.optional && remaining.is_empty() {
+                values.push(Vec::new());
+                continue;
+            }
+            return Err(format!("Missing {}", spec.name));
+        }
+
+        if spec.variadic {
+            if let ArgKind::Expression = spec.kind {
+                values.push(vec![parse_value(&spec.kind, &[remaining.join(" ")], vm)?]);
+            } else {
+                let mut variadic = Vec::new();
+                for chunk in remaining.chunks(count) {
+                    if chunk.len() < count {
+                        return Err(format!("Missing {}", spec.name));
+                    }
+                    variadic.push(parse_value(&spec.kind, chunk, vm)?);
+                }
+                values.push(variadic);
+            }
+            position = words.len();
+        } else if index == specs.len() - 1 && spec.kind.is_expression() {
+            values.push(vec![parse_value(&spec.kind, &[remaining.join(" ")], vm)?]);
+            position = words.len();
+        } else {
+            values.push(vec![parse_value(&spec.kind, &remaining[..count], vm)?]);
+            position += count;
+        }
+    }
+
+    if position < words.len() {
+        return Err(format!("Unexpected argument {}. Wrap expressions with spaces in parentheses, e.g. (Y_0 + 1)",
+                           words[position]));
+    }
+
+    Ok(Args { values: values })
+}
+
+fn parse_value(kind: &ArgKind, words: &[String], vm: &VirtualMachine) -> Result<Value, String> {
+    let word = &words[0];
+    match *kind {
+        ArgKind::Address => parse_address(word, vm).map(Value::Address),
+        ArgKind::Byte => parse_byte(word, vm).map(Value::Byte),
+        ArgKind::Range => {
+            let start = parse_address(word, vm)?;
+            let end = parse_address(&words[1], vm)?;
+            if end < start {
+                return Err(format!("End address {:04X} is before start address {:04X}", end, start));
+            }
+            Ok(Value::Range(start, end))
+        }
+        ArgKind::Expression => {
+            evaluate(word, vm)
+                .map(Value::Number)
+                .map_err(|err| format!("Invalid expression: {}", err))
+        }
+        // Counts and ports are always decimal, unlike addresses
+        ArgKind::Number => {
+            word.parse::<u32>()
+                .map(|number| Value::Number(number as i64))
+                .map_err(|_| format!("Expected a number, found {}", word))
+        }
+        ArgKind::Port => {
+            if word == "off" {
+                return Ok(Value::Word(word.clone()));
+            }
+            word.parse::<u16>()
+                .map(|port| Value::Number(port as i64))
+                .map_err(|_| format!("Expected port number or off, found {}", word))
+        }
+        ArgKind::Enum(choices) => {
+            if choices.contains(&&word[..]) {
+                Ok(Value::Word(word.clone()))
+            } else {
+                Err(format!("Expected {}, found {}", choices.join(" or "), word))
+            }
+        }
+        ArgKind::Flag => {
+            let word = word.to_lowercase();
+            // Flags can also be given by their letter in the status register
+            let letters = ["c", "z", "i", "d", "b", "v", "n"];
+            match letters.iter().position(|letter| *letter == word) {
+                Some(index) => Ok(Value::Word(FLAG_NAMES[index].into())),
+                None if FLAG_NAMES.contains(&&word[..]) => Ok(Value::Word(word)),
+                None => Err(format!("Unknown flag {}, expected one of: {}", word, FLAG_NAMES.join(", "))),
+            }
+        }
+        ArgKind::Command | ArgKind::Alias | ArgKind::Text => Ok(Value::Word(word.clone())),
+    }
+}
+
+/// Builds a usage line from a command name and its argument specs, e.g.
+/// "memset address value..."
+pub fn usage(name: &str, specs: &[ArgSpec]) -> String {
+    let mut usage = name.to_string();
+    for spec in specs {
+        let variadic = if spec.variadic { "..." } else { "" };
+        if spec.optional {
+            usage.push_str(&format!(" [{}{}]", spec.name, variadic));
+        } else {
+            usage.push_str(&format!(" {}{}", spec.name, variadic));
+        }
+    }
+    usage
+}
+
+/// Finds what the word at `index` completes to
+pub fn completion(specs: &[ArgSpec], index: usize) -> Completion {
+    let mut position = 0;
+    for spec in specs {
+        let count = spec.kind.word_count();
+        if spec.variadic || index < position + count {
+            return spec.kind.completion();
+        }
+        position += count;
+    }
+    Completion::None
+}
+
+/// Evaluates a word as an address within the 64KB address space
+fn parse_address(arg: &str, vm: &VirtualMachine) -> Result<usize, String> {
+    match evaluate(arg, vm) {
+        Ok(address) if address >= 0 && address <= u16::max_value() as i64 => Ok(address as usize),
+        Ok(_) => Err(format!("Address {} is outside addressable range", arg)),
+        Err(err) => Err(format!("Expected memory address, found {}: {}", arg, err)),
+    }
+}
+
+/// Evaluates a word as a single byte value
+fn parse_byte(arg: &str, vm: &VirtualMachine) -> Result<u8, String> {
+    match evaluate(arg, vm) {
+        Ok(byte) if byte >= 0 && byte <= u8::max_value() as i64 => Ok(byte as u8),
+        Ok(_) => Err(format!("Value {} does not fit in a byte", arg)),
+        Err(err) => Err(format!("Expected byte, found {}: {}", arg, err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vm::VirtualMachine;
+    use super::super::split_words;
+    use super::{parse, ArgKind, ArgSpec, Args};
+
+    fn memset() -> Vec<ArgSpec> {
+        vec![ArgSpec::required("address", ArgKind::Address),
+             ArgSpec::required("value", ArgKind::Byte).variadic()]
+    }
+
+    fn memdmp() -> Vec<ArgSpec> {
+        vec![ArgSpec::required("page|start", ArgKind::Address),
+             ArgSpec::optional("end", ArgKind::Address)]
+    }
+
+    fn parse_line(specs: &[ArgSpec], line: &str) -> Result<Args, String> {
+        parse(specs, &split_words(line), &VirtualMachine::for_tests())
+    }
+
+    #[test]
+    fn parses_single_words() {
+        let args = parse_line(&memset(), "$0200 1 $FF %10").unwrap();
+        assert_eq!(args.address(0), Some(0x200));
+        assert_eq!(args.bytes(1), vec![1, 0xFF, 2]);
+    }
+
+    #[test]
+    fn groups_expressions_in_parentheses_or_quotes() {
+        let args = parse_line(&memset(), "(Y_0 + 1) 5 (1 << 2)").unwrap();
+        assert_eq!(args.address(0), Some(0x201));
+        assert_eq!(args.bytes(1), vec![5, 4]);
+
+        let args = parse_line(&memset(), "\"Y_0 + 2\" 5").unwrap();
+        assert_eq!(args.address(0), Some(0x202));
+
+        assert!(parse_line(&memset(), "Y_0 + 1 5").is_err());
+    }
+
+    #[test]
+    fn joins_the_last_expression() {
+        let break_args = vec![ArgSpec::optional("address", ArgKind::Address)];
+        assert_eq!(parse_line(&break_args, "Y_0 + 1").unwrap().address(0), Some(0x201));
+
+        let args = parse_line(&memdmp(), "Y_0 Y_0 + $10").unwrap();
+        assert_eq!(args.address(0), Some(0x200));
+        assert_eq!(args.address(1), Some(0x210));
+    }
+
+    #[test]
+    fn evaluates_a_variadic_expression_as_a_whole() {
+        let print = vec![ArgSpec::required("expression", ArgKind::Expression).variadic()];
+        assert_eq!(parse_line(&print, "1 + 2 * 3").unwrap().number(0), Some(7));
+    }
+
+    #[test]
+    fn leaves_out_optional_arguments() {
+        let args = parse_line(&memdmp(), "0").unwrap();
+        assert_eq!(args.address(0), Some(0));
+        assert!(!args.has(1));
+    }
+
+    #[test]
+    fn reports_missing_and_unexpected_arguments() {
+        assert_eq!(parse_line(&memset(), "").err(), Some("Missing address".into()));
+        assert_eq!(parse_line(&memset(), "0").err(), Some("Missing value".into()));
+
+        let history = vec![ArgSpec::optional("count", ArgKind::Number)];
+        assert!(parse_line(&history, "1 2").err().unwrap().starts_with("Unexpected argument 2"));
+    }
+
+    #[test]
+    fn checks_values() {
+        assert!(parse_line(&memset(), "$10000 1").is_err());
+        assert!(parse_line(&memset(), "0 256").is_err());
+
+        let range = vec![ArgSpec::required("start end", ArgKind::Range)];
+        assert_eq!(parse_line(&range, "$10 $20").unwrap().range(0), Some((0x10, 0x20)));
+        assert!(parse_line(&range, "$20 $10").is_err());
+        assert!(parse_line(&range, "20").is_err());
+
+        let flag = vec![ArgSpec::required("flag", ArgKind::Flag)];
+        assert_eq!(parse_line(&flag, "C").unwrap().word(0), Some("carry"));
+        assert!(parse_line(&flag, "q").is_err());
+
+        let dock = vec![ArgSpec::required("dock", ArgKind::Enum(&["left", "right"]))];
+        assert_eq!(parse_line(&dock, "left").unwrap().word(0), Some("left"));
+        assert!(parse_line(&dock, "up").is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use vm::VirtualMachine;
+
+use super::{CommandSystem, FLAG_NAMES};
+
+const REGISTER_NAMES: [&'static str; 5] = ["A", "X", "Y", "PC", "SP"];
+
+/// What a command argument completes to when Tab is pressed
+pub enum Completion {
+    None,
+    /// Registers and level symbols
+    Expression,
+    /// Status flag names
+    Flags,
+    /// Command names and aliases
+    Commands,
+    /// User defined aliases
+    Aliases,
+    /// A fixed set of words, e.g. "on" and "off"
+    Words(Vec<&'static str>),
+}
+
+impl CommandSystem {
+    /// Finds the candidates for the word that ends the given input. Returns
+    /// the byte offset where that word starts, along with the candidates.
+    pub fn complete(&self, input: &str, vm: &VirtualMachine) -> (usize, Vec<String>) {
+        // Only the last command of a chain is being completed
+        let command_start = input.rfind(';').map(|index| index + 1).unwrap_or(0);
+        let command = &input[command_start..];
+
+        let word_start = command.rfind(char::is_whitespace).map(|index| index + 1).unwrap_or(0);
+        let words = command[..word_start].split_whitespace().collect::<Vec<_>>();
+
+        let completion = match words.first() {
+            None => Completion::Commands,
+            Some(name) => self.arg_completion(name, words.len() - 1, vm),
+        };
+
+        // Symbols can be completed part way through an expression, e.g. "A+LOO"
+        let word_start = match completion {
+            Completion::Expression => {
+                command[word_start..]
+                    .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .map(|index| word_start + index + 1)
+                    .unwrap_or(word_start)
+            }
+            _ => word_start,
+        };
+        let prefix = command[word_start..].to_lowercase();
+
+        let mut candidates = self.candidates(completion, vm)
+            .into_iter()
+            .filter(|candidate| candidate.to_lowercase().starts_with(&prefix))
+            .collect::<Vec<_>>();
+        candidates.sort();
+        candidates.dedup();
+
+        (command_start + word_start, candidates)
+    }
+
+    /// Finds what the argument at `index` of the named command or alias completes to
+    fn arg_completion(&self, name: &str, index: usize, vm: &VirtualMachine) -> Completion {
+        let (name, index) = match vm.console.config().get_alias(name) {
+            Some(expansion) => {
+                let words = expansion.split_whitespace().collect::<Vec<_>>();
+                match words.first() {
+                    Some(first) if *first != name => (first.to_string(), index + words.len() - 1),
+                    _ => (name.to_string(), index),
+                }
+            }
+            None => (name.to_string(), index),
+        };
+
+        self.commands
+            .iter()
+            .find(|command| command.matches_name(name.clone()))
+            .map(|command| command.complete_arg(index))
+            .unwrap_or(Completion::None)
+    }
+
+    fn candidates(&self, completion: Completion, vm: &VirtualMachine) -> Vec<String> {
+        let aliases = || {
+            vm.console
+                .config()
+                .get_aliases()
+                .into_iter()
+                .map(|(name, _)| name.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        match completion {
+            Completion::None => Vec::new(),
+            Completion::Expression => {
+                REGISTER_NAMES.iter()
+                    .map(|name| name.to_string())
+                    .chain(vm.symbols.names().into_iter().map(String::from))
+                    .collect()
+            }
+            Completion::Flags => FLAG_NAMES.iter().map(|name| name.to_string()).collect(),
+            Completion::Commands => {
+                let mut names = self.commands
+                    .iter()
+                    .flat_map(|command| command.get_names().into_iter().map(String::from).collect::<Vec<_>>())
+                    .collect::<Vec<_>>();
+                names.extend(aliases());
+                names
+            }
+            Completion::Aliases => aliases(),
+            Completion::Words(words) => words.into_iter().map(String::from).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vm::VirtualMachine;
+    use super::super::CommandSystem;
+
+    fn complete(vm: &VirtualMachine, input: &str) -> (usize, Vec<String>) {
+        CommandSystem::new().complete(input, vm)
+    }
+
+    #[test]
+    fn completes_command_names() {
+        let vm = VirtualMachine::for_tests();
+        let (start, candidates) = complete(&vm, "memd");
+        assert_eq!(start, 0);
+        assert_eq!(candidates, vec!["memdmp"]);
+
+        let (start, candidates) = complete(&vm, "reg; FLA");
+        assert_eq!(start, 5);
+        assert_eq!(candidates, vec!["flags"]);
+    }
+
+    #[test]
+    fn completes_arguments_by_kind() {
+        let vm = VirtualMachine::for_tests();
+        assert_eq!(complete(&vm, "flags ca"), (6, vec!["carry".to_string()]));
+        assert_eq!(complete(&vm, "flags carry o"), (12, vec!["off".to_string(), "on".to_string()]));
+        assert_eq!(complete(&vm, "help memd"), (5, vec!["memdmp".to_string()]));
+        assert_eq!(complete(&vm, "flags carry on x"), (15, Vec::new()));
+    }
+
+    #[test]
+    fn completes_symbols_inside_expressions() {
+        let vm = VirtualMachine::for_tests();
+        assert_eq!(complete(&vm, "memset y"), (7, vec!["Y".to_string(), "Y_0".to_string()]));
+        assert_eq!(complete(&vm, "print A+BE"), (8, vec!["BEEF".to_string()]));
+    }
+
+    #[test]
+    fn completes_the_arguments_of_aliases() {
+        let mut vm = VirtualMachine::for_tests();
+        vm.console.config_mut().set_alias("poke", "memset $0200");
+        assert_eq!(complete(&vm, "po"), (0, vec!["poke".to_string()]));
+        assert_eq!(complete(&vm, "unalias p"), (8, vec!["poke".to_string()]));
+        // The alias already fills in the address, so this is the byte to write
+        assert_eq!(complete(&vm, "poke BE"), (5, vec!["BEEF".to_string()]));
+    }
+}
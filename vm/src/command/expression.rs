@@ -0,0 +1,473 @@
+use std::fmt;
+
+use vm::VirtualMachine;
+
+/// Errors produced while parsing or evaluating an expression
+#[derive(Debug)]
+pub enum ExpressionError {
+    UnexpectedEnd,
+    UnexpectedToken(String),
+    InvalidNumber(String),
+    UnknownSymbol(String),
+    AddressOutOfRange(i64),
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExpressionError::UnexpectedEnd => write!(f, "unexpected end of expression"),
+            ExpressionError::UnexpectedToken(ref token) => write!(f, "unexpected '{}'", token),
+            ExpressionError::InvalidNumber(ref number) => {
+                write!(f, "invalid number '{}'", number)?;
+                write_hex_hint(f, number)
+            }
+            ExpressionError::UnknownSymbol(ref symbol) => {
+                write!(f, "unknown symbol '{}'", symbol)?;
+                write_hex_hint(f, symbol)
+            }
+            ExpressionError::AddressOutOfRange(address) => {
+                write!(f, "address {:X} is outside addressable range", address)
+            }
+        }
+    }
+}
+
+/// Points out the `$` prefix when a bare word was probably meant as hex
+fn write_hex_hint(f: &mut fmt::Formatter, word: &str) -> fmt::Result {
+    if word.chars().all(|c| c.is_digit(16)) {
+        write!(f, ", write ${} for a hex number", word)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Plus,
+    Minus,
+    Star,
+    Ampersand,
+    Pipe,
+    ShiftLeft,
+    ShiftRight,
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+}
+
+/// Evaluates an expression against the current state of the virtual machine.
+///
+/// Numbers may be written as `$hex`, `0xhex`, `%binary` or `#decimal`. A
+/// word starting with a digit is a decimal number, and any other word is a
+/// name, so `10` is ten and `FF` has to be written as `$FF`. Register names
+/// (`A`, `X`, `Y`, `PC`, `SP`), level symbols and `mem[addr]` can be combined
+/// with `+ - * & | << >>` and parentheses.
+pub fn evaluate(input: &str, vm: &VirtualMachine) -> Result<i64, ExpressionError> {
+    let tokens = tokenize(input)?;
+    let mut evaluator = Evaluator {
+        tokens: tokens,
+        position: 0,
+        vm: vm,
+    };
+
+    let value = evaluator.or()?;
+    if let Some(token) = evaluator.next() {
+        return Err(ExpressionError::UnexpectedToken(token.to_string()));
+    }
+
+    Ok(value)
+}
+
+/// Shows a value in hexadecimal, decimal and binary, e.g. `$05  #5  %00000101`.
+/// Negative values are shown as their 16-bit two's complement.
+pub fn format_value(value: i64) -> String {
+    let bits = if value < 0 { value as u16 as i64 } else { value };
+    if bits <= 0xFF {
+        format!("${:02X}  #{}  %{:08b}", bits, value, bits)
+    } else if bits <= 0xFFFF {
+        format!("${:04X}  #{}  %{:016b}", bits, value, bits)
+    } else {
+        format!("${:X}  #{}  %{:b}", bits, value, bits)
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, ExpressionError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' | '\t' => {
+                chars.next();
+            }
+            '+' => {
+                chars.next();
+                tokens.push(Token::Plus);
+            }
+            '-' => {
+                chars.next();
+                tokens.push(Token::Minus);
+            }
+            '*' => {
+                chars.next();
+                tokens.push(Token::Star);
+            }
+            '&' => {
+                chars.next();
+                tokens.push(Token::Ampersand);
+            }
+            '|' => {
+                chars.next();
+                tokens.push(Token::Pipe);
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::OpenParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::CloseParen);
+            }
+            '[' => {
+                chars.next();
+                tokens.push(Token::OpenBracket);
+            }
+            ']' => {
+                chars.next();
+                tokens.push(Token::CloseBracket);
+            }
+            '<' | '>' => {
+                chars.next();
+                if chars.peek() != Some(&c) {
+                    return Err(ExpressionError::UnexpectedToken(c.to_string()));
+                }
+                chars.next();
+                tokens.push(if c == '<' { Token::ShiftLeft } else { Token::ShiftRight });
+            }
+            '$' | '%' | '#' => {
+                chars.next();
+                let digits = take_word(&mut chars);
+                let radix = match c {
+                    '$' => 16,
+                    '%' => 2,
+                    _ => 10,
+                };
+                tokens.push(Token::Number(parse_number(&digits, radix, c)?));
+            }
+            c if c.is_digit(10) => {
+                let word = take_word(&mut chars);
+                if word.starts_with("0x") || word.starts_with("0X") {
+                    tokens.push(Token::Number(parse_number(&word[2..], 16, '$')?));
+                } else {
+                    let number = word.parse::<i64>().map_err(|_| ExpressionError::InvalidNumber(word.clone()))?;
+                    tokens.push(Token::Number(number));
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => tokens.push(Token::Ident(take_word(&mut chars))),
+            _ => return Err(ExpressionError::UnexpectedToken(c.to_string())),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn take_word<I>(chars: &mut ::std::iter::Peekable<I>) -> String
+    where I: Iterator<Item = char>
+{
+    let mut word = String::new();
+    while let Some(&c) = chars.peek() {
+        if c.is_alphanumeric() || c == '_' {
+            word.push(c);
+            chars.next();
+        } else {
+            break;
+        }
+    }
+    word
+}
+
+fn parse_number(digits: &str, radix: u32, prefix: char) -> Result<i64, ExpressionError> {
+    i64::from_str_radix(digits, radix)
+        .map_err(|_| ExpressionError::InvalidNumber(format!("{}{}", prefix, digits)))
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Ident(ref ident) => write!(f, "{}", ident),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Star => write!(f, "*"),
+            Token::Ampersand => write!(f, "&"),
+            Token::Pipe => write!(f, "|"),
+            Token::ShiftLeft => write!(f, "<<"),
+            Token::ShiftRight => write!(f, ">>"),
+            Token::OpenParen => write!(f, "("),
+            Token::CloseParen => write!(f, ")"),
+            Token::OpenBracket => write!(f, "["),
+            Token::CloseBracket => write!(f, "]"),
+        }
+    }
+}
+
+/// A recursive descent evaluator. Precedence, from lowest to highest:
+/// `|`, `&`, `<< >>`, `+ -`, `*`, unary `-`.
+struct Evaluator<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    vm: &'a VirtualMachine,
+}
+
+impl<'a> Evaluator<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ExpressionError> {
+        match self.next() {
+            Some(ref token) if *token == expected => Ok(()),
+            Some(token) => Err(ExpressionError::UnexpectedToken(token.to_string())),
+            None => Err(ExpressionError::UnexpectedEnd),
+        }
+    }
+
+    fn or(&mut self) -> Result<i64, ExpressionError> {
+        let mut value = self.and()?;
+        while self.peek() == Some(&Token::Pipe) {
+            self.next();
+            value |= self.and()?;
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<i64, ExpressionError> {
+        let mut value = self.shift()?;
+        while self.peek() == Some(&Token::Ampersand) {
+            self.next();
+            value &= self.shift()?;
+        }
+        Ok(value)
+    }
+
+    fn shift(&mut self) -> Result<i64, ExpressionError> {
+        let mut value = self.additive()?;
+        loop {
+            match self.peek() {
+                Some(&Token::ShiftLeft) => {
+                    self.next();
+                    let amount = self.additive()?;
+                    value = value.checked_shl(amount as u32).unwrap_or(0);
+                }
+                Some(&Token::ShiftRight) => {
+                    self.next();
+                    let amount = self.additive()?;
+                    value = value.checked_shr(amount as u32).unwrap_or(0);
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    fn additive(&mut self) -> Result<i64, ExpressionError> {
+        let mut value = self.multiplicative()?;
+        loop {
+            match self.peek() {
+                Some(&Token::Plus) => {
+                    self.next();
+                    value = value.wrapping_add(self.multiplicative()?);
+                }
+                Some(&Token::Minus) => {
+                    self.next();
+                    value = value.wrapping_sub(self.multiplicative()?);
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<i64, ExpressionError> {
+        let mut value = self.unary()?;
+        while self.peek() == Some(&Token::Star) {
+            self.next();
+            value = value.wrapping_mul(self.unary()?);
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, ExpressionError> {
+        if self.peek() == Some(&Token::Minus) {
+            self.next();
+            return Ok(self.unary()?.wrapping_neg());
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<i64, ExpressionError> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(n),
+            Some(Token::OpenParen) => {
+                let value = self.or()?;
+                self.expect(Token::CloseParen)?;
+                Ok(value)
+            }
+            Some(Token::Ident(ident)) => {
+                if ident.to_lowercase() == "mem" && self.peek() == Some(&Token::OpenBracket) {
+                    self.next();
+                    let address = self.or()?;
+                    self.expect(Token::CloseBracket)?;
+                    if address < 0 || address > u16::max_value() as i64 {
+                        return Err(ExpressionError::AddressOutOfRange(address));
+                    }
+                    return Ok(self.vm.cpu.memory[address as usize] as i64);
+                }
+                self.resolve(ident)
+            }
+            Some(token) => Err(ExpressionError::UnexpectedToken(token.to_string())),
+            None => Err(ExpressionError::UnexpectedEnd),
+        }
+    }
+
+    /// Looks a name up as a register, then as a level symbol
+    fn resolve(&self, ident: String) -> Result<i64, ExpressionError> {
+        let registers = &self.vm.cpu.registers;
+        match &ident.to_uppercase()[..] {
+            "A" => return Ok(registers.A as i64),
+            "X" => return Ok(registers.X as i64),
+            "Y" => return Ok(registers.Y as i64),
+            "PC" => return Ok(registers.PC as i64),
+            "SP" | "S" => return Ok(self.vm.cpu.stack.pointer as i64),
+            _ => (),
+        }
+
+        match self.vm.symbols.get(&ident) {
+            Some(address) => Ok(address as i64),
+            None => Err(ExpressionError::UnknownSymbol(ident)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vm::VirtualMachine;
+    use super::{evaluate, ExpressionError};
+
+    fn vm() -> VirtualMachine {
+        let mut vm = VirtualMachine::for_tests();
+        vm.cpu.registers.A = 0x42;
+        vm.cpu.registers.X = 0x03;
+        vm
+    }
+
+    fn eval(input: &str) -> i64 {
+        evaluate(input, &vm()).unwrap()
+    }
+
+    #[test]
+    fn parses_number_prefixes() {
+        assert_eq!(eval("$FF"), 0xFF);
+        assert_eq!(eval("0x10"), 0x10);
+        assert_eq!(eval("%101"), 5);
+        assert_eq!(eval("#10"), 10);
+        assert_eq!(eval("10"), 10);
+    }
+
+    #[test]
+    fn follows_operator_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("1 | 2 & 3"), 3);
+        assert_eq!(eval("#1 + #1 << #2"), 8);
+        assert_eq!(eval("$F0 & $F | 1"), 1);
+    }
+
+    #[test]
+    fn shifts() {
+        assert_eq!(eval("$80 >> 3"), 0x10);
+        assert_eq!(eval("1 << 8"), 0x100);
+        assert_eq!(eval("1 >> #80"), 0);
+    }
+
+    #[test]
+    fn negates() {
+        assert_eq!(eval("-1"), -1);
+        assert_eq!(eval("--5"), 5);
+        assert_eq!(eval("#10 - -#2"), 12);
+        assert_eq!(eval("-(1 + 2) * 2"), -6);
+    }
+
+    #[test]
+    fn wraps_on_overflow() {
+        assert_eq!(eval("$7FFFFFFFFFFFFFFF + 1"), i64::min_value());
+        assert_eq!(eval("1 << 64"), 0);
+        match evaluate("99999999999999999999", &vm()) {
+            Err(ExpressionError::InvalidNumber(_)) => (),
+            other => panic!("expected an invalid number, got {:?}", other),
+        }
+        match evaluate("$10000000000000000", &vm()) {
+            Err(ExpressionError::InvalidNumber(_)) => (),
+            other => panic!("expected an invalid number, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn resolves_registers_symbols_and_memory() {
+        let mut vm = vm();
+        vm.cpu.memory[0x0201] = 0x99;
+        assert_eq!(evaluate("X", &vm).unwrap(), 3);
+        assert_eq!(evaluate("a + 1", &vm).unwrap(), 0x43);
+        assert_eq!(evaluate("mem[Y_0 + 1]", &vm).unwrap(), 0x99);
+        assert_eq!(evaluate("BEEF", &vm).unwrap(), 0x300);
+        assert_eq!(evaluate("$BEEF", &vm).unwrap(), 0xBEEF);
+    }
+
+    #[test]
+    fn reads_bare_numbers_as_decimal_and_words_as_names() {
+        assert_eq!(eval("10 + 1"), 11);
+        assert_eq!(eval("A"), 0x42);
+        match evaluate("1F", &vm()) {
+            Err(ref err @ ExpressionError::InvalidNumber(_)) => {
+                assert_eq!(err.to_string(), "invalid number '1F', write $1F for a hex number")
+            }
+            other => panic!("expected an invalid number, got {:?}", other),
+        }
+        match evaluate("C000", &vm()) {
+            Err(ref err @ ExpressionError::UnknownSymbol(_)) => {
+                assert_eq!(err.to_string(), "unknown symbol 'C000', write $C000 for a hex number")
+            }
+            other => panic!("expected an unknown symbol, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reports_unknown_symbols() {
+        match evaluate("NOWHERE + 1", &vm()) {
+            Err(ExpressionError::UnknownSymbol(ref symbol)) if symbol == "NOWHERE" => (),
+            other => panic!("expected an unknown symbol, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reports_malformed_expressions() {
+        match evaluate("(1 + 2", &vm()) {
+            Err(ExpressionError::UnexpectedEnd) => (),
+            other => panic!("expected an unexpected end, got {:?}", other),
+        }
+        match evaluate("1 2", &vm()) {
+            Err(ExpressionError::UnexpectedToken(_)) => (),
+            other => panic!("expected an unexpected token, got {:?}", other),
+        }
+        match evaluate("mem[$10000]", &vm()) {
+            Err(ExpressionError::AddressOutOfRange(0x10000)) => (),
+            other => panic!("expected an address out of range, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,1855 @@
+
+mod args;
+mod completion;
+mod expression;
+mod pipeline;
+
+use std;
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use config::{self, Dock, DOCK_NAMES, SETTING_NAMES};
+use console::Style;
+use hexedit::HexEditor;
+use vm::VirtualMachine;
+use self::args::usage;
+use self::pipeline::Pipeline;
+
+pub use self::args::{Args, ArgKind, ArgSpec};
+pub use self::completion::Completion;
+pub use self::expression::{evaluate, format_value};
+
+pub type UnblockEvent = Box<Fn(&mut VirtualMachine)>;
+
+const DEFAULT_GDB_PORT: u16 = 6502;
+const DEFAULT_DAP_PORT: u16 = 4711;
+const MAX_SCRIPT_DEPTH: usize = 8;
+const MAX_ALIAS_DEPTH: usize = 8;
+
+/// The status flags, as named by the flags command
+const FLAG_NAMES: [&'static str; 7] = ["carry", "zero", "interrupt", "decimal", "break", "overflow", "sign"];
+
+pub struct CommandSystem {
+    commands: Vec<Box<Command>>, 
+}
+impl CommandSystem {
+    pub fn new() -> CommandSystem {
+        let mut system = CommandSystem {
+            commands: Vec::new()
+        };
+
+        system.add_command(HelpCommand);
+        system.add_command(ClearCommand);
+        system.add_command(SourceCommand);
+        system.add_command(ListCommand);
+        system.add_command(RegistersCommand);
+        system.add_command(StepCommand);
+        system.add_command(NextCommand);
+        system.add_command(ContinueCommand);
+        system.add_command(BreakCommand);
+        system.add_command(FlagsCommand);
+        system.add_command(MemdmpCommand);
+        system.add_command(MemsetCommand);
+        system.add_command(HexeditCommand);
+        system.add_command(MonitorCommand);
+        system.add_command(PrintCommand);
+        system.add_command(GdbServerCommand);
+        system.add_command(DapServerCommand);
+        system.add_command(RunCommand::new());
+        system.add_command(HistoryCommand);
+        system.add_command(LogCommand);
+        system.add_command(DockCommand);
+        system.add_command(LayoutCommand);
+        system.add_command(ConfigCommand);
+        system.add_command(AliasCommand);
+        system.add_command(UnaliasCommand);
+        system.add_command(ExitCommand);
+
+        system
+    }
+
+    pub fn add_command<C>(&mut self, command: C)
+        where C: Command + 'static
+    {
+        self.commands.push(Box::new(command));
+    }
+
+    /// Executes a line of input. Commands can be chained with ';', in which
+    /// case they run in order until one of them fails. If more than one command
+    /// blocks, each block is ended as soon as the next command blocks. Output
+    /// of each command can be filtered with '|' and written to a file with '>'.
+    pub fn execute<S>(&self, command: S, vm: &mut VirtualMachine) -> (CommandResult, Option<UnblockEvent>)
+        where S: Into<String>
+    {
+        self.execute_chain(&command.into(), vm, 0)
+    }
+
+    fn execute_chain(&self, chain: &str, vm: &mut VirtualMachine, depth: usize) -> (CommandResult, Option<UnblockEvent>) {
+        let mut result = CommandResult::Sucess;
+        let mut pending: Option<UnblockEvent> = None;
+
+        for command in split_commands(chain) {
+            let pipeline = match Pipeline::parse(command) {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    writeln!(vm.console.styled(Style::Error), "{}", err).unwrap();
+                    if let Some(pending) = pending {
+                        pending(vm);
+                    }
+                    return (CommandResult::InvalidArgs, None);
+                }
+            };
+
+            if pipeline.is_paged() {
+                vm.console.begin_paging();
+            }
+
+            // Output is collected so it can be filtered or written elsewhere
+            if !pipeline.is_plain() {
+                vm.console.begin_capture();
+            }
+
+            // Aliases may expand to a chain or pipeline of their own
+            let command = self.expand_aliases(pipeline.command.into(), vm);
+            let nested = split_commands(&command).len() > 1 ||
+                         Pipeline::parse(&command).map(|nested| !nested.is_plain()).unwrap_or(false);
+            let (mut command_result, unblock_event) = if nested && depth < MAX_ALIAS_DEPTH {
+                self.execute_chain(&command, vm, depth + 1)
+            } else {
+                self.execute_single(&command, vm)
+            };
+
+            if !pipeline.is_plain() {
+                let output = vm.console.end_capture();
+                match command_result {
+                    // Errors are always shown, rather than filtered away
+                    CommandResult::NotFound | CommandResult::InvalidArgs => {
+                        write!(vm.console, "{}", output).unwrap();
+                    }
+                    _ => {
+                        if let Err(err) = pipeline.finish(&output, &mut vm.console) {
+                            writeln!(vm.console.styled(Style::Error), "{}", err).unwrap();
+                            command_result = CommandResult::InvalidArgs;
+                        }
+                    }
+                }
+            }
+
+            if let Some(unblock_event) = unblock_event {
+                if let Some(previous) = pending.take() {
+                    previous(vm);
+                }
+                pending = Some(unblock_event);
+            }
+
+            match command_result {
+                CommandResult::NotFound | CommandResult::InvalidArgs => {
+                    if let Some(pending) = pending {
+                        pending(vm);
+                    }
+                    return (command_result, None);
+                }
+                _ => result = command_result,
+            }
+        }
+
+        if pending.is_some() {
+            (CommandResult::SucessBlock, pending)
+        } else {
+            (result, None)
+        }
+    }
+
+    fn execute_single(&self, command: &str, mut vm: &mut VirtualMachine) -> (CommandResult, Option<UnblockEvent>) {
+        let parts = split_words(command);
+        if parts.is_empty() {
+            return (CommandResult::Sucess, None);
+        }
+
+        for command in self.commands.iter() {
+            if command.matches_name(parts[0].clone()) {
+                let specs = command.get_args();
+                let args = match args::parse(&specs, &parts[1..], vm) {
+                    Ok(args) => args,
+                    Err(err) => {
+                        writeln!(vm.console.styled(Style::Error), "{}", err).unwrap();
+                        writeln!(vm.console.styled(Style::Error), "Usage: {}", usage(&parts[0], &specs)).unwrap();
+                        return (CommandResult::InvalidArgs, None);
+                    }
+                };
+
+                let result = command.execute(args, &self, &mut vm);
+                if let CommandResult::SucessBlock = result {
+                    return (result, Some(command.on_unblock_event()));
+                } else {
+                    return (result, None);
+                }
+            }
+        }
+
+        (CommandResult::NotFound, None)
+    }
+
+    /// Returns true if pressing ENTER on an empty line should run this line of
+    /// input again, which is the case when its first command is repeatable
+    pub fn is_repeatable(&self, command: &str, vm: &VirtualMachine) -> bool {
+        let first = match split_commands(command).into_iter().next() {
+            Some(first) => self.expand_aliases(first.into(), vm),
+            None => return false,
+        };
+
+        match first.split(|c: char| c == ';' || c.is_whitespace()).next() {
+            Some(name) => {
+                self.commands
+                    .iter()
+                    .find(|command| command.matches_name(name.to_string()))
+                    .map(|command| command.is_repeatable())
+                    .unwrap_or(false)
+            }
+            None => false,
+        }
+    }
+
+    /// Replaces a leading alias with its expansion, repeatedly, so aliases can
+    /// refer to other aliases
+    fn expand_aliases(&self, command: String, vm: &VirtualMachine) -> String {
+        let mut command = command;
+        for _ in 0..MAX_ALIAS_DEPTH {
+            let expanded = {
+                let parts = command.split_whitespace().collect::<Vec<_>>();
+                let expansion = match parts.first().and_then(|name| vm.console.config().get_alias(name)) {
+                    Some(expansion) => expansion,
+                    None => break,
+                };
+                let expanded = expand_placeholders(expansion, &parts[1..]);
+
+                // An alias that expands to a command of the same name stops here
+                if expanded.split_whitespace().next() == parts.first().cloned() {
+                    return expanded;
+                }
+                expanded
+            };
+            command = expanded;
+        }
+        command
+    }
+
+    /// Describes a single command in detail, or the command an alias runs
+    fn print_help(&self, name: &str, aliases: &[(String, String)], vm: &mut VirtualMachine) -> CommandResult {
+        let mut name = name.to_string();
+        if let Some(&(_, ref expansion)) = aliases.iter().find(|&&(ref alias, _)| *alias == name) {
+            writeln!(vm.console, "{} is an alias for: {}", name, expansion).unwrap();
+            match expansion.split_whitespace().next() {
+                Some(first) => name = first.to_string(),
+                None => return CommandResult::Sucess,
+            }
+        }
+
+        let command = match self.commands.iter().find(|command| command.matches_name(name.clone())) {
+            Some(command) => command,
+            None => {
+                writeln!(vm.console.styled(Style::Error), "No command named {}", name).unwrap();
+                return CommandResult::InvalidArgs;
+            }
+        };
+
+        let names = command.get_names();
+        writeln!(vm.console, "Usage: {}", usage(names[0], &command.get_args())).unwrap();
+        if names.len() > 1 {
+            writeln!(vm.console, "Also: {}", names[1..].join(", ")).unwrap();
+        }
+
+        // User aliases that run this command
+        let command_aliases = aliases.iter()
+            .filter(|&&(_, ref expansion)| {
+                expansion.split_whitespace()
+                    .next()
+                    .map(|first| command.matches_name(first.to_string()))
+                    .unwrap_or(false)
+            })
+            .map(|&(ref name, _)| &name[..])
+            .collect::<Vec<_>>();
+        if !command_aliases.is_empty() {
+            writeln!(vm.console, "Aliases: {}", command_aliases.join(", ")).unwrap();
+        }
+
+        for line in command.get_help().trim().lines() {
+            writeln!(vm.console, "   {}", line.trim()).unwrap();
+        }
+
+        let examples = command.get_examples();
+        if !examples.is_empty() {
+            writeln!(vm.console, "Examples:").unwrap();
+            for (example, description) in examples {
+                writeln!(vm.console, "   {:<28} {}", example, description).unwrap();
+            }
+        }
+
+        CommandResult::Sucess
+    }
+
+    /// Executes each line of a script as a console command. Blank lines and
+    /// lines starting with '#' are skipped, and the script stops at the first
+    /// line that fails. If a command blocks, the block carries on once the
+    /// script has finished, unless the script fails.
+    pub fn execute_script<P>(&self, path: P, vm: &mut VirtualMachine) -> (CommandResult, Option<UnblockEvent>)
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref();
+        let mut script = String::new();
+        if let Err(err) = File::open(path).and_then(|mut file| file.read_to_string(&mut script)) {
+            writeln!(vm.console.styled(Style::Error), "Unable to read {}: {}", path.display(), err).unwrap();
+            return (CommandResult::InvalidArgs, None);
+        }
+
+        let mut pending: Option<UnblockEvent> = None;
+        for (index, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            writeln!(vm.console.styled(Style::Input), "hakka> {}", line).unwrap();
+            let (result, unblock_event) = self.execute(line, vm);
+            match result {
+                CommandResult::NotFound | CommandResult::InvalidArgs => {
+                    if let CommandResult::NotFound = result {
+                        writeln!(vm.console.styled(Style::Error), "Command not recognized").unwrap();
+                    }
+                    writeln!(vm.console.styled(Style::Error), "Script stopped at {}:{}", path.display(), index + 1).unwrap();
+                    if let Some(pending) = pending {
+                        pending(vm);
+                    }
+                    return (result, None);
+                }
+                _ => (),
+            }
+
+            if let Some(unblock_event) = unblock_event {
+                // Only one command can block the console at a time
+                if let Some(previous) = pending.take() {
+                    previous(vm);
+                }
+                pending = Some(unblock_event);
+            }
+        }
+
+        if pending.is_some() {
+            (CommandResult::SucessBlock, pending)
+        } else {
+            (CommandResult::Sucess, None)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CommandResult {
+    NotFound,
+    InvalidArgs,
+    Sucess,
+    SucessBlock,
+}
+
+pub trait Command {
+    /// Runs the command. The arguments have already been checked against
+    /// `get_args`, so the values it declares as required are present.
+    fn execute(&self, args: Args, system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult;
+
+    fn get_names(&self) -> Vec<&str>;
+
+    /// Describes the arguments the command takes
+    fn get_args(&self) -> Vec<ArgSpec> {
+        Vec::new()
+    }
+
+    fn get_help(&self) -> &str {
+        "No help text provided!"
+    }
+
+    /// Example uses of the command and what they do, shown by `help <command>`
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        Vec::new()
+    }
+
+    fn matches_name(&self, name: String) -> bool {
+        for actual_name in self.get_names() {
+            if actual_name == name {
+                return true;
+            }
+        }
+        return false
+    }
+
+    fn on_unblock_event(&self) -> UnblockEvent {
+        Box::new(|_| {})
+    }
+
+    /// What the argument at `index` completes to when Tab is pressed
+    fn complete_arg(&self, index: usize) -> Completion {
+        args::completion(&self.get_args(), index)
+    }
+
+    /// Whether pressing ENTER on an empty line runs this command again
+    fn is_repeatable(&self) -> bool {
+        false
+    }
+}
+
+/// Splits a line of input into its ';' separated commands. A ';' inside
+/// double quotes doesn't separate commands, so it can be used in an alias.
+fn split_commands(line: &str) -> Vec<&str> {
+    let mut commands = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                commands.push(&line[start..index]);
+                start = index + 1;
+            }
+            _ => (),
+        }
+    }
+    commands.push(&line[start..]);
+
+    commands.into_iter().map(str::trim).filter(|command| !command.is_empty()).collect()
+}
+
+/// Splits a command into words at whitespace. Double quotes group words
+/// together and are removed. Parentheses and brackets also group words, and
+/// are kept, so `memset (Y_0 + 1) 5` has two arguments.
+fn split_words(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    let mut depth = 0;
+    let mut in_word = false;
+    for c in command.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            '(' | '[' if !quoted => {
+                depth += 1;
+                word.push(c);
+                in_word = true;
+            }
+            ')' | ']' if !quoted => {
+                if depth > 0 {
+                    depth -= 1;
+                }
+                word.push(c);
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted && depth == 0 => {
+                if in_word {
+                    words.push(word.clone());
+                    word.clear();
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+/// Substitutes `{1}` to `{9}` in an alias expansion with the arguments given
+/// to the alias, and `{*}` with all of them. If the expansion has no
+/// placeholders the arguments are appended instead. Braces aren't part of
+/// the expression syntax, so a placeholder can't be mistaken for a number.
+fn expand_placeholders(expansion: &str, args: &[&str]) -> String {
+    let mut result = String::new();
+    let mut substituted = false;
+
+    let mut chars = expansion.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '{' {
+            let mut lookahead = chars.clone();
+            let placeholder = lookahead.next();
+            if lookahead.next() == Some('}') {
+                match placeholder {
+                    Some('*') => {
+                        result.push_str(&args.join(" "));
+                    }
+                    Some(digit) if digit >= '1' && digit <= '9' => {
+                        let index = digit.to_digit(10).unwrap() as usize - 1;
+                        result.push_str(args.get(index).cloned().unwrap_or(""));
+                    }
+                    _ => {
+                        result.push(c);
+                        continue;
+                    }
+                }
+                chars = lookahead;
+                substituted = true;
+                continue;
+            }
+        }
+        result.push(c);
+    }
+
+    if !substituted && !args.is_empty() {
+        result.push(' ');
+        result.push_str(&args.join(" "));
+    }
+
+    result
+}
+
+struct HelpCommand;
+impl Command for HelpCommand {
+    fn execute(&self, args: Args, system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let aliases = vm.console.config()
+            .get_aliases()
+            .into_iter()
+            .map(|(name, expansion)| (name.to_string(), expansion.to_string()))
+            .collect::<Vec<_>>();
+
+        if let Some(name) = args.word(0) {
+            return system.print_help(name, &aliases, vm);
+        }
+
+        writeln!(vm.console, "Commands:").unwrap();
+        for command in system.commands.iter() {
+            let names = command.get_names();
+            let mut line = usage(&names.join(", "), &command.get_args());
+            if let Some(summary) = command.get_help().trim().lines().next() {
+                line = format!("{:<32} {}", line, summary.trim());
+            }
+            writeln!(vm.console, "   {}", line).unwrap();
+        }
+
+        if !aliases.is_empty() {
+            writeln!(vm.console, "Aliases:").unwrap();
+            for &(ref name, ref expansion) in &aliases {
+                writeln!(vm.console, "   {} = {}", name, expansion).unwrap();
+            }
+        }
+
+        writeln!(vm.console, "Separate commands with ';'. Send output to a file with > or >>,").unwrap();
+        writeln!(vm.console, "or filter it with | grep [-v] [-i] text, | head [n] or | tail [n].").unwrap();
+        writeln!(vm.console, "Add | more to stop at each screenful.").unwrap();
+        writeln!(vm.console, "Type 'help <command>' for details and examples").unwrap();
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["help", "h", "?"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::optional("command", ArgKind::Command)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Lists the commands, or describes <command> in
+         detail."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("help memset", "Describes the memset command")]
+    }
+}
+
+struct ClearCommand;
+impl Command for ClearCommand {
+    fn execute(&self, _args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        vm.console.clear();
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["clear", "cls"]
+    }
+
+    fn get_help(&self) -> &str {
+        "Clears the console"
+    }
+}
+
+struct SourceCommand;
+impl Command for SourceCommand {
+    fn execute(&self, _args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        // Large levels don't fit on one screen
+        vm.console.begin_paging();
+        vm.dump_disassembly();
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["source"]
+    }
+
+    fn get_help(&self) -> &str {
+        "Lists the code currently running in the virtual
+         machine. A '>' symbol indicates the current
+         program counter. Long listings stop at each
+         screenful: press Space for the next page, ENTER
+         for the next line or q to stop."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("source | grep STA", "Lists the instructions that store A"),
+             ("source >> dump.asm", "Adds the code to the end of dump.asm")]
+    }
+}
+
+struct ListCommand;
+impl Command for ListCommand {
+    fn execute(&self, _args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        vm.dump_local_disassembly();
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["list"]
+    }
+
+    fn get_help(&self) -> &str {
+        "Lists the code surrounding the current
+         program counter."
+    }
+}
+
+struct RegistersCommand;
+impl Command for RegistersCommand {
+    fn execute(&self, _args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        vm.dump_registers();
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["registers", "reg"]
+    }
+
+    fn get_help(&self) -> &str {
+        "Lists the CPU registers and their current
+         values."
+    }
+}
+
+struct MonitorCommand;
+impl Command for MonitorCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let (start, end) = args.range(0).unwrap();
+        vm.enable_memory_monitor(start..end);
+
+        CommandResult::SucessBlock
+    }
+
+    fn on_unblock_event(&self) -> UnblockEvent {
+        Box::new(|vm| {
+            vm.disable_memory_monitor();
+            writeln!(vm.console, "Disabled memory monitor").unwrap();
+        })
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["monitor", "mon"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::required("start end", ArgKind::Range)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Dumps the memory between <start> and <end>
+         (inclusive) every seconds. Press ENTER to
+         stop monitoring."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("monitor 0 $F", "Watches the first 16 bytes of the zero page")]
+    }
+}
+
+struct MemsetCommand;
+impl Command for MemsetCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let start = args.address(0).unwrap();
+        let bytes = args.bytes(1);
+        if bytes.is_empty() {
+            return CommandResult::Sucess;
+        }
+
+        if start + bytes.len() - 1 > u16::max_value() as usize {
+            writeln!(vm.console.styled(Style::Error), "Values would be written outside addressable range").unwrap();
+            return CommandResult::InvalidArgs;
+        }
+
+        for (index, byte) in bytes.iter().enumerate() {
+            vm.cpu.memory[start + index] = *byte;
+        }
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["memset", "set"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::required("address", ArgKind::Address),
+             ArgSpec::required("value", ArgKind::Byte).variadic()]
+    }
+
+    fn get_help(&self) -> &str {
+        "Writes the given value to the given address.
+         Multiple values will be written to consequent
+         addresses."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("memset 0x00 0x01", "Stores 01 at address 0000"),
+             ("memset $10 1 2 3", "Stores 01, 02 and 03 from address 0010")]
+    }
+}
+
+struct MemdmpCommand;
+impl Command for MemdmpCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let start = args.address(0).unwrap();
+
+        // Dump a range
+        if let Some(end) = args.address(1) {
+            if end < start {
+                writeln!(vm.console.styled(Style::Error), "End address {:04X} is before start address {:04X}", end, start).unwrap();
+                return CommandResult::InvalidArgs;
+            }
+
+            vm.dump_memory_range(start, end);
+
+        // Dump a page
+        } else {
+            if start > 0xFF {
+                writeln!(vm.console.styled(Style::Error), "Page index {:X} is outside addressable range", start).unwrap();
+                return CommandResult::InvalidArgs;
+            }
+
+            vm.dump_memory_page(start);
+        }
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["memdmp", "dmp"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::required("page|start", ArgKind::Address),
+             ArgSpec::optional("end", ArgKind::Address)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Dumps a single memory page, or a specified memory
+         range from <start> to <end>."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("memdmp 0", "Dumps the zero page"),
+             ("memdmp $C000 $C0FF", "Dumps the first page of the level code"),
+             ("memdmp 0 $FF > zp.txt", "Saves the zero page to zp.txt")]
+    }
+}
+
+struct HexeditCommand;
+impl Command for HexeditCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let address = args.address(0).unwrap_or(vm.memory_view());
+        let editor = HexEditor::new(address, &vm.cpu.memory[..]);
+        vm.console.open_hex_editor(editor);
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["hexedit", "hex"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::optional("address", ArgKind::Address)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Shows memory in a grid that takes over the console,
+         starting at <address>. Move with the arrow keys,
+         PageUp and PageDown, and type hex digits to
+         overwrite bytes. G goes to another address, and
+         q or Escape goes back to the console. Bytes are
+         highlighted when they change."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("hexedit", "Edits memory from the debugger layout's memory pane"),
+             ("hexedit $0200", "Edits memory from 0200 onwards")]
+    }
+}
+
+struct FlagsCommand;
+impl Command for FlagsCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let name = match args.word(0) {
+            Some(name) => name,
+            None => {
+                vm.dump_flags();
+                return CommandResult::Sucess;
+            }
+        };
+
+        let value = {
+            let flags = &mut vm.cpu.flags;
+            let flag = match name {
+                "carry" => &mut flags.carry,
+                "zero" => &mut flags.zero,
+                "interrupt" => &mut flags.interrupt_disabled,
+                "decimal" => &mut flags.decimal,
+                "break" => &mut flags.breakpoint,
+                "overflow" => &mut flags.overflow,
+                "sign" => &mut flags.sign,
+                _ => {
+                    writeln!(vm.console.styled(Style::Error),
+                             "Unknown flag {}, expected one of: {}",
+                             name,
+                             FLAG_NAMES.join(", "))
+                        .unwrap();
+                    return CommandResult::InvalidArgs;
+                }
+            };
+
+            if let Some(value) = args.word(1) {
+                *flag = value == "on";
+            }
+            *flag
+        };
+        writeln!(vm.console, "{}: {}", name, value).unwrap();
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["flags"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::optional("flag", ArgKind::Flag),
+             ArgSpec::optional("on|off", ArgKind::Enum(&["on", "off"]))]
+    }
+
+    fn get_help(&self) -> &str {
+        "Prints the status flags. Given a <flag>, prints
+         just that flag, and sets or clears it if on or
+         off is given too."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("flags carry", "Prints the carry flag"),
+             ("flags i off", "Clears the interrupt disable flag")]
+    }
+}
+
+struct BreakCommand;
+impl Command for BreakCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        // Break at the given address
+        if let Some(address) = args.address(0) {
+            if vm.toggle_breakpoint(address) {
+                writeln!(vm.console, "Added breakpoint at {:04X}", address).unwrap();
+            } else {
+                writeln!(vm.console, "Removed breakpoint at {:04X}", address).unwrap();
+            }
+
+        // Break at current program counter
+        } else {
+            vm.break_execution();
+            writeln!(vm.console, "Breaking execution at {:04X}", vm.cpu.registers.PC).unwrap();
+        }
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["break", "b"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::optional("address", ArgKind::Address)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Toggles a breakpoint at the specified <address>.
+         If the program counter hits this address, execution
+         stops. If no address is specified, execution will
+         be stopped at the current point, without inserting
+         a breakpoint."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("break $C000", "Toggles a breakpoint at C000"),
+             ("break main_loop", "Toggles a breakpoint at a label in the level")]
+    }
+}
+
+struct ContinueCommand;
+impl Command for ContinueCommand {
+    fn execute(&self, _args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        vm.continue_execution();
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["continue", "c"]
+    }
+
+    fn get_help(&self) -> &str {
+        "Resumes program execution after the program
+         has stopped at a breakpoint."
+    }
+}
+
+struct StepCommand;
+impl Command for StepCommand {
+    fn execute(&self, _args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        vm.step_execution();
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["step", "s"]
+    }
+
+    fn is_repeatable(&self) -> bool {
+        true
+    }
+
+    fn get_help(&self) -> &str {
+        "Executes a single instruction, then stops
+         execution"
+    }
+}
+
+struct NextCommand;
+impl Command for NextCommand {
+    fn execute(&self, _args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        vm.step_over_execution();
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["next", "n"]
+    }
+
+    fn is_repeatable(&self) -> bool {
+        true
+    }
+
+    fn get_help(&self) -> &str {
+        "Executes a single instruction, then stops
+         execution. A JSR runs the whole subroutine and
+         stops once it has returned."
+    }
+}
+
+struct PrintCommand;
+impl Command for PrintCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let value = args.number(0).unwrap();
+        writeln!(vm.console, "{}", format_value(value)).unwrap();
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["print", "p"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::required("expression", ArgKind::Expression).variadic()]
+    }
+
+    fn get_help(&self) -> &str {
+        "Evaluates <expression> and prints the result in
+         hexadecimal, decimal and binary. Numbers are
+         decimal unless written as $hex, 0xhex or %binary,
+         so 10 is ten and $10 is sixteen. Any word that
+         doesn't start with a digit is a name: a register
+         (A, X, Y, PC, SP) or a level symbol. Numbers,
+         names and mem[addr] can be combined with
+         + - * & | << >> and parentheses. Expressions work
+         wherever a command expects an address or value.
+         The last argument of a command takes the rest of
+         the line, but wrap an expression with spaces in
+         parentheses when more arguments follow it, e.g.
+         memset (Y_0 + 1) 5.
+         Wrap >> in parentheses, e.g. (X >> 1), so it isn't
+         taken as writing to a file."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("print Y_0 + 1", "Adds one to the address of a level symbol"),
+             ("print A", "Prints the accumulator"),
+             ("print mem[PC]", "Prints the opcode at the program counter")]
+    }
+}
+
+struct GdbServerCommand;
+impl Command for GdbServerCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        if args.word(0) == Some("off") {
+            if vm.stop_gdb_server() {
+                writeln!(vm.console, "Stopped GDB server").unwrap();
+            } else {
+                writeln!(vm.console, "GDB server is not running").unwrap();
+            }
+            return CommandResult::Sucess;
+        }
+
+        let port = args.number(0).map(|port| port as u16).unwrap_or(DEFAULT_GDB_PORT);
+        match vm.start_gdb_server(port) {
+            Ok(port) => writeln!(vm.console, "GDB server listening on 127.0.0.1:{}", port).unwrap(),
+            Err(err) => writeln!(vm.console.styled(Style::Error), "Unable to start GDB server: {}", err).unwrap(),
+        }
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["gdbserver"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::optional("port|off", ArgKind::Port)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Listens for a GDB remote protocol debugger on
+         localhost <port> (6502 by default). Registers
+         are sent as A, X, Y, S, P, PC. Use 'off' to stop
+         the server."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("gdbserver", "Listens on port 6502"),
+             ("gdbserver off", "Stops the server")]
+    }
+}
+
+struct DapServerCommand;
+impl Command for DapServerCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        if args.word(0) == Some("off") {
+            if vm.stop_dap_server() {
+                writeln!(vm.console, "Stopped debug adapter server").unwrap();
+            } else {
+                writeln!(vm.console, "Debug adapter server is not running").unwrap();
+            }
+            return CommandResult::Sucess;
+        }
+
+        let port = args.number(0).map(|port| port as u16).unwrap_or(DEFAULT_DAP_PORT);
+        match vm.start_dap_server(port) {
+            Ok(port) => writeln!(vm.console, "Debug adapter server listening on 127.0.0.1:{}", port).unwrap(),
+            Err(err) => writeln!(vm.console.styled(Style::Error), "Unable to start debug adapter server: {}", err).unwrap(),
+        }
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["dapserver"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::optional("port|off", ArgKind::Port)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Listens for an editor using the Debug Adapter
+         Protocol on localhost <port> (4711 by default),
+         so breakpoints can be set in level.asm. Use
+         'off' to stop the server."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("dapserver 5000", "Listens on port 5000")]
+    }
+}
+
+struct RunCommand {
+    /// The unblock event of a blocking command in the last script that was run
+    pending: RefCell<Option<UnblockEvent>>,
+    /// How many scripts deep we are, to stop scripts that run themselves
+    depth: Cell<usize>,
+}
+
+impl RunCommand {
+    fn new() -> RunCommand {
+        RunCommand {
+            pending: RefCell::new(None),
+            depth: Cell::new(0),
+        }
+    }
+}
+
+impl Command for RunCommand {
+    fn execute(&self, args: Args, system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        if self.depth.get() >= MAX_SCRIPT_DEPTH {
+            writeln!(vm.console.styled(Style::Error), "Scripts are nested too deeply").unwrap();
+            return CommandResult::InvalidArgs;
+        }
+
+        self.depth.set(self.depth.get() + 1);
+        let (result, unblock_event) = system.execute_script(args.word(0).unwrap(), vm);
+        self.depth.set(self.depth.get() - 1);
+
+        *self.pending.borrow_mut() = unblock_event;
+        result
+    }
+
+    fn on_unblock_event(&self) -> UnblockEvent {
+        self.pending.borrow_mut().take().unwrap_or(Box::new(|_| {}))
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["run"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::required("file", ArgKind::Text)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Runs each line of <file> as a console command,
+         stopping at the first command that fails. Lines
+         starting with '#' are ignored. The 'hakkarc'
+         file in the config directory is run at startup."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("run setup.txt", "Runs the commands in setup.txt")]
+    }
+}
+
+struct HistoryCommand;
+impl Command for HistoryCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let history = vm.console.history().to_vec();
+        let start = match args.number(0) {
+            Some(count) if (count as usize) < history.len() => history.len() - count as usize,
+            _ => 0,
+        };
+        let first = vm.console.history_start();
+        for (index, command) in history.iter().enumerate().skip(start) {
+            writeln!(vm.console, "{:>5}  {}", first + index, command).unwrap();
+        }
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["history"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::optional("count", ArgKind::Number)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Lists the commands entered so far, or the last
+         <count> of them. Use !n to run command n again,
+         !! to run the last command and !prefix to run the
+         last command starting with prefix. The history is
+         kept between sessions, up to history_size commands,
+         per level if history_per_level is set."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("history 10", "Lists the last 10 commands"),
+             ("!3", "Runs the third command again")]
+    }
+}
+
+struct LogCommand;
+impl Command for LogCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        match args.word(0) {
+            Some("on") => {
+                match vm.console.start_transcript(args.word(1)) {
+                    Ok(path) => writeln!(vm.console, "Logging the session to {}", path.display()).unwrap(),
+                    Err(err) => {
+                        writeln!(vm.console.styled(Style::Error), "Unable to start the session log: {}", err).unwrap();
+                        return CommandResult::InvalidArgs;
+                    }
+                }
+            }
+            Some(_) => {
+                match vm.console.stop_transcript() {
+                    Some(path) => writeln!(vm.console, "Stopped logging to {}", path.display()).unwrap(),
+                    None => writeln!(vm.console, "The session is not being logged").unwrap(),
+                }
+            }
+            None => {
+                let path = vm.console.transcript_path().map(|path| path.to_path_buf());
+                match path {
+                    Some(path) => writeln!(vm.console, "Logging the session to {}", path.display()).unwrap(),
+                    None => writeln!(vm.console, "The session is not being logged").unwrap(),
+                }
+            }
+        }
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["log"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::optional("on|off", ArgKind::Enum(&["on", "off"])),
+             ArgSpec::optional("file", ArgKind::Text)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Copies everything shown in the console, including
+         the commands typed, to <file>. Without a file, a
+         new log is started in the logs folder of the
+         hakka data directory. Set log_sessions in the
+         configuration to log every session."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("log on", "Starts a new timestamped session log"),
+             ("log on solution.txt", "Adds the session to the end of solution.txt"),
+             ("log off", "Stops logging")]
+    }
+}
+
+struct DockCommand;
+impl Command for DockCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        if let Some(dock) = args.word(0).and_then(Dock::from_name) {
+            vm.console.config_mut().set_console_dock(dock);
+        }
+        if let Some(size) = args.number(1) {
+            vm.console.config_mut().set_console_size(size as u32);
+        }
+        if args.has(0) {
+            if let Err(err) = vm.console.save_config() {
+                writeln!(vm.console.styled(Style::Error), "Unable to save configuration: {:?}", err).unwrap();
+            }
+        }
+
+        let config = vm.console.config();
+        let (dock, size) = (config.get_console_dock(), config.get_console_size());
+        writeln!(vm.console, "The console is docked {} and covers {}% of the window", dock.name(), size).unwrap();
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["dock"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::optional("left|right|top|bottom", ArgKind::Enum(&DOCK_NAMES)),
+             ArgSpec::optional("percent", ArgKind::Number)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Moves the console to an edge of the window, and
+         sets how much of the window it covers. The edge
+         facing the game can also be dragged with the
+         mouse. Opacity and font size are set with
+         console_opacity and console_font_size in the
+         configuration."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("dock bottom 30", "Shows the console across the bottom third"),
+             ("dock right", "Moves the console to the right edge")]
+    }
+}
+
+struct LayoutCommand;
+impl Command for LayoutCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        if let Some(layout) = args.word(0) {
+            vm.console.config_mut().set_debugger_layout(layout == "debugger");
+            if let Err(err) = vm.console.save_config() {
+                writeln!(vm.console.styled(Style::Error), "Unable to save configuration: {:?}", err).unwrap();
+            }
+        }
+        if let Some(address) = args.address(1) {
+            vm.set_memory_view(address);
+        }
+
+        if vm.console.config().get_debugger_layout() {
+            writeln!(vm.console,
+                     "The debugger layout is shown while execution is stopped, with memory from {:04X}",
+                     vm.memory_view())
+                .unwrap();
+        } else {
+            writeln!(vm.console, "Only the console is shown while execution is stopped").unwrap();
+        }
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["layout"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::optional("debugger|console", ArgKind::Enum(&["debugger", "console"])),
+             ArgSpec::optional("memory", ArgKind::Address)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Chooses what is shown while execution is stopped.
+         The debugger layout has panes for the code around
+         PC, the registers and flags, and memory from
+         <memory>, all kept up to date as you step. The
+         console moves below them."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("layout debugger", "Shows the debugger panes at each breakpoint"),
+             ("layout debugger $0200", "Also shows memory from 0200 onwards"),
+             ("layout console", "Goes back to the console alone")]
+    }
+}
+
+struct ConfigCommand;
+impl Command for ConfigCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let name = args.word(1);
+        match (args.word(0).unwrap(), name) {
+            ("list", _) => {
+                for name in SETTING_NAMES.iter() {
+                    let value = vm.console.config().get_setting(name).unwrap_or_default();
+                    writeln!(vm.console, "{:<20} {}", name, value).unwrap();
+                }
+                return CommandResult::Sucess;
+            }
+            ("get", Some(name)) => {
+                let value = vm.console.config().get_setting(name).unwrap_or_default();
+                writeln!(vm.console, "{} = {}", name, value).unwrap();
+                return CommandResult::Sucess;
+            }
+            ("set", Some(name)) => {
+                let value = args.words(2).join(" ");
+                if value.is_empty() {
+                    writeln!(vm.console.styled(Style::Error), "Missing value").unwrap();
+                    return CommandResult::InvalidArgs;
+                }
+                if let Err(err) = vm.console.config_mut().set_setting(name, &value) {
+                    writeln!(vm.console.styled(Style::Error), "{}", err).unwrap();
+                    return CommandResult::InvalidArgs;
+                }
+            }
+            ("reset", Some(name)) => vm.console.config_mut().reset_setting(name),
+            ("reset", None) => {
+                for name in SETTING_NAMES.iter() {
+                    vm.console.config_mut().reset_setting(name);
+                }
+            }
+            (_, None) => {
+                writeln!(vm.console.styled(Style::Error), "Missing setting").unwrap();
+                return CommandResult::InvalidArgs;
+            }
+            // The subcommand has already been checked against the list in get_args
+            (subcommand, Some(_)) => unreachable!("unknown config subcommand {}", subcommand),
+        }
+
+        // Most settings are read from the configuration as they're used, but
+        // the VM keeps its own clock rate, which --clock may have overridden
+        if name.map(|name| name == "clock_rate").unwrap_or(true) {
+            let clock_rate = vm.console.config().get_clock_rate();
+            vm.set_clock_rate(clock_rate);
+        }
+        if let Err(err) = vm.console.save_config() {
+            writeln!(vm.console.styled(Style::Error), "Unable to save configuration: {:?}", err).unwrap();
+        }
+
+        match name {
+            Some(name) => {
+                let value = vm.console.config().get_setting(name).unwrap_or_default();
+                writeln!(vm.console, "{} = {}", name, value).unwrap();
+                if config::is_startup_setting(name) {
+                    writeln!(vm.console, "This takes effect the next time the game starts").unwrap();
+                }
+            }
+            None => writeln!(vm.console, "Every setting is back to its default").unwrap(),
+        }
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["config"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::required("list|get|set|reset", ArgKind::Enum(&["list", "get", "set", "reset"])),
+             ArgSpec::optional("setting", ArgKind::Enum(&SETTING_NAMES)),
+             ArgSpec::optional("value", ArgKind::Text).variadic()]
+    }
+
+    fn get_help(&self) -> &str {
+        "Shows and changes the settings in config.json.
+         Changes are saved straight away, and most take
+         effect immediately. Keys are given by name, e.g.
+         F1 or Grave, and colors as #RRGGBB or #RRGGBBAA.
+         reset without a setting resets all of them, but
+         keeps your aliases."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("config list", "Shows every setting"),
+             ("config set key.toggle_console F1", "Opens the console with F1"),
+             ("config set color.normal #FFB000", "Draws console output in amber"),
+             ("config reset clock_rate", "Puts the clock rate back to its default")]
+    }
+}
+
+struct AliasCommand;
+impl Command for AliasCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let name = match args.word(0) {
+            Some(name) => name.to_string(),
+            None => {
+                // List every alias
+                let aliases = vm.console.config()
+                    .get_aliases()
+                    .into_iter()
+                    .map(|(name, expansion)| format!("{} = {}", name, expansion))
+                    .collect::<Vec<_>>();
+                if aliases.is_empty() {
+                    writeln!(vm.console, "No aliases defined").unwrap();
+                }
+                for alias in aliases {
+                    writeln!(vm.console, "{}", alias).unwrap();
+                }
+                return CommandResult::Sucess;
+            }
+        };
+
+        // Show a single alias
+        if !args.has(1) {
+            let expansion = vm.console.config().get_alias(&name).map(String::from);
+            match expansion {
+                Some(expansion) => writeln!(vm.console, "{} = {}", name, expansion).unwrap(),
+                None => writeln!(vm.console, "No alias named {}", name).unwrap(),
+            }
+            return CommandResult::Sucess;
+        }
+
+        if self.matches_name(name.clone()) || UnaliasCommand.matches_name(name.clone()) {
+            writeln!(vm.console.styled(Style::Error), "{} can't be aliased", name).unwrap();
+            return CommandResult::InvalidArgs;
+        }
+
+        let expansion = args.words(1).join(" ");
+        vm.console.config_mut().set_alias(name.clone(), expansion.clone());
+        if let Err(err) = vm.console.save_config() {
+            writeln!(vm.console.styled(Style::Error), "Unable to save configuration: {:?}", err).unwrap();
+        }
+        writeln!(vm.console, "{} = {}", name, expansion).unwrap();
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["alias"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::optional("name", ArgKind::Alias),
+             ArgSpec::optional("expansion", ArgKind::Command).variadic()]
+    }
+
+    fn get_help(&self) -> &str {
+        "Defines <name> as a shortcut for <expansion>.
+         {1} to {9} in the expansion are replaced with the
+         arguments given to the alias, and {*} with all of
+         them. Aliases are saved in the configuration.
+         With no expansion, shows the alias(es)."
+    }
+
+    fn get_examples(&self) -> Vec<(&str, &str)> {
+        vec![("alias si step", "Makes 'si' run a single step"),
+             ("alias zp memdmp 0", "Makes 'zp' dump the zero page"),
+             ("alias bx \"break {1}; continue\"", "Breaks at an address and continues")]
+    }
+}
+
+struct UnaliasCommand;
+impl Command for UnaliasCommand {
+    fn execute(&self, args: Args, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let name = args.word(0).unwrap();
+        if !vm.console.config_mut().remove_alias(name) {
+            writeln!(vm.console.styled(Style::Error), "No alias named {}", name).unwrap();
+            return CommandResult::InvalidArgs;
+        }
+        if let Err(err) = vm.console.save_config() {
+            writeln!(vm.console.styled(Style::Error), "Unable to save configuration: {:?}", err).unwrap();
+        }
+        writeln!(vm.console, "Removed alias {}", name).unwrap();
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["unalias"]
+    }
+
+    fn get_args(&self) -> Vec<ArgSpec> {
+        vec![ArgSpec::required("name", ArgKind::Alias)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Removes the alias <name>"
+    }
+}
+
+struct ExitCommand;
+impl Command for ExitCommand {
+    fn execute(&self, _args: Args, _system: &CommandSystem, _vm: &mut VirtualMachine) -> CommandResult {
+        std::process::exit(0);
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["exit"]
+    }
+
+    fn get_help(&self) -> &str {
+        "Quits the game"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use rs6502::CodeSegment;
+
+    use vm::VirtualMachine;
+    use super::{expand_placeholders, split_commands, split_words, CommandResult, CommandSystem};
+
+    /// Runs a line of input, returning its result and the lines it wrote
+    fn run(system: &CommandSystem, vm: &mut VirtualMachine, line: &str) -> (CommandResult, Vec<String>) {
+        // The last line is the one being written to, which is empty after a newline
+        let start = vm.console.lines().len().saturating_sub(1);
+        let (result, _) = system.execute(line, vm);
+        let lines = vm.console.lines();
+        (result, lines[start..lines.len() - 1].to_vec())
+    }
+
+    /// Writes a script to a temporary file
+    fn script(name: &str, lines: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("hakka-{}.txt", name));
+        File::create(&path).unwrap().write_all(lines.as_bytes()).unwrap();
+        path
+    }
+
+    #[test]
+    fn prints_an_expression() {
+        let mut vm = VirtualMachine::for_tests();
+        let (result, output) = run(&CommandSystem::new(), &mut vm, "print 2 + 3");
+        assert_eq!(result, CommandResult::Sucess);
+        assert_eq!(output, vec!["$05  #5  %00000101"]);
+    }
+
+    #[test]
+    fn writes_and_dumps_memory() {
+        let system = CommandSystem::new();
+        let mut vm = VirtualMachine::for_tests();
+        let (result, output) = run(&system, &mut vm, "memset $0200 1 2 3");
+        assert_eq!(result, CommandResult::Sucess);
+        assert!(output.is_empty());
+        assert_eq!(&vm.cpu.memory[0x200..0x203], &[1, 2, 3]);
+
+        let (result, output) = run(&system, &mut vm, "memdmp $0200 $0202");
+        assert_eq!(result, CommandResult::Sucess);
+        assert_eq!(output, vec!["01 02 03 ", ""]);
+    }
+
+    #[test]
+    fn refuses_to_write_past_the_end_of_memory() {
+        let system = CommandSystem::new();
+        let mut vm = VirtualMachine::for_tests();
+        let (result, _) = run(&system, &mut vm, "memset $FFFF 1 2");
+        assert_eq!(result, CommandResult::InvalidArgs);
+        assert_eq!(vm.cpu.memory[0xFFFF], 0);
+
+        let (result, _) = run(&system, &mut vm, "memset $FFFF 1");
+        assert_eq!(result, CommandResult::Sucess);
+        assert_eq!(vm.cpu.memory[0xFFFF], 1);
+
+        let (result, _) = run(&system, &mut vm, "memset 0");
+        assert_eq!(result, CommandResult::InvalidArgs);
+    }
+
+    #[test]
+    fn reports_unknown_commands() {
+        let mut vm = VirtualMachine::for_tests();
+        let (result, output) = run(&CommandSystem::new(), &mut vm, "nosuchcommand 1");
+        assert_eq!(result, CommandResult::NotFound);
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn reports_invalid_arguments_with_the_usage() {
+        let mut vm = VirtualMachine::for_tests();
+        let (result, output) = run(&CommandSystem::new(), &mut vm, "memdmp");
+        assert_eq!(result, CommandResult::InvalidArgs);
+        assert!(output.last().unwrap().starts_with("Usage: memdmp"));
+    }
+
+    #[test]
+    fn stops_a_chain_at_the_first_failure() {
+        let mut vm = VirtualMachine::for_tests();
+        let (result, _) = run(&CommandSystem::new(), &mut vm, "memset $10 1; nosuchcommand; memset $11 2");
+        assert_eq!(result, CommandResult::NotFound);
+        assert_eq!(vm.cpu.memory[0x10], 1);
+        assert_eq!(vm.cpu.memory[0x11], 0);
+    }
+
+    #[test]
+    fn filters_output() {
+        let mut vm = VirtualMachine::for_tests();
+        vm.cpu.registers.X = 0x12;
+        let (result, output) = run(&CommandSystem::new(), &mut vm, "registers | grep X:");
+        assert_eq!(result, CommandResult::Sucess);
+        assert_eq!(output, vec!["X: 18 (0012)"]);
+    }
+
+    #[test]
+    fn sets_flags_by_name_or_letter() {
+        let system = CommandSystem::new();
+        let mut vm = VirtualMachine::for_tests();
+        let (result, output) = run(&system, &mut vm, "flags c on");
+        assert_eq!(result, CommandResult::Sucess);
+        assert_eq!(output, vec!["carry: true"]);
+        assert!(vm.cpu.flags.carry);
+
+        run(&system, &mut vm, "flags sign on");
+        assert!(vm.cpu.flags.sign);
+
+        let (result, _) = run(&system, &mut vm, "flags q on");
+        assert_eq!(result, CommandResult::InvalidArgs);
+        assert!(!vm.cpu.flags.zero);
+    }
+
+    #[test]
+    fn reports_bad_config_subcommands() {
+        let system = CommandSystem::new();
+        let mut vm = VirtualMachine::for_tests();
+        let (result, output) = run(&system, &mut vm, "config get");
+        assert_eq!(result, CommandResult::InvalidArgs);
+        assert_eq!(output, vec!["Missing setting"]);
+
+        let (result, output) = run(&system, &mut vm, "config frobnicate clock_rate");
+        assert_eq!(result, CommandResult::InvalidArgs);
+        assert!(output[0].starts_with("Expected list or get or set or reset"));
+    }
+
+    #[test]
+    fn runs_a_script() {
+        let path = script("runs-a-script",
+                          "# Sets up the zero page\n\nmemset $10 1\n   memset $11 2; memset $12 3\nprint \"#1\"\n");
+        let mut vm = VirtualMachine::for_tests();
+        let (result, output) = run(&CommandSystem::new(), &mut vm, &format!("run {}", path.display()));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(result, CommandResult::Sucess);
+        assert_eq!(&vm.cpu.memory[0x10..0x13], &[1, 2, 3]);
+        assert_eq!(output,
+                   vec!["hakka> memset $10 1",
+                        "hakka> memset $11 2; memset $12 3",
+                        "hakka> print \"#1\"",
+                        "$01  #1  %00000001"]);
+    }
+
+    #[test]
+    fn stops_a_script_at_the_first_failure() {
+        let path = script("stops-a-script", "memset $10 1\nnosuchcommand\nmemset $11 2\n");
+        let mut vm = VirtualMachine::for_tests();
+        let (result, output) = run(&CommandSystem::new(), &mut vm, &format!("run {}", path.display()));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(result, CommandResult::NotFound);
+        assert_eq!(vm.cpu.memory[0x10], 1);
+        assert_eq!(vm.cpu.memory[0x11], 0);
+        assert_eq!(output.last().unwrap(), &format!("Script stopped at {}:2", path.display()));
+    }
+
+    #[test]
+    fn stops_scripts_that_run_themselves() {
+        let path = env::temp_dir().join("hakka-runs-itself.txt");
+        let path = script("runs-itself", &format!("memset $10 (mem[$10] + 1)\nrun {}\n", path.display()));
+        let mut vm = VirtualMachine::for_tests();
+        let (result, output) = run(&CommandSystem::new(), &mut vm, &format!("run {}", path.display()));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(result, CommandResult::InvalidArgs);
+        assert_eq!(vm.cpu.memory[0x10] as usize, super::MAX_SCRIPT_DEPTH);
+        assert!(output.contains(&"Scripts are nested too deeply".to_string()));
+    }
+
+    #[test]
+    fn reports_a_missing_script() {
+        let mut vm = VirtualMachine::for_tests();
+        let (result, output) = run(&CommandSystem::new(), &mut vm, "run /nonexistent/hakka-script.txt");
+        assert_eq!(result, CommandResult::InvalidArgs);
+        assert!(output[0].starts_with("Unable to read /nonexistent/hakka-script.txt"));
+    }
+
+    #[test]
+    fn substitutes_placeholders() {
+        assert_eq!(expand_placeholders("memset {1} {2}", &["10", "5"]), "memset 10 5");
+        assert_eq!(expand_placeholders("memset 10 {*}", &["1", "2", "3"]), "memset 10 1 2 3");
+        assert_eq!(expand_placeholders("print {1} + {1}", &["X"]), "print X + X");
+    }
+
+    #[test]
+    fn appends_arguments_without_placeholders() {
+        assert_eq!(expand_placeholders("memdmp", &["0"]), "memdmp 0");
+        assert_eq!(expand_placeholders("memdmp", &[]), "memdmp");
+    }
+
+    #[test]
+    fn empties_placeholders_without_arguments() {
+        assert_eq!(expand_placeholders("memset {1} {2}", &["10"]), "memset 10 ");
+        assert_eq!(expand_placeholders("print {*}", &[]), "print ");
+    }
+
+    #[test]
+    fn leaves_hex_numbers_alone() {
+        assert_eq!(expand_placeholders("memset $10 $1F {1}", &["5"]), "memset $10 $1F 5");
+        assert_eq!(expand_placeholders("memset $8 $1", &["5"]), "memset $8 $1 5");
+        assert_eq!(expand_placeholders("print {10} {x} {", &["5"]), "print {10} {x} { 5");
+    }
+
+    #[test]
+    fn runs_an_alias_with_hex_numbers() {
+        let system = CommandSystem::new();
+        let mut vm = VirtualMachine::for_tests();
+        vm.console.config_mut().set_alias("poke8", "memset $8 {1}");
+        let (result, _) = run(&system, &mut vm, "poke8 $2A");
+        assert_eq!(result, CommandResult::Sucess);
+        assert_eq!(vm.cpu.memory[0x08], 0x2A);
+    }
+
+    #[test]
+    fn expands_aliases_of_aliases() {
+        let system = CommandSystem::new();
+        let mut vm = VirtualMachine::for_tests();
+        vm.console.config_mut().set_alias("zp", "memdmp 0");
+        vm.console.config_mut().set_alias("z", "zp");
+        vm.console.config_mut().set_alias("fill", "memset {1} {*}");
+        assert_eq!(system.expand_aliases("z".into(), &vm), "memdmp 0");
+        assert_eq!(system.expand_aliases("fill 10 1".into(), &vm), "memset 10 10 1");
+        assert_eq!(system.expand_aliases("memdmp 0".into(), &vm), "memdmp 0");
+    }
+
+    #[test]
+    fn stops_expanding_recursive_aliases() {
+        let system = CommandSystem::new();
+        let mut vm = VirtualMachine::for_tests();
+        // An alias named after the command it runs is expanded once
+        vm.console.config_mut().set_alias("memdmp", "memdmp 0");
+        assert_eq!(system.expand_aliases("memdmp".into(), &vm), "memdmp 0");
+
+        // Aliases that refer to each other give up after a while
+        vm.console.config_mut().set_alias("ping", "pong");
+        vm.console.config_mut().set_alias("pong", "ping");
+        let (result, _) = run(&system, &mut vm, "ping");
+        assert_eq!(result, CommandResult::NotFound);
+    }
+
+    #[test]
+    fn runs_an_alias_that_chains_commands() {
+        let system = CommandSystem::new();
+        let mut vm = VirtualMachine::for_tests();
+        vm.console.config_mut().set_alias("pair", "memset $10 {1}; memset $11 {2}");
+        let (result, _) = run(&system, &mut vm, "pair 5 6");
+        assert_eq!(result, CommandResult::Sucess);
+        assert_eq!(&vm.cpu.memory[0x10..0x12], &[5, 6]);
+
+        // A placeholder without an argument leaves the command short of one
+        let (result, output) = run(&system, &mut vm, "pair 7");
+        assert_eq!(result, CommandResult::InvalidArgs);
+        assert_eq!(vm.cpu.memory[0x10], 7);
+        assert_eq!(output[0], "Missing value");
+    }
+
+    #[test]
+    fn splits_commands_at_semicolons() {
+        assert_eq!(split_commands("step; reg ;flags"), vec!["step", "reg", "flags"]);
+        assert_eq!(split_commands(" ; step;;  "), vec!["step"]);
+        assert!(split_commands("").is_empty());
+    }
+
+    #[test]
+    fn keeps_semicolons_inside_quotes() {
+        assert_eq!(split_commands("alias s \"step; reg\"; s"), vec!["alias s \"step; reg\"", "s"]);
+        // An unterminated quote runs to the end of the line
+        assert_eq!(split_commands("alias s \"step; reg"), vec!["alias s \"step; reg"]);
+    }
+
+    #[test]
+    fn splits_words_at_whitespace_outside_quotes_and_parentheses() {
+        assert_eq!(split_words("memset  10\t1"), vec!["memset", "10", "1"]);
+        assert_eq!(split_words("alias s \"step; reg\""), vec!["alias", "s", "step; reg"]);
+        assert_eq!(split_words("grep \"\""), vec!["grep", ""]);
+        assert_eq!(split_words("memset (Y_0 + 1) mem[PC + 1]"), vec!["memset", "(Y_0 + 1)", "mem[PC + 1]"]);
+    }
+
+    #[test]
+    fn repeats_lines_that_start_with_a_step() {
+        let system = CommandSystem::new();
+        let mut vm = VirtualMachine::for_tests();
+        assert!(system.is_repeatable("step", &vm));
+        assert!(system.is_repeatable("step; reg", &vm));
+        assert!(!system.is_repeatable("reg; step", &vm));
+        assert!(!system.is_repeatable("", &vm));
+
+        vm.console.config_mut().set_alias("s", "step");
+        assert!(system.is_repeatable("s", &vm));
+        assert!(system.is_repeatable("next", &vm));
+        assert!(system.is_repeatable("n", &vm));
+    }
+
+    #[test]
+    fn steps_over_a_subroutine() {
+        let system = CommandSystem::new();
+        let mut vm = VirtualMachine::for_tests();
+        vm.set_step_listing(false);
+        // A JSR to a subroutine of a NOP, followed by a NOP
+        vm.load_code_segments(vec![CodeSegment {
+                                       address: 0xC000,
+                                       code: vec![0x20, 0x05, 0xC0, 0xEA, 0xEA, 0xEA, 0x60],
+                                   }]);
+        vm.break_execution();
+
+        run(&system, &mut vm, "next");
+        for _ in 0..3 {
+            vm.cycle();
+        }
+        assert_eq!(vm.cpu.registers.PC, 0xC003);
+        assert!(vm.is_debugging());
+    }
+}
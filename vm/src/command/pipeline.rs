@@ -0,0 +1,416 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+
+const FILTERS: [&'static str; 4] = ["grep", "head", "tail", "more"];
+const DEFAULT_LINE_COUNT: usize = 10;
+const REGISTER_NAMES: [&'static str; 6] = ["A", "X", "Y", "PC", "SP", "S"];
+
+/// A command whose output goes through filters and/or into a file, e.g.
+/// `source | grep STA > stores.txt`
+pub struct Pipeline<'a> {
+    pub command: &'a str,
+    filters: Vec<Filter>,
+    redirect: Option<Redirect>,
+    /// Whether the output stops at each screenful
+    paged: bool,
+}
+
+enum Filter {
+    Grep {
+        pattern: String,
+        invert: bool,
+        ignore_case: bool,
+    },
+    Head(usize),
+    Tail(usize),
+}
+
+struct Redirect {
+    path: String,
+    append: bool,
+}
+
+enum Token {
+    Pipe,
+    Redirect(bool),
+    /// A word, and whether any of it was quoted
+    Word(String, bool),
+}
+
+impl<'a> Pipeline<'a> {
+    /// Splits the filters and redirection off the end of a command. Operators
+    /// inside quotes or parentheses are left alone, so `print (X >> 1)` still
+    /// shifts. A '|' only starts a filter when a filter name follows it. A
+    /// number or register after '>' is refused rather than written to, unless
+    /// it's quoted.
+    pub fn parse(line: &'a str) -> Result<Pipeline<'a>, String> {
+        let start = match find_operator(line) {
+            Some(start) => start,
+            None => {
+                return Ok(Pipeline {
+                    command: line,
+                    filters: Vec::new(),
+                    redirect: None,
+                    paged: false,
+                })
+            }
+        };
+
+        let mut filters = Vec::new();
+        let mut redirect = None;
+        let mut paged = false;
+
+        let mut tokens = tokenize(&line[start..]).into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            match token {
+                Token::Pipe => {
+                    if redirect.is_some() {
+                        return Err("Filters must come before the file being written to".into());
+                    }
+
+                    let mut words = Vec::new();
+                    while let Some(&Token::Word(..)) = tokens.peek() {
+                        if let Some(Token::Word(word, _)) = tokens.next() {
+                            words.push(word);
+                        }
+                    }
+                    // Paging is done by the console, so it isn't a filter
+                    if words.first().map(|name| name == "more").unwrap_or(false) {
+                        if words.len() > 1 {
+                            return Err("Usage: more".into());
+                        }
+                        paged = true;
+                    } else {
+                        filters.push(Filter::parse(&words)?);
+                    }
+                }
+                Token::Redirect(append) => {
+                    let path = match tokens.next() {
+                        Some(Token::Word(path, quoted)) => {
+                            if !quoted && is_expression(&path) {
+                                return Err(format!("{} looks like part of an expression rather than a file name. \
+                                                    Wrap the expression in parentheses, e.g. (X >> 1), or quote \
+                                                    the file name",
+                                                   path));
+                            }
+                            path
+                        }
+                        _ => return Err("Expected a file name after >".into()),
+                    };
+                    if let Some(Token::Word(word, _)) = tokens.next() {
+                        return Err(format!("Unexpected {} after the file name", word));
+                    }
+                    redirect = Some(Redirect {
+                        path: path,
+                        append: append,
+                    });
+                }
+                Token::Word(word, _) => return Err(format!("Unexpected {}", word)),
+            }
+        }
+
+        Ok(Pipeline {
+            command: line[..start].trim(),
+            filters: filters,
+            redirect: redirect,
+            paged: paged,
+        })
+    }
+
+    /// Returns true if the command's output goes straight to the console
+    pub fn is_plain(&self) -> bool {
+        self.filters.is_empty() && self.redirect.is_none()
+    }
+
+    /// Returns true if the output should stop at each screenful
+    pub fn is_paged(&self) -> bool {
+        self.paged && self.redirect.is_none()
+    }
+
+    /// Passes a command's output through the filters, then writes it to the
+    /// redirected file, or to the console if there isn't one
+    pub fn finish<W>(&self, output: &str, console: &mut W) -> Result<(), String>
+        where W: Write
+    {
+        let mut lines = output.lines().map(String::from).collect::<Vec<_>>();
+        for filter in &self.filters {
+            lines = filter.apply(lines);
+        }
+
+        match self.redirect {
+            Some(ref redirect) => {
+                let file = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .append(redirect.append)
+                    .truncate(!redirect.append)
+                    .open(&redirect.path);
+
+                file.and_then(|mut file| {
+                        for line in &lines {
+                            writeln!(file, "{}", line)?;
+                        }
+                        Ok(())
+                    })
+                    .map_err(|err| format!("Unable to write {}: {}", redirect.path, err))
+            }
+            None => {
+                for line in &lines {
+                    writeln!(console, "{}", line).unwrap();
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Filter {
+    fn parse(words: &[String]) -> Result<Filter, String> {
+        let name = match words.first() {
+            Some(name) => &name[..],
+            None => return Err("Expected a filter after |".into()),
+        };
+        let args = &words[1..];
+
+        match name {
+            "grep" => {
+                let mut invert = false;
+                let mut ignore_case = false;
+                let mut pattern = Vec::new();
+                for arg in args {
+                    match &arg[..] {
+                        "-v" if pattern.is_empty() => invert = true,
+                        "-i" if pattern.is_empty() => ignore_case = true,
+                        _ => pattern.push(&arg[..]),
+                    }
+                }
+                if pattern.is_empty() {
+                    return Err("Usage: grep [-v] [-i] text".into());
+                }
+
+                let pattern = pattern.join(" ");
+                Ok(Filter::Grep {
+                    pattern: if ignore_case { pattern.to_lowercase() } else { pattern },
+                    invert: invert,
+                    ignore_case: ignore_case,
+                })
+            }
+            "head" | "tail" => {
+                let count = match args.first() {
+                    Some(count) => {
+                        count.parse::<usize>()
+                            .map_err(|_| format!("Usage: {} [count]", name))?
+                    }
+                    None => DEFAULT_LINE_COUNT,
+                };
+                if name == "head" {
+                    Ok(Filter::Head(count))
+                } else {
+                    Ok(Filter::Tail(count))
+                }
+            }
+            _ => Err(format!("Unknown filter {}, expected one of: {}", name, FILTERS.join(", "))),
+        }
+    }
+
+    fn apply(&self, lines: Vec<String>) -> Vec<String> {
+        match *self {
+            Filter::Grep { ref pattern, invert, ignore_case } => {
+                lines.into_iter()
+                    .filter(|line| {
+                        let found = if ignore_case {
+                            line.to_lowercase().contains(&pattern[..])
+                        } else {
+                            line.contains(&pattern[..])
+                        };
+                        found != invert
+                    })
+                    .collect()
+            }
+            Filter::Head(count) => lines.into_iter().take(count).collect(),
+            Filter::Tail(count) => {
+                let skip = if lines.len() > count { lines.len() - count } else { 0 };
+                lines.into_iter().skip(skip).collect()
+            }
+        }
+    }
+}
+
+/// Finds the first '>' or filter '|' outside of quotes and parentheses
+fn find_operator(line: &str) -> Option<usize> {
+    let mut quoted = false;
+    let mut depth = 0;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' | '[' if !quoted => depth += 1,
+            ')' | ']' if !quoted && depth > 0 => depth -= 1,
+            '>' if !quoted && depth == 0 => return Some(index),
+            '|' if !quoted && depth == 0 => {
+                let rest = line[index + 1..].trim_start();
+                let name = rest.split_whitespace().next().unwrap_or("");
+                if FILTERS.contains(&name) {
+                    return Some(index);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Whether the word after a '>' reads as a number or register, as in
+/// `print A >> 1`, so the '>' was most likely meant as a shift or comparison
+fn is_expression(word: &str) -> bool {
+    let digits = if word.starts_with("0x") || word.starts_with("0X") {
+        &word[2..]
+    } else if word.starts_with(|c| c == '$' || c == '#' || c == '%') {
+        &word[1..]
+    } else if word.chars().all(|c| c.is_digit(10)) {
+        word
+    } else {
+        ""
+    };
+
+    (!digits.is_empty() && digits.chars().all(|c| c.is_digit(16))) ||
+    REGISTER_NAMES.contains(&&word.to_uppercase()[..]) || word.starts_with('(') || word.starts_with('-')
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    let mut word_quoted = false;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let separator = !quoted && (c.is_whitespace() || c == '|' || c == '>');
+        if separator && in_word {
+            tokens.push(Token::Word(word.clone(), word_quoted));
+            word.clear();
+            in_word = false;
+            word_quoted = false;
+        }
+
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+                word_quoted = true;
+            }
+            '|' if !quoted => tokens.push(Token::Pipe),
+            '>' if !quoted => {
+                let append = chars.peek() == Some(&'>');
+                if append {
+                    chars.next();
+                }
+                tokens.push(Token::Redirect(append));
+            }
+            c if c.is_whitespace() && !quoted => (),
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        tokens.push(Token::Word(word, word_quoted));
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Filter, Pipeline};
+
+    #[test]
+    fn leaves_plain_commands_alone() {
+        let pipeline = Pipeline::parse("memdmp 0 $FF").unwrap();
+        assert_eq!(pipeline.command, "memdmp 0 $FF");
+        assert!(pipeline.is_plain());
+        assert!(!pipeline.is_paged());
+    }
+
+    #[test]
+    fn splits_off_filters_and_redirects() {
+        let pipeline = Pipeline::parse("source | grep -i sta | head 5 >> stores.txt").unwrap();
+        assert_eq!(pipeline.command, "source");
+        assert_eq!(pipeline.filters.len(), 2);
+        match pipeline.filters[0] {
+            Filter::Grep { ref pattern, invert: false, ignore_case: true } => assert_eq!(pattern, "sta"),
+            _ => panic!("expected a grep filter"),
+        }
+        match pipeline.filters[1] {
+            Filter::Head(5) => (),
+            _ => panic!("expected a head filter"),
+        }
+        let redirect = pipeline.redirect.unwrap();
+        assert_eq!(redirect.path, "stores.txt");
+        assert!(redirect.append);
+    }
+
+    #[test]
+    fn ignores_operators_in_quotes() {
+        let pipeline = Pipeline::parse("alias x \"list | grep > \"").unwrap();
+        assert!(pipeline.is_plain());
+
+        let pipeline = Pipeline::parse("history | grep \"a | b > c\"").unwrap();
+        assert_eq!(pipeline.command, "history");
+        match pipeline.filters[0] {
+            Filter::Grep { ref pattern, .. } => assert_eq!(pattern, "a | b > c"),
+            _ => panic!("expected a grep filter"),
+        }
+    }
+
+    #[test]
+    fn ignores_operators_in_parentheses() {
+        let pipeline = Pipeline::parse("print (A >> 1) | (X << 2)").unwrap();
+        assert_eq!(pipeline.command, "print (A >> 1) | (X << 2)");
+        assert!(pipeline.is_plain());
+
+        let pipeline = Pipeline::parse("print mem[PC > 1] > out.txt").unwrap();
+        assert_eq!(pipeline.command, "print mem[PC > 1]");
+        assert_eq!(pipeline.redirect.unwrap().path, "out.txt");
+    }
+
+    #[test]
+    fn pages_with_more() {
+        let pipeline = Pipeline::parse("help | more").unwrap();
+        assert_eq!(pipeline.command, "help");
+        assert!(pipeline.is_paged());
+        assert!(pipeline.is_plain());
+
+        assert!(Pipeline::parse("help | more 10").is_err());
+        assert!(!Pipeline::parse("help | more > help.txt").unwrap().is_paged());
+    }
+
+    #[test]
+    fn passes_unknown_filters_through() {
+        let pipeline = Pipeline::parse("print X | unknownfilter").unwrap();
+        assert_eq!(pipeline.command, "print X | unknownfilter");
+        assert!(pipeline.is_plain());
+    }
+
+    #[test]
+    fn refuses_expressions_as_files() {
+        for line in &["print A >> 1", "print A > $10", "print X >> Y", "print A >> 10", "print A > (1)"] {
+            assert!(Pipeline::parse(line).is_err(), "{} should be refused", line);
+        }
+
+        let pipeline = Pipeline::parse("print A >> \"1\"").unwrap();
+        assert_eq!(pipeline.redirect.unwrap().path, "1");
+        let pipeline = Pipeline::parse("memdmp 0 > 1.txt").unwrap();
+        assert_eq!(pipeline.redirect.unwrap().path, "1.txt");
+    }
+
+    #[test]
+    fn refuses_misplaced_operators() {
+        assert!(Pipeline::parse("list > a.txt | grep x").is_err());
+        assert!(Pipeline::parse("list > a.txt b.txt").is_err());
+        assert!(Pipeline::parse("list >").is_err());
+        assert!(Pipeline::parse("list | grep").is_err());
+        assert!(Pipeline::parse("list | head x").is_err());
+    }
+}
@@ -1,25 +1,242 @@
 
 use sdl2::keyboard::Scancode;
-use rustc_serialize::json;
-use std::path::Path;
+use sdl2::pixels::Color;
+use rustc_serialize::Decodable;
+use rustc_serialize::json::{self, Json};
+use app_dirs::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::fs::File;
 use std::io::{Read, Write};
 use std::io;
 
-const DEFAULT_CONSOLE_TOGGLE: Scancode = Scancode::Grave;
+use console::Style;
+
+pub const APP_INFO: AppInfo = AppInfo { name: "hakka", author: "simon-whitehead" };
+pub const CONFIG_FILE: &'static str = "config.json";
+
+/// The version of the configuration schema written by this build. Files
+/// written by older builds are brought up to it by `MIGRATIONS`.
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Upgrades the JSON of a configuration from the version it's indexed by to
+/// the next version
+const MIGRATIONS: [fn(&mut json::Object); 1] = [migrate_console_toggle];
+
+const DEFAULT_WINDOW_SIZE: (u32, u32) = (1280, 720);
+const DEFAULT_CLOCK_RATE: u32 = 150;
+const DEFAULT_HISTORY_SIZE: usize = 500;
+const DEFAULT_SCROLLBACK_LINES: usize = 1000;
+const DEFAULT_CONSOLE_SIZE: u32 = 50;
+const MIN_CONSOLE_SIZE: u32 = 20;
+const DEFAULT_CONSOLE_OPACITY: u8 = 182;
+const DEFAULT_CONSOLE_FONT_SIZE: u16 = 18;
+const MIN_CONSOLE_FONT_SIZE: u16 = 8;
+/// Glyphs any bigger wouldn't fit in the console's glyph atlas
+const MAX_CONSOLE_FONT_SIZE: u16 = 72;
+
+pub const DOCK_NAMES: [&'static str; 4] = ["left", "right", "top", "bottom"];
+
+/// The edge of the window the console is attached to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Dock {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl Dock {
+    pub fn from_name(name: &str) -> Option<Dock> {
+        match name {
+            "left" => Some(Dock::Left),
+            "right" => Some(Dock::Right),
+            "top" => Some(Dock::Top),
+            "bottom" => Some(Dock::Bottom),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Dock::Left => "left",
+            Dock::Right => "right",
+            Dock::Top => "top",
+            Dock::Bottom => "bottom",
+        }
+    }
+}
+
+/// The settings the config command can show and change
+pub const SETTING_NAMES: [&'static str; 24] = ["window_width",
+                                               "window_height",
+                                               "fullscreen",
+                                               "font_path",
+                                               "clock_rate",
+                                               "key.toggle_console",
+                                               "key.up",
+                                               "key.down",
+                                               "key.quit",
+                                               "color.normal",
+                                               "color.error",
+                                               "color.warning",
+                                               "color.highlight",
+                                               "color.dim",
+                                               "color.input",
+                                               "scrollback_lines",
+                                               "history_size",
+                                               "history_per_level",
+                                               "log_sessions",
+                                               "console_dock",
+                                               "console_size",
+                                               "console_opacity",
+                                               "console_font_size",
+                                               "debugger_layout"];
+
+/// Settings that are only read when the game starts
+const STARTUP_SETTINGS: [&'static str; 9] = ["window_width",
+                                             "window_height",
+                                             "fullscreen",
+                                             "font_path",
+                                             "key.up",
+                                             "key.down",
+                                             "key.quit",
+                                             "history_per_level",
+                                             "log_sessions"];
+
+pub const KEY_ACTION_NAMES: [&'static str; 4] = ["toggle_console", "up", "down", "quit"];
+
+/// Something a key can be bound to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyAction {
+    ToggleConsole,
+    Up,
+    Down,
+    Quit,
+}
+
+impl KeyAction {
+    pub fn from_name(name: &str) -> Option<KeyAction> {
+        match name {
+            "toggle_console" => Some(KeyAction::ToggleConsole),
+            "up" => Some(KeyAction::Up),
+            "down" => Some(KeyAction::Down),
+            "quit" => Some(KeyAction::Quit),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            KeyAction::ToggleConsole => "toggle_console",
+            KeyAction::Up => "up",
+            KeyAction::Down => "down",
+            KeyAction::Quit => "quit",
+        }
+    }
+
+    fn default_key(&self) -> Scancode {
+        match *self {
+            KeyAction::ToggleConsole => Scancode::Grave,
+            KeyAction::Up => Scancode::Up,
+            KeyAction::Down => Scancode::Down,
+            KeyAction::Quit => Scancode::Escape,
+        }
+    }
+}
 
 #[derive(RustcDecodable, RustcEncodable, Debug)]
 pub struct Configuration {
-    console_toggle: i32,
+    version: u32,
+    window_width: Option<u32>,
+    window_height: Option<u32>,
+    fullscreen: Option<bool>,
+    /// A TrueType font used in place of the one that comes with the game
+    font_path: Option<String>,
+    clock_rate: Option<u32>,
+    /// Key names, as SDL names them, by the name of the action they're bound to
+    key_bindings: Option<BTreeMap<String, String>>,
+    // Optional so that config files written before aliases existed still load
+    aliases: Option<BTreeMap<String, String>>,
+    log_sessions: Option<bool>,
+    history_size: Option<usize>,
+    history_per_level: Option<bool>,
+    theme: Option<Theme>,
+    scrollback_lines: Option<usize>,
+    console_dock: Option<String>,
+    console_size: Option<u32>,
+    console_opacity: Option<u8>,
+    console_font_size: Option<u16>,
+    debugger_layout: Option<bool>,
+}
+
+/// The console colors, as "#RRGGBB" or "#RRGGBBAA" strings. Any color left
+/// out, or that can't be read, uses the default.
+#[derive(RustcDecodable, RustcEncodable, Debug, Default)]
+pub struct Theme {
+    normal: Option<String>,
+    error: Option<String>,
+    warning: Option<String>,
+    highlight: Option<String>,
+    dim: Option<String>,
+    input: Option<String>,
+}
+
+impl Theme {
+    fn color_mut(&mut self, style: Style) -> &mut Option<String> {
+        match style {
+            Style::Normal => &mut self.normal,
+            Style::Error => &mut self.error,
+            Style::Warning => &mut self.warning,
+            Style::Highlight => &mut self.highlight,
+            Style::Dim => &mut self.dim,
+            Style::Input => &mut self.input,
+        }
+    }
+
+    fn color(&self, style: Style) -> Option<&str> {
+        let color = match style {
+            Style::Normal => &self.normal,
+            Style::Error => &self.error,
+            Style::Warning => &self.warning,
+            Style::Highlight => &self.highlight,
+            Style::Dim => &self.dim,
+            Style::Input => &self.input,
+        };
+        color.as_ref().map(|color| &color[..])
+    }
 }
 
 impl Configuration {
     pub fn default() -> Configuration {
         Configuration {
-            console_toggle: DEFAULT_CONSOLE_TOGGLE as i32
+            version: CONFIG_VERSION,
+            window_width: None,
+            window_height: None,
+            fullscreen: None,
+            font_path: None,
+            clock_rate: None,
+            key_bindings: None,
+            aliases: None,
+            log_sessions: None,
+            history_size: None,
+            history_per_level: None,
+            theme: None,
+            scrollback_lines: None,
+            console_dock: None,
+            console_size: None,
+            console_opacity: None,
+            console_font_size: None,
+            debugger_layout: None,
         }
     }
 
+    /// Stores the configuration in the user's config directory
+    pub fn save(&self) -> Result<(), ConfigError> {
+        self.store(&user_config_path(CONFIG_FILE))
+    }
+
     pub fn store(&self, target: &Path) -> Result<(), ConfigError> {
         let encoded = json::as_pretty_json(&self);
         let mut file = File::create(target)?;
@@ -28,24 +245,528 @@ impl Configuration {
         Ok(())
     }
 
+    /// Loads a configuration, upgrading it first if it was written by an
+    /// older version of the game
     pub fn load(target: &Path) -> Result<Configuration, ConfigError> {
+        Configuration::read(target).map(|(config, _)| config)
+    }
+
+    /// Loads a configuration, and returns whether it had to be upgraded
+    fn read(target: &Path) -> Result<(Configuration, bool), ConfigError> {
         let mut file = File::open(target)?;
         let mut buffer = String::new();
         file.read_to_string(&mut buffer)?;
-        let config = json::decode(&buffer)?;
-        Ok(config)
+
+        let mut config = Json::from_str(&buffer).map_err(json::DecoderError::ParseError)?;
+        let upgraded = match config {
+            Json::Object(ref mut object) => migrate(object)?,
+            _ => false,
+        };
+        let config = Configuration::decode(&mut json::Decoder::new(config))?;
+        Ok((config, upgraded))
+    }
+
+    /// Loads the configuration from the user's config directory, creating
+    /// it with defaults if it doesn't exist
+    pub fn load_or_default() -> Result<Configuration, ConfigError> {
+        Configuration::load_or_default_at(&user_config_path(CONFIG_FILE))
+    }
+
+    /// Loads a configuration file, creating it with defaults if it doesn't
+    /// exist. A configuration from an older version is upgraded and saved.
+    /// One that can't be read at all is moved aside, with .bak added to its
+    /// name, and replaced with the defaults. One from a newer version, or
+    /// that can't be opened, is left alone and the error returned.
+    pub fn load_or_default_at(config_file: &Path) -> Result<Configuration, ConfigError> {
+        if !config_file.exists() {
+            let default_config = Configuration::default();
+            default_config.store(config_file)?;
+        }
+
+        match Configuration::read(config_file) {
+            Err(ConfigError::Deserialization(err)) => {
+                // Something happend during deserialization, indicating that the file has invalid content
+                let mut backup = config_file.as_os_str().to_owned();
+                backup.push(".bak");
+                let backup = PathBuf::from(backup);
+                println!("{} could not be deserialized. Moving it to {} and replacing it with the \
+                          default ({:?})",
+                         config_file.display(),
+                         backup.display(),
+                         err);
+                ::std::fs::rename(config_file, &backup).unwrap_or(());
+                let default_config = Configuration::default();
+                default_config.store(config_file)?;
+                Ok(default_config)
+            }
+            Err(err) => Err(err),
+            Ok((config, upgraded)) => {
+                if upgraded {
+                    if let Err(err) = config.store(config_file) {
+                        println!("Unable to save the upgraded {} ({:?})", config_file.display(), err);
+                    }
+                }
+                Ok(config)
+            }
+        }
+    }
+
+    /// The schema version the configuration was written in
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The size of the game window when it isn't fullscreen
+    pub fn get_window_size(&self) -> (u32, u32) {
+        (self.window_width.unwrap_or(DEFAULT_WINDOW_SIZE.0), self.window_height.unwrap_or(DEFAULT_WINDOW_SIZE.1))
+    }
+
+    pub fn set_window_size(&mut self, (width, height): (u32, u32)) {
+        self.window_width = Some(width);
+        self.window_height = Some(height);
     }
 
-    pub fn get_scancode(&self) -> Scancode {
-        Scancode::from_i32(self.console_toggle).unwrap_or(DEFAULT_CONSOLE_TOGGLE)
+    pub fn get_fullscreen(&self) -> bool {
+        self.fullscreen.unwrap_or(false)
+    }
+
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        self.fullscreen = Some(fullscreen);
+    }
+
+    /// The font to draw text in, if it isn't the one that comes with the game
+    pub fn get_font_path(&self) -> Option<&Path> {
+        self.font_path.as_ref().map(Path::new)
+    }
+
+    pub fn set_font_path<S>(&mut self, font_path: Option<S>)
+        where S: Into<String>
+    {
+        self.font_path = font_path.map(Into::into);
+    }
+
+    /// How many cycles the CPU runs each frame
+    pub fn get_clock_rate(&self) -> u32 {
+        self.clock_rate.unwrap_or(DEFAULT_CLOCK_RATE)
+    }
+
+    pub fn set_clock_rate(&mut self, clock_rate: u32) {
+        self.clock_rate = Some(clock_rate);
+    }
+
+    /// The key bound to an action. A binding to a key SDL doesn't know falls
+    /// back to the default.
+    pub fn get_key(&self, action: KeyAction) -> Scancode {
+        self.key_bindings
+            .as_ref()
+            .and_then(|bindings| bindings.get(action.name()))
+            .and_then(|name| Scancode::from_name(name))
+            .unwrap_or_else(|| action.default_key())
+    }
+
+    pub fn set_key(&mut self, action: KeyAction, key: Scancode) {
+        self.key_bindings.get_or_insert(BTreeMap::new()).insert(action.name().into(), key.name().into());
+    }
+
+    /// Whether every session is logged to a file in the user's data directory
+    pub fn get_log_sessions(&self) -> bool {
+        self.log_sessions.unwrap_or(false)
+    }
+
+    pub fn set_log_sessions(&mut self, log_sessions: bool) {
+        self.log_sessions = Some(log_sessions);
+    }
+
+    /// The most commands kept in the history file
+    pub fn get_history_size(&self) -> usize {
+        self.history_size.unwrap_or(DEFAULT_HISTORY_SIZE)
+    }
+
+    pub fn set_history_size(&mut self, history_size: usize) {
+        self.history_size = Some(history_size);
+    }
+
+    /// Whether each level keeps a command history of its own
+    pub fn get_history_per_level(&self) -> bool {
+        self.history_per_level.unwrap_or(false)
+    }
+
+    pub fn set_history_per_level(&mut self, history_per_level: bool) {
+        self.history_per_level = Some(history_per_level);
+    }
+
+    /// The most lines of output the console keeps
+    pub fn get_scrollback_lines(&self) -> usize {
+        self.scrollback_lines.unwrap_or(DEFAULT_SCROLLBACK_LINES)
+    }
+
+    pub fn set_scrollback_lines(&mut self, scrollback_lines: usize) {
+        self.scrollback_lines = Some(scrollback_lines);
+    }
+
+    /// The edge of the window the console is docked to
+    pub fn get_console_dock(&self) -> Dock {
+        self.console_dock.as_ref().and_then(|dock| Dock::from_name(dock)).unwrap_or(Dock::Left)
+    }
+
+    pub fn set_console_dock(&mut self, dock: Dock) {
+        self.console_dock = Some(dock.name().into());
+    }
+
+    /// How much of the window the console covers, as a percentage of its
+    /// width when docked left or right, or of its height otherwise
+    pub fn get_console_size(&self) -> u32 {
+        let size = self.console_size.unwrap_or(DEFAULT_CONSOLE_SIZE);
+        ::std::cmp::min(::std::cmp::max(size, MIN_CONSOLE_SIZE), 100)
+    }
+
+    pub fn set_console_size(&mut self, console_size: u32) {
+        self.console_size = Some(::std::cmp::min(::std::cmp::max(console_size, MIN_CONSOLE_SIZE), 100));
+    }
+
+    /// How opaque the console background is, from 0 to 255
+    pub fn get_console_opacity(&self) -> u8 {
+        self.console_opacity.unwrap_or(DEFAULT_CONSOLE_OPACITY)
+    }
+
+    pub fn set_console_opacity(&mut self, console_opacity: u8) {
+        self.console_opacity = Some(console_opacity);
+    }
+
+    pub fn get_console_font_size(&self) -> u16 {
+        let size = self.console_font_size.unwrap_or(DEFAULT_CONSOLE_FONT_SIZE);
+        ::std::cmp::min(::std::cmp::max(size, MIN_CONSOLE_FONT_SIZE), MAX_CONSOLE_FONT_SIZE)
+    }
+
+    pub fn set_console_font_size(&mut self, console_font_size: u16) {
+        self.console_font_size = Some(::std::cmp::min(::std::cmp::max(console_font_size, MIN_CONSOLE_FONT_SIZE),
+                                                      MAX_CONSOLE_FONT_SIZE));
+    }
+
+    /// Whether the registers, disassembly and memory are shown in panes
+    /// beside the console while execution is stopped
+    pub fn get_debugger_layout(&self) -> bool {
+        self.debugger_layout.unwrap_or(false)
+    }
+
+    pub fn set_debugger_layout(&mut self, debugger_layout: bool) {
+        self.debugger_layout = Some(debugger_layout);
+    }
+
+    /// The color console output in the given style is drawn in
+    pub fn get_color(&self, style: Style) -> Color {
+        self.theme
+            .as_ref()
+            .and_then(|theme| theme.color(style))
+            .and_then(parse_color)
+            .unwrap_or_else(|| default_color(style))
+    }
+
+    /// Sets the color of a style from a "#RRGGBB" or "#RRGGBBAA" string
+    pub fn set_color(&mut self, style: Style, color: &str) -> Result<(), String> {
+        if parse_color(color).is_none() {
+            return Err(format!("Expected a color like #2DC82D, found {}", color));
+        }
+        *self.theme.get_or_insert(Theme::default()).color_mut(style) = Some(color.to_uppercase());
+        Ok(())
+    }
+
+    /// Returns a setting from `SETTING_NAMES` as text, as the config command
+    /// shows it
+    pub fn get_setting(&self, name: &str) -> Option<String> {
+        if let Some(action) = setting_key(name) {
+            return Some(self.get_key(action).name().into());
+        }
+        if let Some(style) = setting_style(name) {
+            return Some(color_name(self.get_color(style)));
+        }
+
+        let value = match name {
+            "window_width" => self.get_window_size().0.to_string(),
+            "window_height" => self.get_window_size().1.to_string(),
+            "fullscreen" => switch_name(self.get_fullscreen()),
+            "font_path" => {
+                match self.get_font_path() {
+                    Some(path) => path.display().to_string(),
+                    None => "(the bundled font)".into(),
+                }
+            }
+            "clock_rate" => self.get_clock_rate().to_string(),
+            "scrollback_lines" => self.get_scrollback_lines().to_string(),
+            "history_size" => self.get_history_size().to_string(),
+            "history_per_level" => switch_name(self.get_history_per_level()),
+            "log_sessions" => switch_name(self.get_log_sessions()),
+            "console_dock" => self.get_console_dock().name().into(),
+            "console_size" => self.get_console_size().to_string(),
+            "console_opacity" => self.get_console_opacity().to_string(),
+            "console_font_size" => self.get_console_font_size().to_string(),
+            "debugger_layout" => switch_name(self.get_debugger_layout()),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Changes a setting from `SETTING_NAMES`, reading the value from text
+    pub fn set_setting(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if let Some(action) = setting_key(name) {
+            let key = Scancode::from_name(value).ok_or_else(|| format!("Unknown key {}", value))?;
+            self.set_key(action, key);
+            return Ok(());
+        }
+        if let Some(style) = setting_style(name) {
+            return self.set_color(style, value);
+        }
+
+        match name {
+            "window_width" => {
+                let width = parse_setting(value)?;
+                let height = self.get_window_size().1;
+                self.set_window_size((width, height));
+            }
+            "window_height" => {
+                let height = parse_setting(value)?;
+                let width = self.get_window_size().0;
+                self.set_window_size((width, height));
+            }
+            "fullscreen" => self.set_fullscreen(parse_switch(value)?),
+            "font_path" => {
+                if !Path::new(value).is_file() {
+                    return Err(format!("No font file at {}", value));
+                }
+                self.set_font_path(Some(value));
+            }
+            "clock_rate" => self.set_clock_rate(parse_setting(value)?),
+            "scrollback_lines" => self.set_scrollback_lines(parse_setting(value)?),
+            "history_size" => self.set_history_size(parse_setting(value)?),
+            "history_per_level" => self.set_history_per_level(parse_switch(value)?),
+            "log_sessions" => self.set_log_sessions(parse_switch(value)?),
+            "console_dock" => {
+                let dock = Dock::from_name(value)
+                    .ok_or_else(|| format!("Expected {}, found {}", DOCK_NAMES.join(" or "), value))?;
+                self.set_console_dock(dock);
+            }
+            "console_size" => self.set_console_size(parse_setting(value)?),
+            "console_opacity" => self.set_console_opacity(parse_setting(value)?),
+            "console_font_size" => self.set_console_font_size(parse_setting(value)?),
+            "debugger_layout" => self.set_debugger_layout(parse_switch(value)?),
+            _ => return Err(format!("Unknown setting {}", name)),
+        }
+        Ok(())
+    }
+
+    /// Puts a setting from `SETTING_NAMES` back to its default
+    pub fn reset_setting(&mut self, name: &str) {
+        if let Some(action) = setting_key(name) {
+            if let Some(ref mut bindings) = self.key_bindings {
+                bindings.remove(action.name());
+            }
+            return;
+        }
+        if let Some(style) = setting_style(name) {
+            if let Some(ref mut theme) = self.theme {
+                *theme.color_mut(style) = None;
+            }
+            return;
+        }
+
+        match name {
+            "window_width" => self.window_width = None,
+            "window_height" => self.window_height = None,
+            "fullscreen" => self.fullscreen = None,
+            "font_path" => self.font_path = None,
+            "clock_rate" => self.clock_rate = None,
+            "scrollback_lines" => self.scrollback_lines = None,
+            "history_size" => self.history_size = None,
+            "history_per_level" => self.history_per_level = None,
+            "log_sessions" => self.log_sessions = None,
+            "console_dock" => self.console_dock = None,
+            "console_size" => self.console_size = None,
+            "console_opacity" => self.console_opacity = None,
+            "console_font_size" => self.console_font_size = None,
+            "debugger_layout" => self.debugger_layout = None,
+            _ => (),
+        }
+    }
+
+    /// Returns all aliases and their expansions, sorted by name
+    pub fn get_aliases(&self) -> Vec<(&str, &str)> {
+        match self.aliases {
+            Some(ref aliases) => aliases.iter().map(|(name, expansion)| (&name[..], &expansion[..])).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn get_alias(&self, name: &str) -> Option<&str> {
+        self.aliases.as_ref().and_then(|aliases| aliases.get(name)).map(|expansion| &expansion[..])
+    }
+
+    pub fn set_alias<S>(&mut self, name: S, expansion: S)
+        where S: Into<String>
+    {
+        self.aliases.get_or_insert(BTreeMap::new()).insert(name.into(), expansion.into());
+    }
+
+    pub fn remove_alias(&mut self, name: &str) -> bool {
+        match self.aliases {
+            Some(ref mut aliases) => aliases.remove(name).is_some(),
+            None => false,
+        }
+    }
+}
+
+/// Returns true if a setting only takes effect when the game is next started
+pub fn is_startup_setting(name: &str) -> bool {
+    STARTUP_SETTINGS.contains(&name)
+}
+
+/// The action a "key.<action>" setting binds
+fn setting_key(name: &str) -> Option<KeyAction> {
+    if name.starts_with("key.") {
+        KeyAction::from_name(&name[4..])
+    } else {
+        None
+    }
+}
+
+/// The style a "color.<style>" setting is for
+fn setting_style(name: &str) -> Option<Style> {
+    match name {
+        "color.normal" => Some(Style::Normal),
+        "color.error" => Some(Style::Error),
+        "color.warning" => Some(Style::Warning),
+        "color.highlight" => Some(Style::Highlight),
+        "color.dim" => Some(Style::Dim),
+        "color.input" => Some(Style::Input),
+        _ => None,
+    }
+}
+
+fn parse_setting<T>(value: &str) -> Result<T, String>
+    where T: ::std::str::FromStr
+{
+    value.parse::<T>().map_err(|_| format!("Expected a number, found {}", value))
+}
+
+fn parse_switch(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "on" => Ok(true),
+        "false" | "off" => Ok(false),
+        _ => Err(format!("Expected on or off, found {}", value)),
     }
 }
 
+fn switch_name(value: bool) -> String {
+    if value { "on".into() } else { "off".into() }
+}
+
+/// Writes a color as "#RRGGBB", or "#RRGGBBAA" if it isn't opaque
+fn color_name(color: Color) -> String {
+    let (r, g, b, a) = color.rgba();
+    if a == 255 {
+        format!("#{:02X}{:02X}{:02X}", r, g, b)
+    } else {
+        format!("#{:02X}{:02X}{:02X}{:02X}", r, g, b, a)
+    }
+}
+
+/// Brings the JSON of a configuration up to the current version, returning
+/// true if anything had to change. A configuration from a newer version of
+/// the game is refused, as this one can't know what has changed since.
+fn migrate(config: &mut json::Object) -> Result<bool, ConfigError> {
+    // Files written before the version was added are version 0
+    let version = config.get("version").and_then(Json::as_u64).unwrap_or(0);
+    if version > CONFIG_VERSION as u64 {
+        return Err(ConfigError::UnsupportedVersion(version));
+    }
+    if version == CONFIG_VERSION as u64 {
+        return Ok(false);
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(config);
+    }
+    config.insert("version".into(), Json::U64(CONFIG_VERSION as u64));
+    Ok(true)
+}
+
+/// Version 1 moved the console toggle, stored as an SDL scancode number,
+/// into the key bindings by name
+fn migrate_console_toggle(config: &mut json::Object) {
+    let toggle = config.remove("console_toggle")
+        .and_then(|toggle| toggle.as_i64())
+        .and_then(|toggle| Scancode::from_i32(toggle as i32));
+
+    if let Some(toggle) = toggle {
+        let bindings = config.entry("key_bindings".into()).or_insert_with(|| Json::Object(json::Object::new()));
+        if let Json::Object(ref mut bindings) = *bindings {
+            bindings.insert(KeyAction::ToggleConsole.name().into(),
+                            Json::String(toggle.name().into()));
+        }
+    }
+}
+
+fn default_color(style: Style) -> Color {
+    match style {
+        Style::Normal => Color::RGBA(45, 200, 45, 255),
+        Style::Error => Color::RGBA(230, 70, 70, 255),
+        Style::Warning => Color::RGBA(230, 200, 50, 255),
+        Style::Highlight => Color::RGBA(180, 255, 180, 255),
+        Style::Dim => Color::RGBA(30, 120, 30, 255),
+        Style::Input => Color::RGBA(150, 220, 150, 255),
+    }
+}
+
+/// Reads a "#RRGGBB" or "#RRGGBBAA" color
+fn parse_color(text: &str) -> Option<Color> {
+    let hex = if text.starts_with('#') { &text[1..] } else { return None };
+    if (hex.len() != 6 && hex.len() != 8) || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut channels = Vec::new();
+    for index in 0..hex.len() / 2 {
+        match u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16) {
+            Ok(channel) => channels.push(channel),
+            Err(_) => return None,
+        }
+    }
+    let alpha = channels.get(3).cloned().unwrap_or(255);
+
+    Some(Color::RGBA(channels[0], channels[1], channels[2], alpha))
+}
+
+/// Returns the path of a file in the user's hakka config directory
+pub fn user_config_path<P>(file: P) -> PathBuf
+    where P: AsRef<Path>
+{
+    let mut path = app_root(AppDataType::UserConfig, &APP_INFO).unwrap();
+    path.push(file);
+    path
+}
+
 #[derive(Debug)]
 pub enum ConfigError {
     File(io::Error),
     Serialization(json::EncoderError),
     Deserialization(json::DecoderError),
+    /// The configuration was written by a newer version of the game
+    UnsupportedVersion(u64),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::File(ref err) => write!(f, "{}", err),
+            ConfigError::Serialization(ref err) => write!(f, "{}", err),
+            ConfigError::Deserialization(ref err) => write!(f, "{}", err),
+            ConfigError::UnsupportedVersion(version) => {
+                write!(f,
+                       "version {} was written by a newer version of hakka, which reads up to version {}",
+                       version,
+                       CONFIG_VERSION)
+            }
+        }
+    }
 }
 
 impl From<io::Error> for ConfigError {
@@ -64,4 +785,154 @@ impl From<json::DecoderError> for ConfigError {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+
+    use rustc_serialize::json::{self, Json};
+
+    use super::{migrate, ConfigError, Configuration, CONFIG_VERSION, SETTING_NAMES};
+
+    fn object(text: &str) -> json::Object {
+        match Json::from_str(text).unwrap() {
+            Json::Object(object) => object,
+            _ => panic!("expected an object"),
+        }
+    }
+
+    #[test]
+    fn migrates_a_version_0_file() {
+        let mut config = object(r#"{ "console_toggle": 53, "fullscreen": true }"#);
+        assert!(migrate(&mut config).unwrap());
+
+        assert_eq!(config.get("version"), Some(&Json::U64(CONFIG_VERSION as u64)));
+        assert!(config.get("console_toggle").is_none());
+        assert_eq!(config.get("fullscreen"), Some(&Json::Boolean(true)));
+        let bindings = config.get("key_bindings").and_then(Json::as_object).unwrap();
+        assert_eq!(bindings.get("toggle_console"), Some(&Json::String("`".into())));
+    }
+
+    #[test]
+    fn leaves_a_current_file_unchanged() {
+        let text = format!(r#"{{ "version": {}, "key_bindings": {{ "up": "W" }} }}"#, CONFIG_VERSION);
+        let mut config = object(&text);
+        assert!(!migrate(&mut config).unwrap());
+        assert_eq!(config, object(&text));
+    }
+
+    #[test]
+    fn refuses_a_newer_version() {
+        let text = format!(r#"{{ "version": {}, "new_setting": 1 }}"#, CONFIG_VERSION + 1);
+        let mut config = object(&text);
+        match migrate(&mut config) {
+            Err(ConfigError::UnsupportedVersion(version)) => assert_eq!(version, CONFIG_VERSION as u64 + 1),
+            _ => panic!("expected a newer version to be refused"),
+        }
+        assert_eq!(config, object(&text));
+    }
+
+    #[test]
+    fn leaves_a_newer_file_alone() {
+        let path = env::temp_dir().join("hakka-config-newer-version.json");
+        let text = format!(r#"{{ "version": {} }}"#, CONFIG_VERSION + 1);
+        File::create(&path).unwrap().write_all(text.as_bytes()).unwrap();
+
+        assert!(Configuration::load_or_default_at(&path).is_err());
 
+        let mut contents = String::new();
+        File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, text);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reports_a_file_that_cant_be_created() {
+        let path = env::temp_dir().join("hakka-no-such-directory").join("config.json");
+        match Configuration::load_or_default_at(&path) {
+            Err(ConfigError::File(_)) => (),
+            _ => panic!("expected a file error"),
+        }
+    }
+
+    #[test]
+    fn clamps_the_console_font_size() {
+        let mut config = Configuration::default();
+        config.set_setting("console_font_size", "1000").unwrap();
+        assert_eq!(config.get_console_font_size(), 72);
+        config.set_setting("console_font_size", "1").unwrap();
+        assert_eq!(config.get_console_font_size(), 8);
+
+        // A size edited into the file by hand is clamped too
+        config.console_font_size = Some(500);
+        assert_eq!(config.get_console_font_size(), 72);
+    }
+
+    /// A value for each setting other than its default, as `get_setting`
+    /// shows it, and a value it refuses
+    fn examples(font: &str) -> Vec<(&'static str, String, &'static str)> {
+        vec![("window_width", "1024".into(), "wide"),
+             ("window_height", "768".into(), "-1"),
+             ("fullscreen", "on".into(), "yes"),
+             ("font_path", font.into(), "no-such-font.ttf"),
+             ("clock_rate", "2000".into(), "fast"),
+             ("key.toggle_console", "F1".into(), "NoSuchKey"),
+             ("key.up", "F1".into(), ""),
+             ("key.down", "F1".into(), "NoSuchKey"),
+             ("key.quit", "F1".into(), "NoSuchKey"),
+             ("color.normal", "#102030".into(), "green"),
+             ("color.error", "#10203040".into(), "#12345"),
+             ("color.warning", "#102030".into(), "#GG0000"),
+             ("color.highlight", "#102030".into(), "102030"),
+             ("color.dim", "#102030".into(), "#1020304050"),
+             ("color.input", "#102030".into(), "#"),
+             ("scrollback_lines", "500".into(), "lots"),
+             ("history_size", "50".into(), "1.5"),
+             ("history_per_level", "on".into(), "1"),
+             ("log_sessions", "on".into(), "always"),
+             ("console_dock", "top".into(), "middle"),
+             ("console_size", "30".into(), "half"),
+             ("console_opacity", "128".into(), "256"),
+             ("console_font_size", "24".into(), "big"),
+             ("debugger_layout", "on".into(), "maybe")]
+    }
+
+    #[test]
+    fn changes_and_resets_every_setting() {
+        let font = env::temp_dir().join("hakka-setting-font.ttf");
+        File::create(&font).unwrap();
+        let examples = examples(font.to_str().unwrap());
+        assert_eq!(examples.iter().map(|&(name, _, _)| name).collect::<Vec<_>>(), SETTING_NAMES.to_vec());
+
+        for (name, value, _) in examples {
+            let mut config = Configuration::default();
+            let default = config.get_setting(name).unwrap();
+            assert!(default != value, "{} already defaults to {}", name, value);
+
+            config.set_setting(name, &value).unwrap();
+            assert_eq!(config.get_setting(name), Some(value), "{}", name);
+            config.reset_setting(name);
+            assert_eq!(config.get_setting(name), Some(default), "{}", name);
+        }
+        fs::remove_file(&font).unwrap();
+    }
+
+    #[test]
+    fn refuses_bad_setting_values() {
+        for (name, _, bad) in examples("") {
+            let mut config = Configuration::default();
+            let default = config.get_setting(name);
+            assert!(config.set_setting(name, bad).is_err(), "{} accepted {}", name, bad);
+            assert_eq!(config.get_setting(name), default, "{}", name);
+        }
+    }
+
+    #[test]
+    fn refuses_unknown_settings() {
+        let mut config = Configuration::default();
+        assert_eq!(config.set_setting("volume", "11"), Err("Unknown setting volume".into()));
+        assert_eq!(config.get_setting("volume"), None);
+        config.reset_setting("volume");
+    }
+}
@@ -1,150 +1,311 @@
 
 use std;
-use std::path::Path;
-use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::io::{Read, Write};
 
-use sdl2::event::Event;
+use sdl2::clipboard::ClipboardUtil;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::*;
-use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
 use sdl2::rect::{Point, Rect};
-use sdl2::render::{BlendMode, Renderer, Texture, TextureQuery};
-use sdl2::surface::Surface;
-use sdl2::ttf::{Font, Sdl2TtfContext, STYLE_BOLD};
+use sdl2::render::{BlendMode, Renderer};
+use sdl2::ttf::Sdl2TtfContext;
 
-use app_dirs::*;
-
-use position::Position;
-use text::Text;
-use config::{Configuration, ConfigError};
-
-const APP_INFO: AppInfo = AppInfo { name: "hakka", author: "simon-whitehead" };
-const CONFIG_FILE: &'static str = "config.json";
+use atlas::GlyphAtlas;
+use config::{self, ConfigError, Configuration, Dock, KeyAction};
+use hexedit::HexEditor;
+use transcript::Transcript;
 
 const BORDER_COLOR: Color = Color::RGBA(255, 255, 255, 64);
 
+const HISTORY_FILE: &'static str = "history";
+
 const PADDING: i32 = 10;
 
-const FONT_COLOR: Color = Color::RGBA(45, 200, 45, 255);
+const SELECTION_COLOR: Color = Color::RGBA(255, 255, 255, 64);
+const HIGHLIGHT_BACKGROUND: Color = Color::RGBA(255, 255, 255, 40);
+/// The font size until the renderer reads the configured one
 const FONT_SIZE: u16 = 18;
 
-pub struct Console<'a> {
+/// How close to the console's inner edge a click starts resizing it
+const RESIZE_MARGIN: i32 = 4;
+
+const SCROLLBAR_COLOR: Color = Color::RGBA(255, 255, 255, 96);
+const SCROLLBAR_WIDTH: u32 = 4;
+/// Shown at the start of the rows a long line is wrapped onto
+const WRAP_MARKER: &'static str = "\u{bb} ";
+
+/// How a span of console output is drawn. The colors come from the theme
+/// in the configuration.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Style {
+    Normal,
+    Error,
+    /// Something that needs attention, e.g. a breakpoint being hit
+    Warning,
+    /// The current line, e.g. the program counter in a disassembly
+    Highlight,
+    /// Secondary detail, e.g. the addresses in a memory dump
+    Dim,
+    /// Commands echoed back after they've been entered
+    Input,
+}
+
+/// Writes to the console in a style, going back to the previous style when
+/// dropped, e.g. `writeln!(console.styled(Style::Error), "Oops")`
+pub struct Styled<'a> {
+    console: &'a mut Console,
+    previous: Style,
+}
+
+impl<'a> Write for Styled<'a> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.console.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.console.flush()
+    }
+}
+
+impl<'a> Drop for Styled<'a> {
+    fn drop(&mut self) {
+        self.console.style = self.previous;
+    }
+}
+
+/// A Ctrl+R reverse search through the command history
+struct HistorySearch {
+    query: String,
+    /// The index in the history of the current match
+    position: usize,
+    /// The input from before the search, restored if it's cancelled
+    original: String,
+}
+
+/// Holds long output back a screenful at a time, like `more`
+struct Pager {
+    /// The buffer line the paged output starts at
+    start: usize,
+    /// How many screen rows of the output have been paged past. Long lines
+    /// wrap onto several rows, which only the renderer knows about.
+    rows: usize,
+}
+
+/// A click-drag selection over the scrollback, in pixels. The y coordinates
+/// leave out the scroll offset, so the selection stays over the same text
+/// while scrolling.
+#[derive(Clone, Copy)]
+struct Selection {
+    start: (i32, i32),
+    end: (i32, i32),
+}
+
+/// The state of the in-game console: its output buffer, the input line and
+/// the command history. It holds no SDL resources, so it can be driven
+/// headless; `ConsoleRenderer` draws it into an SDL window.
+pub struct Console {
     pub visible: bool,
     pub input_blocked: bool,
     visible_start_time: u32, /* Used to ensure that the KeyDown event that opens the console does not trigger text input */
 
     config: Configuration,
+    /// Where the configuration is saved, if anywhere
+    config_file: Option<PathBuf>,
 
-    font_file: &'a str,
-    leader: Text,
     input_buffer: String,
-    last_command: String,
+    /// The next command to execute. An empty command asks to repeat the last one.
+    last_command: Option<String>,
     command_history: Vec<String>,
+    /// How many of the oldest commands have been dropped from the history
+    /// this session, so the rest keep their numbers
+    history_dropped: usize,
+    /// Where the command history is saved, if anywhere
+    history_file: Option<PathBuf>,
     history_position: usize,
     cursor_position: usize,
+    completion_requested: bool,
+    /// The text removed by the last Ctrl+U, Ctrl+K or Ctrl+W, for Ctrl+Y
+    kill_buffer: String,
+    overwrite: bool,
+    search: Option<HistorySearch>,
+    selection: Option<Selection>,
+    selecting: bool,
+    /// Clipboard requests, carried out by the renderer which owns the SDL
+    /// video subsystem
+    copy_requested: bool,
+    paste_requested: bool,
     buffer: Vec<String>,
+    /// The styles of each line of the buffer, as the byte offset each style
+    /// starts at
+    styles: Vec<Vec<(usize, Style)>>,
+    /// The style new output is written in
+    style: Style,
+    /// Output being collected rather than shown, innermost last
+    captures: Vec<String>,
+    transcript: Option<Transcript>,
+    /// Changes whenever the buffer does, so the renderer knows when to wrap
+    /// the lines again
+    revision: u64,
+    pager: Option<Pager>,
+    /// How many lines fit on screen, known once the console has been rendered
+    page_lines: Option<usize>,
+    /// How far the buffer is scrolled up from the bottom, in pixels
     backbuffer_y: i32,
-    texture: Texture,
-    ttf_context: &'a Sdl2TtfContext,
-    size: (u32, u32),
-    font: Font<'a, 'a>,
+    /// The size of the window the console is drawn in, once it's known
+    window_size: Option<(u32, u32)>,
+    /// Whether the console's edge is being dragged
+    resizing: bool,
+    /// The part of the window the console is drawn in when another layout,
+    /// rather than the dock, places it
+    pane: Option<Rect>,
+    /// Takes over the console while memory is being edited
+    hex_editor: Option<HexEditor>,
 }
 
-impl<'a> Console<'a> {
-    /// Creates a new empty Console
-    pub fn new(ttf_context: &'a Sdl2TtfContext,
-               mut renderer: &mut Renderer,
-               font_file: &'a str)
-               -> Console<'a> {
-
-        let (width, height) = renderer.window().unwrap().size();
-        let mut texture =
-            renderer.create_texture_streaming(PixelFormatEnum::RGBA8888, width / 2, height)
-                .unwrap();
-        texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
-                for y in 0..height {
-                    for x in 0..width / 2 {
-                        let x = x as usize;
-                        let y = y as usize;
-                        let offset = y * pitch + x * 4;
-                        buffer[offset] = 182;
-                        buffer[offset + 1] = 0;
-                        buffer[offset + 2] = 0;
-                        buffer[offset + 3] = 0;
-                    }
-                }
-            })
-            .unwrap();
-
-        let mut font = ttf_context.load_font(Path::new(font_file), FONT_SIZE).unwrap();
-        font.set_style(STYLE_BOLD);
-
-        let config_root = app_root(AppDataType::UserConfig, &APP_INFO).unwrap();
-        let config_file = {
-            let mut config_file = config_root.clone();
-            config_file.push(CONFIG_FILE);
-            config_file
-        };
-
-        if !config_file.exists() {
-            let default_config = Configuration::default();
-            default_config.store(&config_file).unwrap();
-        }
-
-        let config = match Configuration::load(&config_file) {
-            Err(err) => match err {
-                ConfigError::Deserialization(err) => {
-                    // Something happend during deserialization, indicating that the file has invalid content
-                    println!("config.json could not be deserialized. Replacing with default ({:?})", err);
-                    let default_config = Configuration::default();
-                    default_config.store(&config_file).unwrap();
-                    default_config
-                },
-                _ => {
-                    panic!("Unable to load the configuration file! {:?}", err);
-                }
-            },
-            Ok(config) => {
-                config
-            }
-        };
-
+impl Console {
+    /// Creates a new empty Console. Nothing is read from or saved to disk:
+    /// the history and settings only last as long as the console does.
+    pub fn new(config: Configuration) -> Console {
         Console {
             visible: false,
             visible_start_time: 0,
 
             config: config,
+            config_file: None,
 
-            font_file: font_file,
-            leader: Text::new(ttf_context,
-                              &mut renderer,
-                              "hakka>",
-                              Position::XY(PADDING, height as i32 - FONT_SIZE as i32 - PADDING),
-                              FONT_SIZE,
-                              FONT_COLOR,
-                              font_file),
             input_buffer: "".into(),
-            last_command: "".into(),
+            last_command: None,
             command_history: Vec::new(),
+            history_dropped: 0,
+            history_file: None,
             history_position: 0,
             cursor_position: 0,
+            completion_requested: false,
+            kill_buffer: String::new(),
+            overwrite: false,
+            search: None,
+            selection: None,
+            selecting: false,
+            copy_requested: false,
+            paste_requested: false,
             buffer: Vec::new(),
+            styles: Vec::new(),
+            style: Style::Normal,
+            captures: Vec::new(),
+            transcript: None,
+            revision: 0,
+            pager: None,
+            page_lines: None,
             backbuffer_y: 0,
-            texture: texture,
-            ttf_context: ttf_context,
-            size: (width / 2, height),
-            font: font,
+            window_size: None,
+            resizing: false,
+            pane: None,
+            hex_editor: None,
             input_blocked: false,
         }
     }
 
+    /// Creates a Console that saves its configuration and command history in
+    /// the user's config directory, loading the history from there. The
+    /// session is logged there too if the configuration asks for it.
+    pub fn with_user_files(config: Configuration) -> Console {
+        let mut console = Console::new(config);
+        console.config_file = Some(config::user_config_path(config::CONFIG_FILE));
+        console.set_history_file(config::user_config_path(HISTORY_FILE));
+
+        if console.config.get_log_sessions() {
+            match Transcript::create() {
+                Ok(transcript) => console.transcript = Some(transcript),
+                Err(err) => println!("Unable to start the session log: {}", err),
+            }
+        }
+
+        console
+    }
+
+    /// Switches to the level's own command history, if the configuration asks
+    /// for one history per level and the history is kept in the user's files
+    pub fn use_level_history(&mut self, level: &str) {
+        if self.config.get_history_per_level() && self.history_file.is_some() {
+            self.set_history_file(config::user_config_path(format!("{}-{}", HISTORY_FILE, level)));
+        }
+    }
+
+    /// Saves the command history to a file from now on, replacing the
+    /// history with the one already in it
+    pub fn set_history_file(&mut self, path: PathBuf) {
+        self.history_file = Some(path);
+        self.load_history();
+    }
+
+    fn load_history(&mut self) {
+        let mut history = String::new();
+        let loaded = self.history_file
+            .as_ref()
+            .map(|path| File::open(path).and_then(|mut file| file.read_to_string(&mut history)));
+        self.command_history = match loaded {
+            Some(Ok(_)) => history.lines().map(String::from).collect(),
+            _ => Vec::new(),
+        };
+        self.trim_history();
+        self.history_dropped = 0;
+        self.history_position = self.command_history.len();
+    }
+
+    fn save_history(&self) {
+        let history_file = match self.history_file {
+            Some(ref history_file) => history_file,
+            None => return,
+        };
+        let result = File::create(history_file).and_then(|mut file| {
+            for command in &self.command_history {
+                writeln!(file, "{}", command)?;
+            }
+            Ok(())
+        });
+        if let Err(err) = result {
+            println!("Unable to save the command history: {}", err);
+        }
+    }
+
+    /// Adds a command to the end of the history, unless it's the same as the
+    /// last one. Earlier commands are never moved, so `!n` keeps running the
+    /// command `history` listed as n.
+    fn push_history(&mut self, command: String) {
+        if self.command_history.last() == Some(&command) {
+            return;
+        }
+        self.command_history.push(command);
+        self.trim_history();
+        self.save_history();
+    }
+
+    /// Drops the oldest commands beyond the configured history size
+    fn trim_history(&mut self) {
+        let size = self.config.get_history_size();
+        if self.command_history.len() > size {
+            let excess = self.command_history.len() - size;
+            self.command_history.drain(..excess);
+            self.history_dropped += excess;
+        }
+    }
+
     pub fn process(&mut self, event: &Event) {
         // Used to check if no modifiers are held when toggeling console
         let no_mods = |keymod: Mod|
             !keymod.intersects(LALTMOD | LCTRLMOD | LSHIFTMOD | RALTMOD | RCTRLMOD | RSHIFTMOD);
+
+        // The console is laid out again for the new size when it's next drawn
+        if let Event::Window { win_event: WindowEvent::Resized(width, height), .. } = *event {
+            self.window_size = Some((width as u32, height as u32));
+        }
         
         if !self.visible {
             if let Event::KeyDown { scancode, keymod, timestamp, .. } = *event {
-                if no_mods(keymod) && scancode == Some(self.config.get_scancode()) {
+                if no_mods(keymod) && scancode == Some(self.config.get_key(KeyAction::ToggleConsole)) {
                     self.toggle(timestamp);
                     return;
                 }
@@ -152,35 +313,152 @@ impl<'a> Console<'a> {
             return;
         } 
 
+        // The hex editor has the keyboard while it's open, apart from the key
+        // that hides the console
+        if self.hex_editor.is_some() {
+            let toggle = match *event {
+                Event::KeyDown { scancode, keymod, .. } => {
+                    no_mods(keymod) && scancode == Some(self.config.get_key(KeyAction::ToggleConsole))
+                }
+                _ => false,
+            };
+            if !toggle {
+                let open = self.hex_editor.as_mut().map(|editor| editor.process(event)).unwrap_or(false);
+                if !open {
+                    self.hex_editor = None;
+                }
+                return;
+            }
+        }
+
         // Main event processing, only run if visible
         match *event {
             Event::TextInput { ref text, timestamp, .. } => {
                 if self.visible && timestamp > self.visible_start_time + 50 && !self.input_blocked {
-                    self.add_text(text);
+                    if self.pager.is_some() {
+                        match &text[..] {
+                            " " => self.page_down(),
+                            "q" | "Q" => self.end_paging(),
+                            _ => (),
+                        }
+                    } else if self.search.is_some() {
+                        self.search_add_text(text);
+                    } else {
+                        self.add_text(text);
+                    }
                 }
             }
+            Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } if self.is_on_edge(x, y) => {
+                self.resizing = true;
+            }
+            Event::MouseMotion { x, y, .. } if self.resizing => self.resize_to(x, y),
+            Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } if self.resizing => {
+                self.resizing = false;
+                if let Err(err) = self.save_config() {
+                    writeln!(self.styled(Style::Error), "Unable to save configuration: {:?}", err).unwrap();
+                }
+            }
+            Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+                let y = y - self.backbuffer_y;
+                self.selection = Some(Selection {
+                    start: (x, y),
+                    end: (x, y),
+                });
+                self.selecting = true;
+            }
+            Event::MouseMotion { x, y, .. } if self.selecting => {
+                let y = y - self.backbuffer_y;
+                if let Some(ref mut selection) = self.selection {
+                    selection.end = (x, y);
+                }
+            }
+            Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => {
+                self.selecting = false;
+            }
             Event::MouseWheel { y, .. } => {
-                if self.visible &&
-                    self.buffer.len() * FONT_SIZE as usize > (self.size.1 - (FONT_SIZE as u32 * 2)) as usize {
-                    self.backbuffer_y += y * 6;
-                    if self.backbuffer_y < 0 {
-                        self.backbuffer_y = 0;
-                    }
+                if self.visible && self.pager.is_none() {
+                    self.scroll(y * 6);
                 }
             }
             Event::KeyDown { keycode, scancode, timestamp, keymod, .. } => {
                 if self.visible {
-                    if no_mods(keymod) && scancode == Some(self.config.get_scancode()) {
+                    if no_mods(keymod) && scancode == Some(self.config.get_key(KeyAction::ToggleConsole)) {
                         self.toggle(timestamp);
                         return;
                     } else if !self.input_blocked {
+                        let ctrl = keymod.intersects(LCTRLMOD | RCTRLMOD);
+
+                        // Space and q arrive as text input, and ENTER when
+                        // the key is released
+                        if self.pager.is_some() {
+                            match keycode {
+                                Some(Keycode::PageDown) => self.page_down(),
+                                Some(Keycode::Down) => self.page_forward(1),
+                                Some(Keycode::Escape) => self.end_paging(),
+                                Some(Keycode::C) if ctrl => self.end_paging(),
+                                _ => (),
+                            }
+                            return;
+                        }
+
+                        let page = self.page_height();
+                        match keycode {
+                            Some(Keycode::PageUp) => self.scroll(page),
+                            Some(Keycode::PageDown) => self.scroll(-page),
+                            // The renderer stops this at the top of the buffer
+                            Some(Keycode::Home) if ctrl => self.backbuffer_y = i32::max_value() / 2,
+                            Some(Keycode::End) if ctrl => self.backbuffer_y = 0,
+                            _ => (),
+                        }
+
+                        if self.search.is_some() {
+                            match keycode {
+                                Some(Keycode::R) if ctrl => self.search_next(),
+                                Some(Keycode::Backspace) => self.search_backspace(),
+                                Some(Keycode::Escape) => self.end_search(false),
+                                Some(Keycode::C) | Some(Keycode::G) if ctrl => self.end_search(false),
+                                // Moving or editing keeps the match, and carries on as normal
+                                Some(Keycode::Left) | Some(Keycode::Right) | Some(Keycode::Home) |
+                                Some(Keycode::End) | Some(Keycode::Up) | Some(Keycode::Down) |
+                                Some(Keycode::Tab) | Some(Keycode::Delete) => self.end_search(true),
+                                Some(Keycode::LCtrl) | Some(Keycode::RCtrl) => (),
+                                Some(_) if ctrl => self.end_search(true),
+                                // Typing is handled by the TextInput event, and
+                                // Enter when the key is released
+                                _ => (),
+                            }
+                            if self.search.is_some() {
+                                return;
+                            }
+                        }
+
+                        let shift = keymod.intersects(LSHIFTMOD | RSHIFTMOD);
+
                         match keycode { 
+                            Some(Keycode::C) if ctrl && shift => self.copy_requested = true,
+                            Some(Keycode::V) if ctrl => self.paste_requested = true,
                             Some(Keycode::C) => {
-                                if keymod.intersects(LCTRLMOD | RCTRLMOD) {
+                                if ctrl {
                                     self.input_buffer.push_str("^C");
                                     self.commit(false);
                                 }
                             }
+                            Some(Keycode::Left) if ctrl => self.word_left(),
+                            Some(Keycode::Right) if ctrl => self.word_right(),
+                            Some(Keycode::A) if ctrl => self.cursor_position = 0,
+                            Some(Keycode::E) if ctrl => self.cursor_position = self.input_buffer.len(),
+                            Some(Keycode::U) if ctrl => {
+                                let end = self.cursor_position;
+                                self.kill(0, end);
+                            }
+                            Some(Keycode::K) if ctrl => {
+                                let (start, end) = (self.cursor_position, self.input_buffer.len());
+                                self.kill(start, end);
+                            }
+                            Some(Keycode::W) if ctrl => self.kill_word_back(),
+                            Some(Keycode::Y) if ctrl => self.yank(),
+                            Some(Keycode::R) if ctrl => self.begin_search(),
+                            Some(Keycode::Insert) => self.overwrite = !self.overwrite,
                             Some(Keycode::Left) => {
                                 self.cursor_left();
                             }
@@ -190,6 +468,9 @@ impl<'a> Console<'a> {
                             Some(Keycode::Backspace) => {
                                 self.backspace();
                             }
+                            Some(Keycode::Tab) => {
+                                self.completion_requested = true;
+                            }
                             Some(Keycode::Delete) => {
                                 if self.cursor_position < self.input_buffer.len() {
                                     self.cursor_position += 1;
@@ -201,8 +482,17 @@ impl<'a> Console<'a> {
                     }
                 }
             }
-            Event::KeyUp { keycode, timestamp, .. } => {
+            Event::KeyUp { keycode, timestamp, keymod, .. } => {
                 if self.visible && !self.input_blocked {
+                    let ctrl = keymod.intersects(LCTRLMOD | RCTRLMOD);
+
+                    if self.pager.is_some() {
+                        if keycode == Some(Keycode::Return) {
+                            self.page_forward(1);
+                        }
+                        return;
+                    }
+
                     match keycode { 
                         Some(Keycode::Up) => {
                             // Special check that an automatic console toggle
@@ -225,12 +515,13 @@ impl<'a> Console<'a> {
                             }
                         }
                         Some(Keycode::Return) => {
+                            self.end_search(true);
                             self.commit(true);
                         }
-                        Some(Keycode::End) => {
+                        Some(Keycode::End) if !ctrl => {
                             self.cursor_position = self.input_buffer.len();
                         }
-                        Some(Keycode::Home) => {
+                        Some(Keycode::Home) if !ctrl => {
                             self.cursor_position = 0;
                         }
                         _ => (),
@@ -242,15 +533,283 @@ impl<'a> Console<'a> {
     }
 
     pub fn process_command(&mut self) {
-        let command = self.input_buffer.clone();
+        let command = self.input_buffer.trim().to_string();
         if !command.is_empty() {
-            self.command_history.push(command.clone());
-            self.last_command = command.clone();
+            self.push_history(command.clone());
+        }
+        self.last_command = Some(command);
+    }
+
+    /// Returns every command entered so far, oldest first
+    pub fn history(&self) -> &[String] {
+        &self.command_history
+    }
+
+    /// The number `!n` recalls the first command of `history()` by
+    pub fn history_start(&self) -> usize {
+        self.history_dropped + 1
+    }
+
+    /// Expands `!!` to the previous command, `!n` to the nth command in the
+    /// history and `!prefix` to the last command that starts with prefix.
+    /// Anything else is returned as is.
+    fn recall(&self, command: &str) -> Option<String> {
+        let command = command.trim();
+        if !command.starts_with('!') || command.len() == 1 {
+            return Some(command.into());
+        }
+
+        let event = &command[1..];
+        if event == "!" {
+            self.command_history.last().cloned()
+        } else if let Ok(number) = event.parse::<usize>() {
+            number.checked_sub(self.history_start())
+                .and_then(|index| self.command_history.get(index))
+                .cloned()
+        } else {
+            self.command_history.iter().rev().find(|command| command.starts_with(event)).cloned()
         }
     }
 
     pub fn clear(&mut self) {
         self.buffer.clear();
+        self.styles.clear();
+        self.revision += 1;
+        self.pager = None;
+        self.backbuffer_y = 0;
+    }
+
+    /// Returns a writer that writes to the console in the given style
+    pub fn styled<'a>(&'a mut self, style: Style) -> Styled<'a> {
+        let previous = self.style;
+        self.style = style;
+        Styled {
+            console: self,
+            previous: previous,
+        }
+    }
+
+    /// Splits the part of a line of the buffer between two byte offsets into
+    /// its differently styled spans
+    pub fn spans(&self, line: usize, from: usize, to: usize) -> Vec<(&str, Style)> {
+        let text = &self.buffer[line];
+        let styles = &self.styles[line];
+        styles.iter()
+            .enumerate()
+            .map(|(index, &(start, style))| {
+                let end = styles.get(index + 1).map(|&(end, _)| end).unwrap_or(text.len());
+                let (start, end) = (::std::cmp::max(start, from), ::std::cmp::min(end, to));
+                (if start < end { &text[start..end] } else { "" }, style)
+            })
+            .filter(|&(span, _)| !span.is_empty())
+            .collect()
+    }
+
+    /// Splits the buffer between two (line, byte offset) positions, given in
+    /// either order, into (line, start, end) byte ranges from top to bottom
+    fn spans_between(&self, start: (usize, usize), end: (usize, usize)) -> Vec<(usize, usize, usize)> {
+        let (start, end) = if end < start { (end, start) } else { (start, end) };
+        (start.0..end.0 + 1)
+            .map(|line| {
+                let from = if line == start.0 { start.1 } else { 0 };
+                let to = if line == end.0 { end.1 } else { self.buffer[line].len() };
+                (line, from, to)
+            })
+            .collect()
+    }
+
+    /// Joins (line, start, end) byte ranges of the buffer into the text that's
+    /// copied to the clipboard, a line per range
+    fn text_of(&self, spans: &[(usize, usize, usize)]) -> String {
+        spans.iter()
+            .map(|&(line, start, end)| &self.buffer[line][start..end])
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn config(&self) -> &Configuration {
+        &self.config
+    }
+
+    pub fn config_mut(&mut self) -> &mut Configuration {
+        &mut self.config
+    }
+
+    /// Saves the configuration to the file it was loaded from. Without one,
+    /// changes only last for this session.
+    pub fn save_config(&self) -> Result<(), ConfigError> {
+        match self.config_file {
+            Some(ref config_file) => self.config.store(config_file),
+            None => Ok(()),
+        }
+    }
+
+    /// Saves the configuration to another file from now on, e.g. one given
+    /// on the command line
+    pub fn set_config_file(&mut self, path: PathBuf) {
+        self.config_file = Some(path);
+    }
+
+    /// Collects everything written to the console from now on, until
+    /// `end_capture` is called. Captures can be nested.
+    pub fn begin_capture(&mut self) {
+        self.captures.push(String::new());
+    }
+
+    /// Stops the innermost capture and returns what was written during it
+    pub fn end_capture(&mut self) -> String {
+        self.captures.pop().unwrap_or_default()
+    }
+
+    /// Mirrors everything written to the console into a file. Without a path,
+    /// a timestamped file in the user's data directory is used. Returns the
+    /// path of the file.
+    pub fn start_transcript(&mut self, path: Option<&str>) -> std::io::Result<PathBuf> {
+        self.stop_transcript();
+        let transcript = match path {
+            Some(path) => Transcript::open(path)?,
+            None => Transcript::create()?,
+        };
+        let path = transcript.path().to_path_buf();
+        self.transcript = Some(transcript);
+        Ok(path)
+    }
+
+    /// Stops mirroring the console, returning the path of the file it went to
+    pub fn stop_transcript(&mut self) -> Option<PathBuf> {
+        self.transcript.take().map(|transcript| transcript.path().to_path_buf())
+    }
+
+    pub fn transcript_path(&self) -> Option<&Path> {
+        self.transcript.as_ref().map(|transcript| transcript.path())
+    }
+
+    /// Scrolls the buffer up by a number of pixels, or down if negative. The
+    /// renderer stops it at the top of the buffer.
+    fn scroll(&mut self, distance: i32) {
+        self.backbuffer_y += distance;
+        if self.backbuffer_y < 0 {
+            self.backbuffer_y = 0;
+        }
+    }
+
+    /// The height of the lines that fit on screen, in pixels
+    fn page_height(&self) -> i32 {
+        self.page_lines.unwrap_or(1) as i32 * self.config.get_console_font_size() as i32
+    }
+
+    /// Shows the hex editor in place of the console until it's closed
+    pub fn open_hex_editor(&mut self, editor: HexEditor) {
+        self.end_paging();
+        self.hex_editor = Some(editor);
+    }
+
+    pub fn hex_editor_mut(&mut self) -> Option<&mut HexEditor> {
+        self.hex_editor.as_mut()
+    }
+
+    /// Places the console in a pane of a larger layout, or back at its dock
+    /// when given None
+    pub fn set_pane(&mut self, pane: Option<Rect>) {
+        self.pane = pane;
+    }
+
+    /// Returns true if a point is on the edge of the console facing the game
+    fn is_on_edge(&self, x: i32, y: i32) -> bool {
+        // A pane is sized by its layout
+        if self.pane.is_some() {
+            return false;
+        }
+        let window = match self.window_size {
+            Some(window) => window,
+            None => return false,
+        };
+        let area = console_area(&self.config, window);
+        let distance = match self.config.get_console_dock() {
+            Dock::Left => x - area.right(),
+            Dock::Right => x - area.x(),
+            Dock::Top => y - area.bottom(),
+            Dock::Bottom => y - area.y(),
+        };
+        distance.abs() <= RESIZE_MARGIN
+    }
+
+    /// Moves the console's inner edge to a point, while it's being dragged
+    fn resize_to(&mut self, x: i32, y: i32) {
+        let (width, height) = match self.window_size {
+            Some((width, height)) => (width as i32, height as i32),
+            None => return,
+        };
+        let percent = match self.config.get_console_dock() {
+            Dock::Left => x * 100 / width,
+            Dock::Right => (width - x) * 100 / width,
+            Dock::Top => y * 100 / height,
+            Dock::Bottom => (height - y) * 100 / height,
+        };
+        self.config.set_console_size(::std::cmp::max(percent, 0) as u32);
+    }
+
+    /// Shows the output that follows a screenful at a time. Captured output
+    /// isn't paged, and neither is a console that isn't being drawn.
+    pub fn begin_paging(&mut self) {
+        if self.captures.is_empty() && self.page_lines.is_some() {
+            let start = self.buffer.len().saturating_sub(1);
+            self.pager = Some(Pager {
+                start: start,
+                rows: 0,
+            });
+        }
+    }
+
+    pub fn is_paging(&self) -> bool {
+        self.pager.is_some()
+    }
+
+    /// Stops paging and goes back to the end of the buffer
+    pub fn end_paging(&mut self) {
+        self.pager = None;
+        self.backbuffer_y = 0;
+    }
+
+    fn page_down(&mut self) {
+        let lines = self.page_lines.unwrap_or(1);
+        self.page_forward(lines);
+    }
+
+    /// Moves the pager on by a number of screen rows. The renderer ends
+    /// paging once the rest of the output fits on screen.
+    fn page_forward(&mut self, rows: usize) {
+        if let Some(ref mut pager) = self.pager {
+            pager.rows += rows;
+        }
+    }
+
+    /// Drops the oldest lines beyond the configured scrollback size
+    fn trim_buffer(&mut self) {
+        let limit = ::std::cmp::max(self.config.get_scrollback_lines(), 1);
+        if self.buffer.len() > limit {
+            let excess = self.buffer.len() - limit;
+            self.buffer.drain(..excess);
+            self.styles.drain(..excess);
+            if let Some(ref mut pager) = self.pager {
+                pager.start = pager.start.saturating_sub(excess);
+            }
+            self.revision += 1;
+        }
+    }
+
+    /// Returns the lines written to the console so far
+    pub fn lines(&self) -> &[String] {
+        &self.buffer
+    }
+
+    /// Queues a line of input as if it had been typed and committed with ENTER
+    pub fn submit<S>(&mut self, command: S)
+        where S: Into<String>
+    {
+        self.input_buffer = command.into();
+        self.cursor_position = self.input_buffer.len();
+        self.commit(true);
     }
 
     fn history_navigate_back(&mut self) {
@@ -275,14 +834,60 @@ impl<'a> Console<'a> {
         }
     }
 
+    /// Returns the command that was last committed, if it hasn't been taken
+    /// already. An empty command means ENTER was pressed on an empty line.
     pub fn get_next_command(&mut self) -> Option<String> {
-        if !self.last_command.is_empty() {
-            let cmd = self.last_command.clone();
-            self.last_command.clear();
-            Some(cmd)
-        } else {
-            None
-        }
+        self.last_command.take()
+    }
+
+    /// Returns true once after Tab has been pressed. The completions themselves
+    /// come from the command system, which the console knows nothing about.
+    pub fn take_completion_request(&mut self) -> bool {
+        let requested = self.completion_requested;
+        self.completion_requested = false;
+        requested
+    }
+
+    /// Returns the input typed before the cursor
+    pub fn input_to_cursor(&self) -> &str {
+        &self.input_buffer[..self.cursor_position]
+    }
+
+    /// Completes the word that starts at `start` and ends at the cursor. A
+    /// single candidate replaces the word. Otherwise the word is extended as
+    /// far as the candidates agree, and the candidates are listed.
+    pub fn complete(&mut self, start: usize, candidates: &[String]) {
+        let replacement = match candidates.len() {
+            0 => return,
+            1 => format!("{} ", candidates[0]),
+            _ => {
+                let mut common = candidates[0].clone();
+                for candidate in &candidates[1..] {
+                    let length = common.chars()
+                        .zip(candidate.chars())
+                        .take_while(|&(a, b)| a.to_lowercase().eq(b.to_lowercase()))
+                        .map(|(a, _)| a.len_utf8())
+                        .sum();
+                    common.truncate(length);
+                }
+
+                let input = self.input_buffer.clone();
+                writeln!(self.styled(Style::Input), "hakka> {}", input).unwrap();
+                writeln!(self, "{}", candidates.join("  ")).unwrap();
+
+                // Keep what was typed if the candidates don't extend it
+                if common.len() < self.cursor_position - start {
+                    return;
+                }
+                common
+            }
+        };
+
+        self.input_buffer = format!("{}{}{}",
+                                    &self.input_buffer[..start],
+                                    replacement,
+                                    &self.input_buffer[self.cursor_position..]);
+        self.cursor_position = start + replacement.len();
     }
 
     /// Toggles the visibility of the Console
@@ -294,21 +899,209 @@ impl<'a> Console<'a> {
     }
 
     pub fn add_text(&mut self, input: &str) {
-        self.input_buffer.insert(self.cursor_position, input.chars().next().unwrap());
+        if self.overwrite {
+            for _ in input.chars() {
+                if self.cursor_position < self.input_buffer.len() {
+                    self.cursor_right();
+                    self.backspace();
+                }
+            }
+        }
+        self.input_buffer.insert_str(self.cursor_position, input);
         self.cursor_position += input.len();
     }
 
+    /// Inserts pasted text at the cursor. Pasted lines become a chain of
+    /// commands, as the input is a single line.
+    pub fn paste(&mut self, text: &str) {
+        let text = text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("; ");
+        let text = text.chars().filter(|c| !c.is_control()).collect::<String>();
+        self.add_text(&text);
+    }
+
+    /// Returns true once after Ctrl+Shift+C has been pressed
+    pub fn take_copy_request(&mut self) -> bool {
+        let requested = self.copy_requested;
+        self.copy_requested = false;
+        requested
+    }
+
+    /// Returns true once after Ctrl+V has been pressed
+    pub fn take_paste_request(&mut self) -> bool {
+        let requested = self.paste_requested;
+        self.paste_requested = false;
+        requested
+    }
+
+    /// Returns true if typing replaces the character under the cursor
+    pub fn is_overwriting(&self) -> bool {
+        self.overwrite
+    }
+
+    /// Moves the cursor to the start of the current or previous word
+    pub fn word_left(&mut self) {
+        let mut position = self.cursor_position;
+        let mut in_word = false;
+        for (index, c) in self.input_buffer[..self.cursor_position].char_indices().rev() {
+            if is_word_char(c) {
+                in_word = true;
+            } else if in_word {
+                break;
+            }
+            position = index;
+        }
+        self.cursor_position = position;
+    }
+
+    /// Moves the cursor to the end of the current or next word
+    pub fn word_right(&mut self) {
+        let mut position = self.input_buffer.len();
+        let mut in_word = false;
+        for (index, c) in self.input_buffer[self.cursor_position..].char_indices() {
+            if is_word_char(c) {
+                in_word = true;
+            } else if in_word {
+                position = self.cursor_position + index;
+                break;
+            }
+        }
+        self.cursor_position = position;
+    }
+
+    /// Removes the input between two byte offsets, keeping it for `yank`
+    fn kill(&mut self, start: usize, end: usize) {
+        if start < end {
+            self.kill_buffer = self.input_buffer.drain(start..end).collect();
+            self.cursor_position = start;
+        }
+    }
+
+    /// Removes the whitespace separated word before the cursor
+    pub fn kill_word_back(&mut self) {
+        let mut start = self.cursor_position;
+        let mut in_word = false;
+        for (index, c) in self.input_buffer[..self.cursor_position].char_indices().rev() {
+            if !c.is_whitespace() {
+                in_word = true;
+            } else if in_word {
+                break;
+            }
+            start = index;
+        }
+        let end = self.cursor_position;
+        self.kill(start, end);
+    }
+
+    /// Inserts the text that was last killed at the cursor
+    pub fn yank(&mut self) {
+        let text = self.kill_buffer.clone();
+        self.input_buffer.insert_str(self.cursor_position, &text);
+        self.cursor_position += text.len();
+    }
+
+    /// Returns the reverse search prompt, e.g. "(reverse-i-search)`mem'", while
+    /// a search is in progress
+    pub fn search_prompt(&self) -> Option<String> {
+        self.search.as_ref().map(|search| format!("(reverse-i-search)`{}'", search.query))
+    }
+
+    fn begin_search(&mut self) {
+        self.search = Some(HistorySearch {
+            query: String::new(),
+            position: self.command_history.len(),
+            original: self.input_buffer.clone(),
+        });
+    }
+
+    /// Finds the next older command that matches the query
+    fn search_next(&mut self) {
+        let start = match self.search {
+            Some(ref search) => search.position,
+            None => return,
+        };
+        self.search_from(start);
+    }
+
+    fn search_add_text(&mut self, text: &str) {
+        let start = match self.search {
+            Some(ref mut search) => {
+                search.query.push_str(text);
+                // The current match may still match the longer query
+                search.position + 1
+            }
+            None => return,
+        };
+        self.search_from(start);
+    }
+
+    fn search_backspace(&mut self) {
+        if let Some(ref mut search) = self.search {
+            search.query.pop();
+        }
+        let length = self.command_history.len();
+        self.search_from(length);
+    }
+
+    /// Shows the newest command before `start` in the history that contains
+    /// the query
+    fn search_from(&mut self, start: usize) {
+        let found = match self.search {
+            Some(ref search) => {
+                let start = ::std::cmp::min(start, self.command_history.len());
+                self.command_history[..start]
+                    .iter()
+                    .rposition(|command| command.contains(&search.query[..]))
+            }
+            None => return,
+        };
+
+        if let Some(position) = found {
+            self.input_buffer = self.command_history[position].clone();
+            self.cursor_position = self.input_buffer.len();
+            if let Some(ref mut search) = self.search {
+                search.position = position;
+            }
+        }
+    }
+
+    /// Stops searching, either keeping the match or going back to the input
+    /// from before the search
+    fn end_search(&mut self, accept: bool) {
+        if let Some(search) = self.search.take() {
+            if !accept {
+                self.input_buffer = search.original;
+                self.cursor_position = self.input_buffer.len();
+            }
+        }
+    }
+
     pub fn commit(&mut self, execute: bool) {
         let command = self.input_buffer.clone();
-        writeln!(self, "hakka> {}", command).unwrap();
 
         if execute {
-            self.process_command();
+            match self.recall(&command) {
+                Some(recalled) => {
+                    writeln!(self.styled(Style::Input), "hakka> {}", recalled).unwrap();
+                    self.input_buffer = recalled;
+                    self.process_command();
+                }
+                None => {
+                    writeln!(self.styled(Style::Input), "hakka> {}", command).unwrap();
+                    writeln!(self.styled(Style::Error), "{}: event not found", command.trim()).unwrap();
+                }
+            }
+        } else {
+            writeln!(self.styled(Style::Input), "hakka> {}", command).unwrap();
         }
 
         self.input_buffer.clear();
         self.cursor_position = 0;
         self.history_position = self.command_history.len();
+        self.backbuffer_y = 0;
     }
 
     pub fn cursor_left(&mut self) {
@@ -339,149 +1132,503 @@ impl<'a> Console<'a> {
         }
     }
 
+    /// Adds text to the last line of the buffer in the current style
+    fn append(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if let (Some(line), Some(styles)) = (self.buffer.last_mut(), self.styles.last_mut()) {
+            if styles.last().map(|&(_, style)| style) != Some(self.style) {
+                styles.push((line.len(), self.style));
+            }
+            line.push_str(text);
+        }
+        self.revision += 1;
+    }
+}
+
+/// Splits a line into (start, end) byte ranges that each fit in `width`
+/// pixels, breaking after whitespace where possible. Rows after the first
+/// are `indent` pixels narrower, to make room for the wrap marker.
+fn wrap_line<F>(text: &str, width: i32, indent: i32, width_of: F) -> Vec<(usize, usize)>
+    where F: Fn(&str) -> u32
+{
+    let mut rows = Vec::new();
+    let mut start = 0;
+    loop {
+        let available = if start == 0 { width } else { width - indent };
+        let mut end = start;
+        let mut pen = 0;
+        let mut last_break = None;
+        for (index, c) in text[start..].char_indices() {
+            let index = start + index;
+            let next = index + c.len_utf8();
+            pen += width_of(&text[index..next]) as i32;
+            // A row always holds at least one character
+            if pen > available && end > start {
+                break;
+            }
+            end = next;
+            if c.is_whitespace() {
+                last_break = Some(end);
+            }
+        }
+
+        if end < text.len() {
+            if let Some(last_break) = last_break {
+                end = last_break;
+            }
+        }
+        rows.push((start, end));
+
+        if end >= text.len() {
+            return rows;
+        }
+        start = end;
+    }
+}
+
+/// Finds the byte offset of the character `x` pixels along a line of text,
+/// or the end of the line if it's past the last character
+fn column_at<F>(text: &str, x: i32, width_of: F) -> usize
+    where F: Fn(&str) -> u32
+{
+    text.char_indices()
+        .find(|&(index, c)| width_of(&text[..index + c.len_utf8()]) as i32 > x)
+        .map(|(index, _)| index)
+        .unwrap_or(text.len())
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Returns the part of a window of the given size that the console covers
+pub fn console_area(config: &Configuration, (width, height): (u32, u32)) -> Rect {
+    let size = config.get_console_size();
+    let across = ::std::cmp::max(width * size / 100, 1);
+    let down = ::std::cmp::max(height * size / 100, 1);
+    match config.get_console_dock() {
+        Dock::Left => Rect::new(0, 0, across, height),
+        Dock::Right => Rect::new((width - across) as i32, 0, across, height),
+        Dock::Top => Rect::new(0, 0, width, down),
+        Dock::Bottom => Rect::new(0, (height - down) as i32, width, down),
+    }
+}
+
+/// A line of the buffer as it's drawn. Lines too long for the console are
+/// wrapped onto several rows.
+#[derive(Clone, Copy)]
+struct Row {
+    line: usize,
+    /// The byte range of the line shown on the row
+    start: usize,
+    end: usize,
+}
+
+/// Draws a `Console` into an SDL window
+pub struct ConsoleRenderer<'a> {
+    font_file: &'a str,
+    ttf_context: &'a Sdl2TtfContext,
+    /// The part of the window the console covers
+    area: Rect,
+    atlas: GlyphAtlas<'a>,
+    clipboard: ClipboardUtil,
+    /// The buffer wrapped to the width of the console, top to bottom
+    rows: Vec<Row>,
+    /// The buffer revision, width and font size the rows were wrapped for
+    wrapped: Option<(u64, u32, u16)>,
+    /// How much of the output being paged has been shown
+    paged_percent: usize,
+}
+
+impl<'a> ConsoleRenderer<'a> {
+    /// Creates a renderer that draws the console docked to one edge of the
+    /// window, as set in the configuration. The clipboard is used for copy
+    /// and paste.
+    pub fn new(ttf_context: &'a Sdl2TtfContext,
+               renderer: &mut Renderer,
+               font_file: &'a str,
+               clipboard: ClipboardUtil)
+               -> ConsoleRenderer<'a> {
+
+        let (width, height) = renderer.window().unwrap().size();
+
+        ConsoleRenderer {
+            font_file: font_file,
+            ttf_context: ttf_context,
+            area: Rect::new(0, 0, width / 2, height),
+            atlas: GlyphAtlas::new(ttf_context, renderer, font_file, FONT_SIZE),
+            clipboard: clipboard,
+            rows: Vec::new(),
+            wrapped: None,
+            paged_percent: 0,
+        }
+    }
+
     /// Renders the Console
-    pub fn render(&mut self, mut renderer: &mut Renderer) {
-        if self.visible {
+    pub fn render(&mut self, console: &mut Console, mut renderer: &mut Renderer) {
+        if console.visible {
+            self.layout(console, renderer);
 
             renderer.set_blend_mode(BlendMode::Blend);
-            self.texture.set_blend_mode(BlendMode::Blend);
-            renderer.copy(&self.texture,
-                      None,
-                      Some(Rect::new(0, 0, self.size.0, self.size.1)))
-                .unwrap();
-            self.generate_backbuffer_texture(&mut renderer);
-
-            if !self.input_blocked {
-                self.render_leader(&mut renderer);
+            renderer.set_draw_color(Color::RGBA(0, 0, 0, console.config().get_console_opacity()));
+            renderer.fill_rect(self.area).unwrap();
+            renderer.set_clip_rect(Some(self.area));
+
+            if let Some(ref editor) = console.hex_editor {
+                editor.render(&mut self.atlas, renderer, self.area, console.config());
+                renderer.set_clip_rect(None);
+                self.render_border(&mut renderer);
+                return;
+            }
+
+            self.wrap(console);
+            self.clamp_scroll(console);
+            self.render_buffer(console, &mut renderer);
+            self.render_selection(console, &mut renderer);
+            self.render_scrollbar(console, &mut renderer);
+            self.process_clipboard(console);
+
+            let color = console.config().get_color(Style::Normal);
+            let input_color = console.config().get_color(Style::Input);
+            let input_y = self.input_y();
+
+            if console.is_paging() {
+                self.clear_input_line(&mut renderer);
+                let prompt = format!("-- More ({}%) -- Space: next page, ENTER: next line, q: stop",
+                                     self.paged_percent);
+                let highlight = console.config().get_color(Style::Highlight);
+                self.atlas.draw(&mut renderer, &prompt, self.area.x() + PADDING, input_y, highlight);
+            } else if !console.input_blocked {
+                self.render_leader(input_color, &mut renderer);
+                let input_x = self.area.x() + PADDING + self.leader_width();
                 // Insert the cursor via a dodgy vertical line
-                let cursor_x =
-                    60 + PADDING as i16 +
-                    self.font.size_of(&self.input_buffer[..self.cursor_position]).unwrap().0 as i16;
+                let cursor_x = input_x +
+                               self.atlas.width_of(&console.input_buffer[..console.cursor_position]) as i32;
                 // Draw a dodgy cursor
-                renderer.draw_line(Point::new(cursor_x as i32,
-                                self.size.1 as i32 - FONT_SIZE as i32 - PADDING as i32),
-                                Point::new(cursor_x as i32,
-                                self.size.1 as i32 - PADDING as i32))
+                renderer.draw_line(Point::new(cursor_x, input_y),
+                                   Point::new(cursor_x, input_y + self.atlas.size() as i32))
                     .unwrap();
 
-                if !self.input_buffer.is_empty() {
-                    let text = Text::new(self.ttf_context,
-                                         &mut renderer,
-                                         &self.input_buffer[..],
-                                         Position::XY(60 + PADDING,
-                                                      self.size.1 as i32 - FONT_SIZE as i32 - PADDING),
-                                         FONT_SIZE,
-                                         FONT_COLOR,
-                                         self.font_file);
-                    text.render(&mut renderer);
+                // Underline the character that typing will replace
+                if console.is_overwriting() {
+                    let next = console.input_buffer[console.cursor_position..].chars().next().unwrap_or(' ');
+                    let width = self.atlas.width_of(&next.to_string()) as i32;
+                    renderer.draw_line(Point::new(cursor_x, input_y + self.atlas.size() as i32),
+                                       Point::new(cursor_x + width, input_y + self.atlas.size() as i32))
+                        .unwrap();
                 }
+
+                if let Some(prompt) = console.search_prompt() {
+                    let y = input_y - self.atlas.size() as i32;
+                    self.atlas.draw(&mut renderer, &prompt, self.area.x() + PADDING, y, color);
+                }
+
+                self.atlas.draw(&mut renderer, &console.input_buffer, input_x, input_y, input_color);
             } else {
-                let text = Text::new(self.ttf_context,
-                                     &mut renderer,
-                                     "Press Ctrl+C or ENTER to cancel",
-                                     Position::XY(PADDING,
-                                                  self.size.1 as i32 - FONT_SIZE as i32 - PADDING),
-                                     FONT_SIZE,
-                                     FONT_COLOR,
-                                     self.font_file);
-                text.render(&mut renderer);
+                let x = self.area.x() + PADDING;
+                self.atlas.draw(&mut renderer, "Press Ctrl+C or ENTER to cancel", x, input_y, color);
             }
 
+            renderer.set_clip_rect(None);
             self.render_border(&mut renderer);
         }
     }
 
-    fn render_border(&self, mut renderer: &mut Renderer) {
-        // Render the border
-        renderer.set_draw_color(Color::RGBA(255, 255, 255, 255));
-        // North
-        renderer.draw_line(Point::new(0, 0), Point::new(self.size.0 as i32, 0)).unwrap();
-
-        // East
-        renderer.draw_line(Point::new(self.size.0 as i32,
-                        0),
-                        Point::new(self.size.0 as i32,
-                        self.size.1 as i32))
-            .unwrap();
+    /// Fits the console to the window and the configuration, which may both
+    /// have changed since the last frame
+    fn layout(&mut self, console: &mut Console, renderer: &Renderer) {
+        let window = renderer.window().unwrap().size();
+        console.window_size = Some(window);
+        self.area = console.pane.unwrap_or_else(|| console_area(console.config(), window));
+
+        let font_size = console.config().get_console_font_size();
+        if font_size != self.atlas.size() {
+            self.atlas = GlyphAtlas::new(self.ttf_context, renderer, self.font_file, font_size);
+        }
+    }
+
+    fn process_clipboard(&self, console: &mut Console) {
+        if console.take_paste_request() {
+            if let Ok(text) = self.clipboard.clipboard_text() {
+                console.paste(&text);
+            }
+        }
+
+        if console.take_copy_request() {
+            let text = console.text_of(&self.selected_spans(console));
+            if !text.is_empty() {
+                self.clipboard.set_clipboard_text(&text).unwrap_or(());
+            }
+        }
+    }
+
+    fn render_selection(&self, console: &Console, renderer: &mut Renderer) {
+        renderer.set_draw_color(SELECTION_COLOR);
+        for (line, start, end) in self.selected_spans(console) {
+            // A wrapped line is highlighted on each of its rows
+            for (index, row) in self.rows.iter().enumerate().filter(|&(_, row)| row.line == line) {
+                let (from, to) = (::std::cmp::max(start, row.start), ::std::cmp::min(end, row.end));
+                if from >= to {
+                    continue;
+                }
+                let text = &console.buffer[line];
+                let x = self.row_x(row) + self.atlas.width_of(&text[row.start..from]) as i32;
+                let width = self.atlas.width_of(&text[from..to]);
+                let y = self.row_top(index) + console.backbuffer_y;
+                renderer.fill_rect(Rect::new(x, y, width, self.atlas.size() as u32)).unwrap();
+            }
+        }
+    }
+
+    /// Resolves the console's selection to (line, start, end) byte ranges of
+    /// the buffer, from top to bottom
+    fn selected_spans(&self, console: &Console) -> Vec<(usize, usize, usize)> {
+        let selection = match console.selection {
+            Some(selection) if selection.start != selection.end => selection,
+            _ => return Vec::new(),
+        };
+
+        match (self.position_at(console, selection.start), self.position_at(console, selection.end)) {
+            (Some(start), Some(end)) => console.spans_between(start, end),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Finds the buffer line and byte offset under a point, ignoring scrolling
+    fn position_at(&self, console: &Console, (x, y): (i32, i32)) -> Option<(usize, usize)> {
+        if self.rows.is_empty() {
+            return None;
+        }
+
+        let font_size = self.atlas.size() as i32;
+        let distance = self.area.bottom() - PADDING - y;
+        let screen_row = if distance > 0 { (distance + font_size - 1) / font_size } else { 0 };
+        let from_bottom = ::std::cmp::max(screen_row, 2) as usize - 2;
+        let row = self.rows[self.rows.len() - 1 - ::std::cmp::min(from_bottom, self.rows.len() - 1)];
+
+        let text = &console.buffer[row.line][row.start..row.end];
+        let column = column_at(text, x - self.row_x(&row), |text| self.atlas.width_of(text));
+
+        Some((row.line, row.start + column))
+    }
+
+    /// Wraps the buffer into rows again if it, the console's width or the
+    /// font have changed since the last frame
+    fn wrap(&mut self, console: &Console) {
+        let key = (console.revision, self.area.width(), self.atlas.size());
+        if self.wrapped == Some(key) {
+            return;
+        }
+
+        self.rows.clear();
+        // The empty line after the last writeln! isn't rendered
+        let count = match console.buffer.last() {
+            Some(line) if line.is_empty() => console.buffer.len() - 1,
+            _ => console.buffer.len(),
+        };
+        let width = self.area.width() as i32 - PADDING * 2 - SCROLLBAR_WIDTH as i32;
+        let indent = self.atlas.width_of(WRAP_MARKER) as i32;
+        for line in 0..count {
+            for (start, end) in wrap_line(&console.buffer[line], width, indent, |text| self.atlas.width_of(text)) {
+                self.rows.push(Row {
+                    line: line,
+                    start: start,
+                    end: end,
+                });
+            }
+        }
+        self.wrapped = Some(key);
+    }
+
+    /// Returns the x coordinate a row's text starts at
+    fn row_x(&self, row: &Row) -> i32 {
+        let indent = if row.start > 0 { self.atlas.width_of(WRAP_MARKER) as i32 } else { 0 };
+        self.area.x() + PADDING + indent
+    }
+
+    /// Returns the y coordinate of the top of a row, ignoring scrolling. The
+    /// last row sits just above the input line.
+    fn row_top(&self, row: usize) -> i32 {
+        let from_bottom = (self.rows.len() - row) as i32 + 1;
+        self.area.bottom() - self.atlas.size() as i32 * from_bottom - PADDING
+    }
+
+    /// Returns the y coordinate of the top of the input line
+    fn input_y(&self) -> i32 {
+        self.area.bottom() - self.atlas.size() as i32 - PADDING
+    }
+
+    /// The width of the "hakka>" leader, and the space after it
+    fn leader_width(&self) -> i32 {
+        self.atlas.width_of("hakka> ") as i32
+    }
 
-        // South
-        renderer.draw_line(Point::new(0,
-                        self.size.1 as i32 - 1),
-                        Point::new(self.size.0 as i32,
-                        self.size.1 as i32 - 1))
+    /// How many rows of output fit above the input line
+    fn page_lines(&self) -> usize {
+        ::std::cmp::max((self.area.height() as i32 - PADDING) / self.atlas.size() as i32 - 1, 1) as usize
+    }
+
+    /// How far the buffer can scroll before its first row reaches the top
+    fn max_scroll(&self) -> i32 {
+        let rows_above = self.rows.len() as i32 - self.page_lines() as i32;
+        ::std::cmp::max(rows_above, 0) * self.atlas.size() as i32
+    }
+
+    /// Keeps the scroll offset within the buffer, and scrolls the pager's top
+    /// row to the top of the console. Paging ends once the rest of the output
+    /// fits on screen.
+    fn clamp_scroll(&mut self, console: &mut Console) {
+        let page_lines = self.page_lines();
+        console.page_lines = Some(page_lines);
+
+        let position = console.pager.as_ref().map(|pager| (pager.start, pager.rows));
+        if let Some((start, rows)) = position {
+            let first = self.rows.iter().position(|row| row.line >= start).unwrap_or(self.rows.len());
+            let top = first + rows;
+            let rows_above = self.rows.len() as i32 - top as i32 - page_lines as i32;
+            if rows_above > 0 {
+                console.backbuffer_y = rows_above * self.atlas.size() as i32;
+                let total = self.rows.len() - first;
+                self.paged_percent = ::std::cmp::min((top - first + page_lines) * 100 / total, 100);
+            } else {
+                console.end_paging();
+            }
+        }
+
+        let max_scroll = self.max_scroll();
+        if console.backbuffer_y > max_scroll {
+            console.backbuffer_y = max_scroll;
+        }
+    }
+
+    /// Draws a bar along the right edge showing which part of the buffer is
+    /// on screen, when it doesn't all fit
+    fn render_scrollbar(&self, console: &Console, renderer: &mut Renderer) {
+        let max_scroll = self.max_scroll();
+        if max_scroll == 0 {
+            return;
+        }
+
+        let font_size = self.atlas.size() as i32;
+        let track = self.area.height() as i32 - font_size - PADDING;
+        let page = (self.page_lines() as i32 * font_size) as f32;
+        let thumb = ::std::cmp::max((track as f32 * page / (page + max_scroll as f32)) as i32, font_size);
+        // Scrolled all the way down puts the thumb at the bottom of the track
+        let offset = ((track - thumb) as f32 * (1.0 - console.backbuffer_y as f32 / max_scroll as f32)) as i32;
+
+        renderer.set_draw_color(SCROLLBAR_COLOR);
+        renderer.fill_rect(Rect::new(self.area.right() - SCROLLBAR_WIDTH as i32 - 2,
+                                     self.area.y() + offset,
+                                     SCROLLBAR_WIDTH,
+                                     thumb as u32))
             .unwrap();
     }
 
-    fn render_leader(&self, mut renderer: &mut Renderer) {
-        // Render a black background behind it so the buffer scrolling looks
-        // nicer.
-        let rect_y = self.size.1 as i32 - FONT_SIZE as i32 - PADDING;
-        renderer.set_draw_color(Color::RGBA(0, 0, 0, 255));
-        renderer.fill_rect(Rect::new(0, rect_y, self.size.0, rect_y as u32)).unwrap();
-        self.leader.render(&mut renderer);
+    fn render_border(&self, renderer: &mut Renderer) {
+        renderer.set_draw_color(Color::RGBA(255, 255, 255, 255));
+        renderer.draw_rect(self.area).unwrap();
     }
 
-    fn generate_backbuffer_texture(&mut self, mut renderer: &mut Renderer) {
-        let mut main_surface = Surface::new(self.size.0,
-                                            (self.size.1 - (FONT_SIZE as u32)),
-                                            PixelFormatEnum::RGBA8888)
+    /// Renders a black background behind the input line so the buffer
+    /// scrolling looks nicer
+    fn clear_input_line(&self, renderer: &mut Renderer) {
+        renderer.set_draw_color(Color::RGBA(0, 0, 0, 255));
+        renderer.fill_rect(Rect::new(self.area.x(),
+                                     self.input_y(),
+                                     self.area.width(),
+                                     self.atlas.size() as u32 + PADDING as u32))
             .unwrap();
-        let mut counter = 2;
-        // TODO: Make the line render limit here configurable
-        for (index, line) in self.buffer.iter().rev().take(200).enumerate() {
-            // index 0 is the last line, b/c the iterator is reversed. writeln!
-            // outputs a newline at the end of what is written, creating a new
-            // string in the buffer, which we do not want to render
-            if index == 0 && line.is_empty() {
+    }
+
+    fn render_leader(&mut self, color: Color, mut renderer: &mut Renderer) {
+        self.clear_input_line(&mut renderer);
+        let (x, y) = (self.area.x() + PADDING, self.input_y());
+        self.atlas.draw(&mut renderer, "hakka>", x, y, color);
+    }
+
+    /// Draws the rows of the buffer that are on screen, straight from the
+    /// glyph atlas
+    fn render_buffer(&mut self, console: &Console, renderer: &mut Renderer) {
+        let font_size = self.atlas.size() as i32;
+        // Rows scrolled partly under the input line are cut off
+        let height = self.area.height() - self.atlas.size() as u32;
+        renderer.set_clip_rect(Some(Rect::new(self.area.x(), self.area.y(), self.area.width(), height)));
+        let dim = console.config().get_color(Style::Dim);
+
+        for index in (0..self.rows.len()).rev() {
+            let row = self.rows[index];
+            let y = self.row_top(index) + console.backbuffer_y;
+
+            // Only the rows on screen are drawn
+            if y + font_size < self.area.y() {
+                break;
+            }
+            if y >= self.area.y() + height as i32 {
                 continue;
             }
 
-            let y_pos = self.size.1 as i32 - (FONT_SIZE as i32 * counter) + self.backbuffer_y;
-            counter += 1;
+            let spans = console.spans(row.line, row.start, row.end);
+            if spans.iter().any(|&(_, style)| style == Style::Highlight) {
+                renderer.set_draw_color(HIGHLIGHT_BACKGROUND);
+                renderer.fill_rect(Rect::new(self.area.x(), y, self.area.width(), font_size as u32)).unwrap();
+            }
 
-            if line.trim().is_empty() {
-                continue;
+            if row.start > 0 {
+                self.atlas.draw(renderer, WRAP_MARKER, self.area.x() + PADDING, y, dim);
             }
 
-            let surface = self.font
-                .render(line)
-                .blended(FONT_COLOR)
-                .unwrap();
-            surface.blit(None,
-                      &mut main_surface,
-                      Some(Rect::new(PADDING, y_pos - PADDING, self.size.1, FONT_SIZE as u32)))
-                .unwrap();
+            // Each span is drawn in its own color, just after the one before it
+            let mut x = self.row_x(&row);
+            for (text, style) in spans {
+                x += self.atlas.draw(renderer, text, x, y, console.config().get_color(style)) as i32;
+            }
         }
-        let texture = renderer.create_texture_from_surface(&main_surface)
-            .unwrap();
 
-        let TextureQuery { width, height, .. } = texture.query();
-
-        renderer.copy(&texture, None, Some(Rect::new(0, 0, width, height))).unwrap();
+        renderer.set_clip_rect(Some(self.area));
     }
 }
 
-impl<'a> Write for Console<'a> {
+
+impl Write for Console {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // Like stderr, errors are never captured, so they can't be filtered
+        // away or sent to a file
+        if self.style != Style::Error {
+            if let Some(capture) = self.captures.last_mut() {
+                capture.push_str(&String::from_utf8_lossy(buf));
+                return Ok(buf.len());
+            }
+        }
+
+        // Losing the log shouldn't stop the game, so it's dropped on failure
+        let mut failed = false;
+        if let Some(ref mut transcript) = self.transcript {
+            failed = transcript.write_all(buf).is_err();
+        }
+        if failed {
+            self.transcript = None;
+        }
+
         if self.buffer.is_empty() {
             self.buffer.push(String::new());
+            self.styles.push(Vec::new());
         }
 
         let mut text = String::from(std::str::from_utf8(buf).unwrap());
         while !text.is_empty() {
             if let Some(index) = text.find('\n') {
                 let substring: String = text.drain(..index+1).filter(|c| *c != '\n' && *c != '\r').collect();
-
-                if let Some(last) = self.buffer.last_mut() {
-                    last.push_str(&substring);
-                }
+                self.append(&substring);
 
                 self.buffer.push(String::new());
+                self.styles.push(Vec::new());
+                self.revision += 1;
+                self.trim_buffer();
             } else {
-                if let Some(last) = self.buffer.last_mut() {
-                    last.push_str(&text);
-                }
-                text.drain(..);
+                let rest = text.drain(..).collect::<String>();
+                self.append(&rest);
             }
         }
 
@@ -493,3 +1640,400 @@ impl<'a> Write for Console<'a> {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+
+    use sdl2::event::{Event, WindowEvent};
+    use sdl2::keyboard::*;
+    use sdl2::mouse::{MouseButton, MouseState};
+    use sdl2::rect::Rect;
+
+    use config::{Configuration, Dock};
+    use super::{column_at, console_area, wrap_line, Console, Style};
+
+    fn console() -> Console {
+        let mut console = Console::new(Configuration::default());
+        console.visible = true;
+        console
+    }
+
+    /// Types a command and presses ENTER, returning the command to be run
+    fn enter(console: &mut Console, command: &str) -> Option<String> {
+        console.add_text(command);
+        console.commit(true);
+        console.get_next_command()
+    }
+
+    #[test]
+    fn saves_committed_commands_to_the_history() {
+        let history_file = env::temp_dir().join("hakka-history-saves");
+        let _ = fs::remove_file(&history_file);
+        let mut console = console();
+        console.set_history_file(history_file.clone());
+        assert_eq!(enter(&mut console, "  reg  "), Some("reg".into()));
+        assert_eq!(enter(&mut console, "step"), Some("step".into()));
+        assert_eq!(enter(&mut console, "reg"), Some("reg".into()));
+        // A command repeated straight away is only remembered once
+        assert_eq!(enter(&mut console, "reg"), Some("reg".into()));
+        // ENTER on an empty line is passed on, but not remembered
+        assert_eq!(enter(&mut console, ""), Some("".into()));
+        assert_eq!(console.history(), &["reg".to_string(), "step".to_string(), "reg".to_string()]);
+
+        let mut saved = String::new();
+        File::open(&history_file).unwrap().read_to_string(&mut saved).unwrap();
+        assert_eq!(saved, "reg\nstep\nreg\n");
+        fs::remove_file(&history_file).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_history_in_memory_without_a_file() {
+        let mut console = console();
+        enter(&mut console, "reg");
+        assert_eq!(console.history(), &["reg".to_string()]);
+        assert!(console.history_file.is_none());
+        assert!(console.save_config().is_ok());
+    }
+
+    #[test]
+    fn recalls_commands_by_number_or_prefix() {
+        let mut console = console();
+        enter(&mut console, "memdmp 0");
+        enter(&mut console, "memset 10 1");
+        enter(&mut console, "reg");
+
+        assert_eq!(enter(&mut console, "!!"), Some("reg".into()));
+        assert_eq!(enter(&mut console, "!1"), Some("memdmp 0".into()));
+        assert_eq!(enter(&mut console, "!mem"), Some("memdmp 0".into()));
+        assert_eq!(enter(&mut console, "!mems"), Some("memset 10 1".into()));
+        assert_eq!(enter(&mut console, "!"), Some("!".into()));
+
+        assert_eq!(enter(&mut console, "!0"), None);
+        assert_eq!(enter(&mut console, "!99"), None);
+        assert_eq!(enter(&mut console, "!nothing"), None);
+        assert!(console.lines().iter().any(|line| line == "!nothing: event not found"));
+    }
+
+    #[test]
+    fn keeps_history_numbers_when_commands_are_recalled_or_dropped() {
+        let mut console = console();
+        console.config_mut().set_history_size(3);
+        enter(&mut console, "memdmp 0");
+        enter(&mut console, "memset 10 1");
+        enter(&mut console, "reg");
+
+        // Running an older command again doesn't renumber the ones after it
+        assert_eq!(enter(&mut console, "!1"), Some("memdmp 0".into()));
+        assert_eq!(enter(&mut console, "!3"), Some("reg".into()));
+
+        // Once the oldest commands are dropped, the rest keep their numbers
+        assert_eq!(console.history_start(), 3);
+        assert_eq!(console.history(), &["reg".to_string(), "memdmp 0".to_string(), "reg".to_string()]);
+        assert_eq!(enter(&mut console, "!1"), None);
+        assert_eq!(enter(&mut console, "!4"), Some("memdmp 0".into()));
+    }
+
+    #[test]
+    fn completes_the_word_before_the_cursor() {
+        let mut console = console();
+        console.add_text("flags c");
+        console.complete(6, &["carry".to_string()]);
+        assert_eq!(console.input_buffer, "flags carry ");
+        assert_eq!(console.cursor_position, 12);
+
+        // Several candidates extend the word as far as they agree, and are listed
+        console.add_text("o");
+        console.complete(12, &["off".to_string(), "on".to_string()]);
+        assert_eq!(console.input_buffer, "flags carry o");
+        assert!(console.lines().iter().any(|line| line == "off  on"));
+
+        console.input_buffer.clear();
+        console.cursor_position = 0;
+        console.add_text("memd");
+        console.complete(0, &["MEMDMP".to_string(), "memdmpx".to_string()]);
+        assert_eq!(console.input_buffer, "MEMDMP");
+    }
+
+    fn key_down(keycode: Keycode, keymod: Mod) -> Event {
+        Event::KeyDown {
+            timestamp: 1000,
+            window_id: 0,
+            keycode: Some(keycode),
+            scancode: None,
+            keymod: keymod,
+            repeat: false,
+        }
+    }
+
+    /// Presses or lets go of the left mouse button
+    fn mouse_button(down: bool, x: i32, y: i32) -> Event {
+        if down {
+            Event::MouseButtonDown {
+                timestamp: 0,
+                window_id: 0,
+                which: 0,
+                mouse_btn: MouseButton::Left,
+                x: x,
+                y: y,
+            }
+        } else {
+            Event::MouseButtonUp {
+                timestamp: 0,
+                window_id: 0,
+                which: 0,
+                mouse_btn: MouseButton::Left,
+                x: x,
+                y: y,
+            }
+        }
+    }
+
+    fn mouse_motion(x: i32, y: i32) -> Event {
+        Event::MouseMotion {
+            timestamp: 0,
+            window_id: 0,
+            which: 0,
+            mousestate: MouseState::from_sdl_state(0),
+            x: x,
+            y: y,
+            xrel: 0,
+            yrel: 0,
+        }
+    }
+
+    #[test]
+    fn selects_with_the_mouse_and_asks_to_copy() {
+        let mut console = console();
+        console.process(&mouse_button(true, 20, 40));
+        console.process(&mouse_motion(80, 90));
+        console.process(&mouse_button(false, 80, 90));
+        // Moving the mouse after letting go leaves the selection alone
+        console.process(&mouse_motion(100, 100));
+        let selection = console.selection.unwrap();
+        assert_eq!((selection.start, selection.end), ((20, 40), (80, 90)));
+
+        assert!(!console.take_copy_request());
+        console.process(&key_down(Keycode::C, LCTRLMOD | LSHIFTMOD));
+        assert!(console.take_copy_request());
+        assert!(!console.take_copy_request());
+        // Ctrl+C on its own cancels the input line instead
+        console.process(&key_down(Keycode::C, LCTRLMOD));
+        assert!(!console.take_copy_request());
+    }
+
+    #[test]
+    fn copies_the_text_between_two_positions() {
+        let mut console = console();
+        write!(console, "memdmp 0\n00 01 02\nreg").unwrap();
+
+        let spans = console.spans_between((1, 3), (0, 7));
+        assert_eq!(spans, vec![(0, 7, 8), (1, 0, 3)]);
+        assert_eq!(console.text_of(&spans), "0\n00 ");
+        assert_eq!(console.text_of(&console.spans_between((0, 0), (2, 3))), "memdmp 0\n00 01 02\nreg");
+        assert_eq!(console.text_of(&console.spans_between((2, 1), (2, 1))), "");
+    }
+
+    #[test]
+    fn finds_the_character_under_a_point() {
+        let width_of = |text: &str| text.chars().count() as u32 * 10;
+        assert_eq!(column_at("reg", 0, width_of), 0);
+        assert_eq!(column_at("reg", 9, width_of), 0);
+        assert_eq!(column_at("reg", 10, width_of), 1);
+        assert_eq!(column_at("reg", 35, width_of), 3);
+        assert_eq!(column_at("é1", 15, width_of), 2);
+    }
+
+    #[test]
+    fn splits_lines_into_styled_spans() {
+        let mut console = console();
+        write!(console.styled(Style::Dim), "C000: ").unwrap();
+        write!(console, "A9 ").unwrap();
+        write!(console.styled(Style::Highlight), "LDA ").unwrap();
+        write!(console.styled(Style::Error), "#$").unwrap();
+        write!(console.styled(Style::Highlight), "01").unwrap();
+        // Each style only lasts as long as its writer
+        writeln!(console, "").unwrap();
+        writeln!(console.styled(Style::Warning), "Breakpoint").unwrap();
+
+        assert_eq!(console.lines()[0], "C000: A9 LDA #$01");
+        assert_eq!(console.spans(0, 0, 17),
+                   vec![("C000: ", Style::Dim),
+                        ("A9 ", Style::Normal),
+                        ("LDA ", Style::Highlight),
+                        ("#$", Style::Error),
+                        ("01", Style::Highlight)]);
+        // Only the part of each span between the offsets is returned
+        assert_eq!(console.spans(0, 3, 11),
+                   vec![("0: ", Style::Dim), ("A9 ", Style::Normal), ("LD", Style::Highlight)]);
+        assert_eq!(console.spans(1, 0, 10), vec![("Breakpoint", Style::Warning)]);
+        assert_eq!(console.spans(2, 0, 0), vec![]);
+    }
+
+    #[test]
+    fn never_captures_errors() {
+        let mut console = console();
+        console.begin_capture();
+        writeln!(console, "captured").unwrap();
+        writeln!(console.styled(Style::Error), "Unable to read").unwrap();
+        assert_eq!(console.end_capture(), "captured\n");
+        assert_eq!(console.lines(), &["Unable to read".to_string(), "".to_string()]);
+        assert_eq!(console.spans(0, 0, 14), vec![("Unable to read", Style::Error)]);
+    }
+
+    #[test]
+    fn docks_to_an_edge_of_the_window() {
+        let mut config = Configuration::default();
+        assert_eq!(console_area(&config, (800, 600)), Rect::new(0, 0, 400, 600));
+
+        config.set_console_size(25);
+        config.set_console_dock(Dock::Right);
+        assert_eq!(console_area(&config, (800, 600)), Rect::new(600, 0, 200, 600));
+        config.set_console_dock(Dock::Top);
+        assert_eq!(console_area(&config, (800, 600)), Rect::new(0, 0, 800, 150));
+        config.set_console_dock(Dock::Bottom);
+        assert_eq!(console_area(&config, (800, 600)), Rect::new(0, 450, 800, 150));
+
+        // The console never gets too small to use, or bigger than the window
+        config.set_console_size(0);
+        assert_eq!(console_area(&config, (800, 600)), Rect::new(0, 480, 800, 120));
+        config.set_console_size(150);
+        assert_eq!(console_area(&config, (800, 600)), Rect::new(0, 0, 800, 600));
+    }
+
+    #[test]
+    fn resizes_by_dragging_its_inner_edge() {
+        let mut console = console();
+        console.config_mut().set_console_dock(Dock::Bottom);
+        console.process(&Event::Window {
+            timestamp: 0,
+            window_id: 0,
+            win_event: WindowEvent::Resized(800, 600),
+        });
+
+        // The bottom half of the window, so the edge is at y = 300
+        console.process(&mouse_button(true, 400, 302));
+        console.process(&mouse_motion(400, 150));
+        console.process(&mouse_button(false, 400, 150));
+        assert_eq!(console.config().get_console_size(), 75);
+
+        // Further from the edge, a click starts a selection instead
+        console.process(&mouse_button(true, 400, 300));
+        console.process(&mouse_motion(400, 500));
+        assert_eq!(console.config().get_console_size(), 75);
+        assert!(console.selection.is_some());
+
+        // A pane is sized by its layout, so it can't be dragged
+        console.set_pane(Some(Rect::new(0, 0, 800, 150)));
+        console.process(&mouse_button(true, 400, 150));
+        console.process(&mouse_motion(400, 0));
+        assert_eq!(console.config().get_console_size(), 75);
+    }
+
+    #[test]
+    fn wraps_long_lines_after_whitespace() {
+        // Every character is 10 pixels wide, so the first row fits 8
+        let width_of = |text: &str| text.chars().count() as u32 * 10;
+        assert_eq!(wrap_line("memdmp 0", 80, 20, width_of), vec![(0, 8)]);
+        assert_eq!(wrap_line("", 80, 20, width_of), vec![(0, 0)]);
+        // Rows after the first are narrower, to make room for the marker
+        assert_eq!(wrap_line("00 01 02 03 04 05", 80, 20, width_of), vec![(0, 6), (6, 12), (12, 17)]);
+        // A word longer than a row is broken wherever it runs out of room
+        assert_eq!(wrap_line("0123456789AB", 80, 20, width_of), vec![(0, 8), (8, 12)]);
+        assert_eq!(wrap_line("ab 0123456789", 80, 20, width_of), vec![(0, 3), (3, 9), (9, 13)]);
+    }
+
+    #[test]
+    fn wraps_at_least_a_character_a_row() {
+        let width_of = |text: &str| text.chars().count() as u32 * 10;
+        assert_eq!(wrap_line("éé", 5, 0, width_of), vec![(0, 2), (2, 4)]);
+        assert_eq!(wrap_line("ab", 10, 20, width_of), vec![(0, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn navigates_the_history() {
+        let mut console = console();
+        enter(&mut console, "one");
+        enter(&mut console, "two");
+
+        console.history_navigate_back();
+        assert_eq!(console.input_buffer, "two");
+        console.history_navigate_back();
+        assert_eq!(console.input_buffer, "one");
+        console.history_navigate_back();
+        assert_eq!(console.input_buffer, "one");
+        console.history_navigate_forward();
+        assert_eq!(console.input_buffer, "two");
+        assert_eq!(console.cursor_position, 3);
+    }
+
+    #[test]
+    fn searches_back_through_the_history() {
+        let mut console = console();
+        enter(&mut console, "memdmp 0");
+        enter(&mut console, "reg");
+        enter(&mut console, "memset 10 1");
+        console.add_text("typed");
+
+        console.begin_search();
+        console.search_add_text("mem");
+        assert_eq!(console.input_buffer, "memset 10 1");
+        console.search_next();
+        assert_eq!(console.input_buffer, "memdmp 0");
+        console.end_search(false);
+        assert_eq!(console.input_buffer, "typed");
+
+        console.begin_search();
+        console.search_add_text("re");
+        console.end_search(true);
+        assert_eq!(console.input_buffer, "reg");
+    }
+
+    #[test]
+    fn edits_the_input_line() {
+        let mut console = console();
+        console.add_text("memset 10 1");
+        console.cursor_left();
+        console.backspace();
+        assert_eq!(console.input_buffer, "memset 101");
+        console.add_text(" 2");
+        assert_eq!(console.input_buffer, "memset 10 21");
+
+        console.word_left();
+        assert_eq!(console.cursor_position, 10);
+        console.word_left();
+        assert_eq!(console.cursor_position, 7);
+        console.word_right();
+        assert_eq!(console.cursor_position, 9);
+
+        console.overwrite = true;
+        console.add_text("FF");
+        assert_eq!(console.input_buffer, "memset 10FF1");
+    }
+
+    #[test]
+    fn kills_and_yanks_words() {
+        let mut console = console();
+        console.add_text("memset 10 1");
+        console.kill_word_back();
+        assert_eq!(console.input_buffer, "memset 10 ");
+        console.kill_word_back();
+        assert_eq!(console.input_buffer, "memset ");
+
+        console.cursor_position = 0;
+        console.yank();
+        assert_eq!(console.input_buffer, "10 memset ");
+        assert_eq!(console.cursor_position, 3);
+    }
+
+    #[test]
+    fn edits_multibyte_input() {
+        let mut console = console();
+        console.add_text("é1");
+        console.cursor_left();
+        console.backspace();
+        assert_eq!(console.input_buffer, "1");
+        assert_eq!(console.cursor_position, 0);
+    }
+}
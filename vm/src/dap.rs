@@ -0,0 +1,695 @@
+//! A Debug Adapter Protocol server, so that editors can debug a level's
+//! assembly source directly.
+//!
+//! Like the GDB stub, the server is polled once per VM cycle and never blocks.
+//! Editors connect to it over localhost TCP (a `debugServer` port in VS Code's
+//! launch configuration, for example). There is a single thread. Its frames
+//! come from the VM's shadow call stack, and registers, flags and the zero
+//! page are exposed as variables.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use rustc_serialize::base64::{STANDARD, ToBase64};
+use rustc_serialize::json::Json;
+
+use command::{evaluate, format_value};
+use vm::VirtualMachine;
+
+const THREAD_ID: i64 = 1;
+
+const REGISTERS_REFERENCE: i64 = 1;
+const FLAGS_REFERENCE: i64 = 2;
+const ZERO_PAGE_REFERENCE: i64 = 3;
+
+pub struct DapServer {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    buffer: Vec<u8>,
+    seq: i64,
+    /// Set once the client has attached and expects to hear about stops
+    attached: bool,
+    /// Whether the client believes the VM is running
+    running: bool,
+    stepping: bool,
+    /// Addresses of the breakpoints the client added. Ones that were already
+    /// set, from the console for example, aren't the client's to remove.
+    breakpoints: Vec<usize>,
+}
+
+impl DapServer {
+    /// Starts listening on the given localhost port
+    pub fn bind(port: u16) -> io::Result<DapServer> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(DapServer {
+            listener: listener,
+            client: None,
+            buffer: Vec::new(),
+            seq: 1,
+            attached: false,
+            running: false,
+            stepping: false,
+            breakpoints: Vec::new(),
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.listener.local_addr().map(|addr| addr.port()).unwrap_or(0)
+    }
+
+    /// Accepts a pending connection, handles any requests that have arrived and
+    /// tells the client when the VM stops or resumes.
+    pub fn poll(&mut self, vm: &mut VirtualMachine) {
+        if self.client.is_none() {
+            if let Ok((stream, addr)) = self.listener.accept() {
+                if stream.set_nonblocking(true).is_ok() {
+                    writeln!(vm.console, "Debug adapter client connected from {}", addr).unwrap();
+                    self.client = Some(stream);
+                    self.buffer.clear();
+                    self.attached = false;
+                }
+            }
+        }
+
+        if !self.receive() {
+            writeln!(vm.console, "Debug adapter client disconnected").unwrap();
+            self.disconnect(vm);
+            return;
+        }
+
+        while let Some(request) = self.next_message() {
+            self.handle(&request, vm);
+        }
+
+        if !self.attached {
+            return;
+        }
+
+        if self.running && vm.is_debugging() && !vm.is_stepping() {
+            let reason = if self.stepping {
+                "step"
+            } else if vm.is_breakpoint(vm.cpu.registers.PC as usize) {
+                "breakpoint"
+            } else {
+                "pause"
+            };
+            self.running = false;
+            self.stepping = false;
+            self.send_event("stopped",
+                            object(vec![("reason", string(reason)),
+                                        ("threadId", Json::I64(THREAD_ID)),
+                                        ("allThreadsStopped", Json::Boolean(true))]));
+        } else if !self.running && !vm.is_debugging() {
+            // Execution was resumed from the in-game console
+            self.running = true;
+            self.send_event("continued",
+                            object(vec![("threadId", Json::I64(THREAD_ID)),
+                                        ("allThreadsContinued", Json::Boolean(true))]));
+        }
+    }
+
+    /// Reads whatever is available from the client. Returns false if the
+    /// connection was closed.
+    fn receive(&mut self) -> bool {
+        let mut closed = false;
+        if let Some(ref mut client) = self.client {
+            let mut chunk = [0; 4096];
+            loop {
+                match client.read(&mut chunk) {
+                    Ok(0) => {
+                        closed = true;
+                        break;
+                    }
+                    Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(_) => {
+                        closed = true;
+                        break;
+                    }
+                }
+            }
+        }
+        !closed
+    }
+
+    /// Pulls the next complete `Content-Length` framed message off the buffer
+    fn next_message(&mut self) -> Option<Json> {
+        loop {
+            let header_end = match self.buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+                Some(header_end) => header_end,
+                None => return None,
+            };
+
+            let length = {
+                let header = String::from_utf8_lossy(&self.buffer[..header_end]);
+                header.lines()
+                    .filter_map(|line| {
+                        let mut parts = line.splitn(2, ':');
+                        match (parts.next(), parts.next()) {
+                            (Some(name), Some(value)) if name.trim() == "Content-Length" => {
+                                value.trim().parse::<usize>().ok()
+                            }
+                            _ => None,
+                        }
+                    })
+                    .next()
+            };
+
+            let body_start = header_end + 4;
+            let length = match length {
+                Some(length) => length,
+                None => {
+                    // Not a header we understand, skip it
+                    self.buffer.drain(..body_start);
+                    continue;
+                }
+            };
+
+            if self.buffer.len() < body_start + length {
+                return None;
+            }
+
+            let message = self.buffer.drain(..body_start + length).skip(body_start).collect::<Vec<_>>();
+            if let Ok(json) = Json::from_str(&String::from_utf8_lossy(&message)) {
+                return Some(json);
+            }
+        }
+    }
+
+    fn handle(&mut self, request: &Json, vm: &mut VirtualMachine) {
+        let command = request.find("command").and_then(|c| c.as_string()).unwrap_or("").to_string();
+        let empty = Json::Object(BTreeMap::new());
+        let arguments = request.find("arguments").unwrap_or(&empty);
+
+        let result = match &command[..] {
+            "initialize" => {
+                let capabilities = object(vec![
+                    ("supportsConfigurationDoneRequest", Json::Boolean(true)),
+                    ("supportsReadMemoryRequest", Json::Boolean(true)),
+                    ("supportsEvaluateForHovers", Json::Boolean(true)),
+                ]);
+                self.respond(request, Ok(capabilities));
+                self.send_event("initialized", Json::Null);
+                return;
+            }
+            "launch" | "attach" => {
+                self.attached = true;
+                self.running = !vm.is_debugging();
+                Ok(Json::Null)
+            }
+            "setBreakpoints" => self.set_breakpoints(arguments, vm),
+            "setExceptionBreakpoints" => Ok(object(vec![("breakpoints", Json::Array(Vec::new()))])),
+            "configurationDone" => Ok(Json::Null),
+            "threads" => {
+                let thread = object(vec![("id", Json::I64(THREAD_ID)), ("name", string("6502"))]);
+                Ok(object(vec![("threads", Json::Array(vec![thread]))]))
+            }
+            "stackTrace" => Ok(stack_trace(vm)),
+            "scopes" => Ok(scopes()),
+            "variables" => {
+                let reference = arguments.find("variablesReference").and_then(|r| r.as_i64()).unwrap_or(0);
+                Ok(object(vec![("variables", Json::Array(variables(reference, vm)))]))
+            }
+            "readMemory" => read_memory(arguments, vm),
+            "evaluate" => evaluate_expression(arguments, vm),
+            "continue" => {
+                vm.continue_execution();
+                self.running = true;
+                Ok(object(vec![("allThreadsContinued", Json::Boolean(true))]))
+            }
+            "stepIn" => {
+                vm.step_execution();
+                self.running = true;
+                self.stepping = true;
+                Ok(Json::Null)
+            }
+            // Steps over a JSR by running until its RTS
+            "next" => {
+                vm.step_over_execution();
+                self.running = true;
+                self.stepping = true;
+                Ok(Json::Null)
+            }
+            "stepOut" => {
+                if vm.step_out_execution() {
+                    self.running = true;
+                    self.stepping = true;
+                    Ok(Json::Null)
+                } else {
+                    Err("Not inside a subroutine".into())
+                }
+            }
+            "pause" => {
+                vm.break_execution();
+                Ok(Json::Null)
+            }
+            "disconnect" => {
+                self.respond(request, Ok(Json::Null));
+                writeln!(vm.console, "Debug adapter client detached").unwrap();
+                self.disconnect(vm);
+                return;
+            }
+            _ => Err(format!("Unsupported request '{}'", command)),
+        };
+
+        self.respond(request, result);
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Json, vm: &mut VirtualMachine) -> Result<Json, String> {
+        let path = arguments.find_path(&["source", "path"]).and_then(|p| p.as_string()).unwrap_or("");
+        let lines = arguments.find("breakpoints")
+            .and_then(|b| b.as_array())
+            .map(|breakpoints| {
+                breakpoints.iter()
+                    .filter_map(|b| b.find("line").and_then(|l| l.as_u64()))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or(Vec::new());
+
+        // Breakpoints always arrive as the full set for a source file
+        for address in self.breakpoints.drain(..) {
+            if vm.is_breakpoint(address) {
+                vm.toggle_breakpoint(address);
+            }
+        }
+
+        let is_level_source = match vm.source_path() {
+            Some(source_path) => same_file(source_path, Path::new(path)),
+            None => false,
+        };
+
+        let mut results = Vec::new();
+        for line in lines {
+            let location = if is_level_source {
+                vm.symbols.address_of_line(line as usize)
+            } else {
+                None
+            };
+
+            match location {
+                Some((actual_line, address)) => {
+                    let address = address as usize;
+                    if !vm.is_breakpoint(address) {
+                        vm.toggle_breakpoint(address);
+                        self.breakpoints.push(address);
+                    }
+                    results.push(object(vec![("verified", Json::Boolean(true)),
+                                             ("line", Json::U64(actual_line as u64))]));
+                }
+                None => {
+                    results.push(object(vec![("verified", Json::Boolean(false)),
+                                             ("line", Json::U64(line)),
+                                             ("message", string("No instruction at or after this line"))]));
+                }
+            }
+        }
+
+        Ok(object(vec![("breakpoints", Json::Array(results))]))
+    }
+
+    /// Removes the breakpoints the client added and lets the VM carry on running
+    fn disconnect(&mut self, vm: &mut VirtualMachine) {
+        for address in self.breakpoints.drain(..) {
+            if vm.is_breakpoint(address) {
+                vm.toggle_breakpoint(address);
+            }
+        }
+        if self.attached {
+            vm.continue_execution();
+        }
+        self.client = None;
+        self.attached = false;
+        self.running = false;
+        self.stepping = false;
+    }
+
+    fn respond(&mut self, request: &Json, result: Result<Json, String>) {
+        let request_seq = request.find("seq").and_then(|s| s.as_i64()).unwrap_or(0);
+        let command = request.find("command").cloned().unwrap_or(Json::Null);
+
+        let mut fields = vec![("type", string("response")),
+                              ("request_seq", Json::I64(request_seq)),
+                              ("command", command)];
+        match result {
+            Ok(body) => {
+                fields.push(("success", Json::Boolean(true)));
+                if body != Json::Null {
+                    fields.push(("body", body));
+                }
+            }
+            Err(message) => {
+                fields.push(("success", Json::Boolean(false)));
+                fields.push(("message", string(message)));
+            }
+        }
+        self.send(object(fields));
+    }
+
+    fn send_event(&mut self, event: &str, body: Json) {
+        let mut fields = vec![("type", string("event")), ("event", string(event))];
+        if body != Json::Null {
+            fields.push(("body", body));
+        }
+        self.send(object(fields));
+    }
+
+    fn send(&mut self, message: Json) {
+        let mut message = message;
+        if let Json::Object(ref mut fields) = message {
+            fields.insert("seq".into(), Json::I64(self.seq));
+        }
+        self.seq += 1;
+
+        let body = message.to_string();
+        let packet = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let mut data = packet.as_bytes();
+
+        let mut failed = false;
+        if let Some(ref mut client) = self.client {
+            while !data.is_empty() {
+                match client.write(data) {
+                    Ok(n) => data = &data[n..],
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                    Err(_) => {
+                        failed = true;
+                        break;
+                    }
+                }
+            }
+        }
+        if failed {
+            self.client = None;
+        }
+    }
+}
+
+/// The innermost frame is the program counter, followed by the call site of
+/// each subroutine on the shadow stack.
+fn stack_trace(vm: &VirtualMachine) -> Json {
+    let call_stack = vm.call_stack();
+    let mut locations = vec![(vm.cpu.registers.PC, call_stack.last().map(|frame| frame.target))];
+    for (index, frame) in call_stack.iter().enumerate().rev() {
+        let routine = if index > 0 { Some(call_stack[index - 1].target) } else { None };
+        locations.push((frame.call_site, routine));
+    }
+
+    let source = vm.source_path().map(|path| {
+        object(vec![("name", string(path.file_name().unwrap_or_default().to_string_lossy())),
+                    ("path", string(path.to_string_lossy()))])
+    });
+
+    let frames = locations.iter()
+        .enumerate()
+        .map(|(id, &(address, routine))| {
+            let name = routine.and_then(|routine| vm.symbols.label_at(routine))
+                .map(String::from)
+                .unwrap_or(format!("${:04X}", address));
+            let line = vm.symbols.line_of_address(address).unwrap_or(0);
+
+            let mut fields = vec![("id", Json::I64(id as i64)),
+                                  ("name", string(name)),
+                                  ("line", Json::U64(line as u64)),
+                                  ("column", Json::U64(if line > 0 { 1 } else { 0 })),
+                                  ("instructionPointerReference", string(format!("0x{:04X}", address)))];
+            if let Some(ref source) = source {
+                if line > 0 {
+                    fields.push(("source", source.clone()));
+                }
+            }
+            object(fields)
+        })
+        .collect::<Vec<_>>();
+
+    let total = frames.len() as i64;
+    object(vec![("stackFrames", Json::Array(frames)), ("totalFrames", Json::I64(total))])
+}
+
+/// Registers are global, so every frame has the same scopes
+fn scopes() -> Json {
+    let scope = |name: &str, reference: i64| {
+        object(vec![("name", string(name)),
+                    ("variablesReference", Json::I64(reference)),
+                    ("expensive", Json::Boolean(false))])
+    };
+
+    object(vec![("scopes",
+                 Json::Array(vec![scope("Registers", REGISTERS_REFERENCE),
+                                  scope("Flags", FLAGS_REFERENCE),
+                                  scope("Zero Page", ZERO_PAGE_REFERENCE)]))])
+}
+
+fn variables(reference: i64, vm: &VirtualMachine) -> Vec<Json> {
+    let variable = |name: String, value: String| {
+        object(vec![("name", string(name)),
+                    ("value", string(value)),
+                    ("variablesReference", Json::I64(0))])
+    };
+
+    match reference {
+        REGISTERS_REFERENCE => {
+            let registers = &vm.cpu.registers;
+            vec![variable("A".into(), format!("${:02X} ({})", registers.A, registers.A)),
+                 variable("X".into(), format!("${:02X} ({})", registers.X, registers.X)),
+                 variable("Y".into(), format!("${:02X} ({})", registers.Y, registers.Y)),
+                 variable("S".into(), format!("${:02X}", vm.cpu.stack.pointer)),
+                 variable("PC".into(), format!("${:04X}", registers.PC))]
+        }
+        FLAGS_REFERENCE => {
+            let flags = &vm.cpu.flags;
+            vec![variable("Carry".into(), flags.carry.to_string()),
+                 variable("Zero".into(), flags.zero.to_string()),
+                 variable("Interrupts disabled".into(), flags.interrupt_disabled.to_string()),
+                 variable("Decimal mode".into(), flags.decimal.to_string()),
+                 variable("Break".into(), flags.breakpoint.to_string()),
+                 variable("Overflow".into(), flags.overflow.to_string()),
+                 variable("Sign".into(), flags.sign.to_string())]
+        }
+        ZERO_PAGE_REFERENCE => {
+            vm.cpu.memory[0x00..0x100]
+                .chunks(16)
+                .enumerate()
+                .map(|(row, chunk)| {
+                    let bytes = chunk.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>();
+                    let mut row = variable(format!("${:02X}", row * 16), bytes.join(" "));
+                    if let Json::Object(ref mut fields) = row {
+                        fields.insert("memoryReference".into(), string("0x0000"));
+                    }
+                    row
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Evaluates an expression the way the console's print command does
+fn evaluate_expression(arguments: &Json, vm: &VirtualMachine) -> Result<Json, String> {
+    let expression = arguments.find("expression").and_then(|e| e.as_string()).unwrap_or("");
+    match evaluate(expression, vm) {
+        Ok(value) => Ok(object(vec![("result", string(format_value(value))), ("variablesReference", Json::I64(0))])),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn read_memory(arguments: &Json, vm: &VirtualMachine) -> Result<Json, String> {
+    let reference = arguments.find("memoryReference").and_then(|r| r.as_string()).unwrap_or("");
+    let base = if reference.starts_with("0x") {
+        i64::from_str_radix(&reference[2..], 16).ok()
+    } else {
+        reference.parse::<i64>().ok()
+    };
+    let base = match base {
+        Some(base) => base,
+        None => return Err(format!("Invalid memory reference '{}'", reference)),
+    };
+
+    let offset = arguments.find("offset").and_then(|o| o.as_i64()).unwrap_or(0);
+    let count = arguments.find("count").and_then(|c| c.as_i64()).unwrap_or(0);
+
+    let start = base + offset;
+    let (start, end) = if start < 0 || start > 0xFFFF {
+        (0, 0)
+    } else {
+        (start as usize, ::std::cmp::min(start + count.max(0), 0x10000) as usize)
+    };
+
+    let data = vm.cpu.memory[start..end].to_base64(STANDARD);
+    Ok(object(vec![("address", string(format!("0x{:04X}", start))), ("data", string(data))]))
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a.file_name() == b.file_name(),
+    }
+}
+
+fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(fields.into_iter().map(|(name, value)| (name.to_string(), value)).collect())
+}
+
+fn string<S>(value: S) -> Json
+    where S: Into<String>
+{
+    Json::String(value.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use rs6502::CodeSegment;
+    use rustc_serialize::json::Json;
+
+    use vm::VirtualMachine;
+    use super::{evaluate_expression, object, stack_trace, string, DapServer};
+
+    const SOURCE: &'static str = ".ORG $C000
+Start:
+  JSR Sub
+  NOP
+  RTS
+Sub:
+  NOP
+  RTS
+";
+
+    fn server(buffer: &[u8]) -> DapServer {
+        let mut server = DapServer::bind(0).unwrap();
+        server.buffer.extend_from_slice(buffer);
+        server
+    }
+
+    fn frame(body: &str) -> Vec<u8> {
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes()
+    }
+
+    /// A VM running SOURCE, with its symbols read from a temporary file
+    fn vm(name: &str) -> (VirtualMachine, PathBuf) {
+        let path = env::temp_dir().join(format!("hakka-dap-{}.asm", name));
+        File::create(&path).unwrap().write_all(SOURCE.as_bytes()).unwrap();
+
+        let mut vm = VirtualMachine::for_tests();
+        vm.set_step_listing(false);
+        vm.load_code_segments(vec![CodeSegment {
+                                       address: 0xC000,
+                                       code: vec![0x20, 0x05, 0xC0, 0xEA, 0x60, 0xEA, 0x60],
+                                   }]);
+        vm.load_symbols(&path).unwrap();
+        vm.break_execution();
+        (vm, path)
+    }
+
+    fn set_breakpoints(server: &mut DapServer, vm: &mut VirtualMachine, path: &PathBuf, lines: &[u64]) -> Json {
+        let breakpoints = lines.iter().map(|&line| object(vec![("line", Json::U64(line))])).collect();
+        let arguments = object(vec![("source", object(vec![("path", string(path.to_string_lossy()))])),
+                                    ("breakpoints", Json::Array(breakpoints))]);
+        server.set_breakpoints(&arguments, vm).unwrap()
+    }
+
+    #[test]
+    fn reads_framed_messages() {
+        let mut buffer = frame("{\"seq\":1,\"command\":\"threads\"}");
+        buffer.extend(frame("{\"seq\":2,\"command\":\"scopes\"}"));
+        let mut server = server(&buffer);
+
+        let first = server.next_message().unwrap();
+        assert_eq!(first.find("command").and_then(|c| c.as_string()), Some("threads"));
+        let second = server.next_message().unwrap();
+        assert_eq!(second.find("seq").and_then(|s| s.as_i64()), Some(2));
+        assert!(server.next_message().is_none());
+    }
+
+    #[test]
+    fn waits_for_the_whole_message() {
+        let message = frame("{\"seq\":1,\"command\":\"threads\"}");
+        let (header, body) = message.split_at(10);
+        let mut server = server(header);
+        assert!(server.next_message().is_none());
+
+        server.buffer.extend_from_slice(&body[..body.len() - 1]);
+        assert!(server.next_message().is_none());
+
+        server.buffer.push(*body.last().unwrap());
+        assert!(server.next_message().is_some());
+        assert!(server.buffer.is_empty());
+    }
+
+    #[test]
+    fn skips_headers_without_a_length() {
+        let mut buffer = b"Content-Type: text/plain\r\n\r\n".to_vec();
+        buffer.extend(frame("{\"seq\":3}"));
+        let mut server = server(&buffer);
+        assert_eq!(server.next_message().unwrap().find("seq").and_then(|s| s.as_i64()), Some(3));
+    }
+
+    #[test]
+    fn only_removes_the_breakpoints_it_added() {
+        let (mut vm, path) = vm("breakpoints");
+        let mut server = DapServer::bind(0).unwrap();
+        // Set from the console before the client attached
+        vm.toggle_breakpoint(0xC003);
+
+        let reply = set_breakpoints(&mut server, &mut vm, &path, &[2, 4, 7, 20]);
+        let breakpoints = reply.find("breakpoints").and_then(|b| b.as_array()).unwrap();
+        let verified = breakpoints.iter()
+            .map(|b| {
+                (b.find("verified").and_then(|v| v.as_boolean()).unwrap(),
+                 b.find("line").and_then(|l| l.as_u64()).unwrap())
+            })
+            .collect::<Vec<_>>();
+        // A line without an instruction moves down to the next one
+        assert_eq!(verified, vec![(true, 3), (true, 4), (true, 7), (false, 20)]);
+        assert!(vm.is_breakpoint(0xC000));
+        assert!(vm.is_breakpoint(0xC005));
+        assert_eq!(server.breakpoints, vec![0xC000, 0xC005]);
+
+        // Breakpoints arrive as the full set, so an empty set clears the client's
+        set_breakpoints(&mut server, &mut vm, &path, &[]);
+        assert!(!vm.is_breakpoint(0xC000));
+        assert!(!vm.is_breakpoint(0xC005));
+        assert!(vm.is_breakpoint(0xC003));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn builds_the_stack_trace_from_the_shadow_stack() {
+        let (mut vm, path) = vm("stack");
+        vm.step_execution();
+        vm.cycle();
+        assert_eq!(vm.cpu.registers.PC, 0xC005);
+
+        let trace = stack_trace(&vm);
+        let frames = trace.find("stackFrames").and_then(|f| f.as_array()).unwrap();
+        let names = frames.iter().map(|f| f.find("name").and_then(|n| n.as_string()).unwrap()).collect::<Vec<_>>();
+        let lines = frames.iter().map(|f| f.find("line").and_then(|l| l.as_u64()).unwrap()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["Sub", "$C000"]);
+        assert_eq!(lines, vec![7, 3]);
+        assert_eq!(trace.find("totalFrames").and_then(|t| t.as_i64()), Some(2));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn evaluates_like_print() {
+        let vm = VirtualMachine::for_tests();
+        let result = |expression: &str| {
+            let arguments = object(vec![("expression", string(expression))]);
+            evaluate_expression(&arguments, &vm)
+                .map(|body| body.find("result").and_then(|r| r.as_string()).unwrap().to_string())
+        };
+        assert_eq!(result("Y_0 + 1"), Ok("$0201  #513  %0000001000000001".into()));
+        assert_eq!(result("-1"), Ok("$FFFF  #-1  %1111111111111111".into()));
+        assert!(result("NOWHERE").is_err());
+    }
+}
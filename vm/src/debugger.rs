@@ -0,0 +1,269 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Renderer};
+use sdl2::ttf::Sdl2TtfContext;
+
+use atlas::GlyphAtlas;
+use console::Style;
+use vm::VirtualMachine;
+
+const PADDING: i32 = 10;
+const BORDER_COLOR: Color = Color::RGBA(255, 255, 255, 255);
+const CURRENT_LINE_BACKGROUND: Color = Color::RGBA(255, 255, 255, 40);
+/// How many bytes each row of the memory pane shows
+const MEMORY_ROW: usize = 8;
+
+/// Where each pane of the debugger layout goes in the window. The code,
+/// registers and memory are side by side above the console.
+pub struct Panes {
+    pub disassembly: Rect,
+    pub registers: Rect,
+    pub memory: Rect,
+    pub console: Rect,
+}
+
+impl Panes {
+    pub fn new((width, height): (u32, u32)) -> Panes {
+        let top = height * 3 / 5;
+        let disassembly = width * 2 / 5;
+        let registers = width / 5;
+
+        Panes {
+            disassembly: Rect::new(0, 0, disassembly, top),
+            registers: Rect::new(disassembly as i32, 0, registers, top),
+            memory: Rect::new((disassembly + registers) as i32, 0, width - disassembly - registers, top),
+            console: Rect::new(0, top as i32, width, height - top),
+        }
+    }
+}
+
+/// Draws the disassembly around the program counter, the registers and
+/// flags, and a page of memory, each in a pane of its own. The console is
+/// given the rest of the window.
+pub struct DebuggerRenderer<'a> {
+    font_file: &'a str,
+    ttf_context: &'a Sdl2TtfContext,
+    atlas: GlyphAtlas<'a>,
+}
+
+impl<'a> DebuggerRenderer<'a> {
+    pub fn new(ttf_context: &'a Sdl2TtfContext,
+               renderer: &Renderer,
+               font_file: &'a str,
+               font_size: u16)
+               -> DebuggerRenderer<'a> {
+        DebuggerRenderer {
+            font_file: font_file,
+            ttf_context: ttf_context,
+            atlas: GlyphAtlas::new(ttf_context, renderer, font_file, font_size),
+        }
+    }
+
+    /// Draws the panes from the current state of the VM, and returns the
+    /// pane left for the console
+    pub fn render(&mut self, vm: &VirtualMachine, renderer: &mut Renderer) -> Rect {
+        // The panes use the same font size as the console
+        let font_size = vm.console.config().get_console_font_size();
+        if font_size != self.atlas.size() {
+            self.atlas = GlyphAtlas::new(self.ttf_context, renderer, self.font_file, font_size);
+        }
+
+        let panes = Panes::new(renderer.window().unwrap().size());
+        renderer.set_blend_mode(BlendMode::Blend);
+
+        self.begin_pane(vm, renderer, panes.disassembly, "Disassembly");
+        self.render_disassembly(vm, renderer, panes.disassembly);
+        self.end_pane(renderer, panes.disassembly);
+
+        self.begin_pane(vm, renderer, panes.registers, "Registers");
+        self.render_registers(vm, renderer, panes.registers);
+        self.end_pane(renderer, panes.registers);
+
+        let title = format!("Memory at {:04X}", vm.memory_view());
+        self.begin_pane(vm, renderer, panes.memory, &title);
+        self.render_memory(vm, renderer, panes.memory);
+        self.end_pane(renderer, panes.memory);
+
+        panes.console
+    }
+
+    /// Fills in a pane's background and draws its title
+    fn begin_pane(&mut self, vm: &VirtualMachine, renderer: &mut Renderer, pane: Rect, title: &str) {
+        let config = vm.console.config();
+        renderer.set_draw_color(Color::RGBA(0, 0, 0, config.get_console_opacity()));
+        renderer.fill_rect(pane).unwrap();
+        renderer.set_clip_rect(Some(pane));
+
+        let highlight = config.get_color(Style::Highlight);
+        self.atlas.draw(renderer, title, pane.x() + PADDING, pane.y() + PADDING, highlight);
+    }
+
+    fn end_pane(&self, renderer: &mut Renderer, pane: Rect) {
+        renderer.set_clip_rect(None);
+        renderer.set_draw_color(BORDER_COLOR);
+        renderer.draw_rect(pane).unwrap();
+    }
+
+    /// The y coordinate of a line of a pane, below its title
+    fn line_y(&self, pane: Rect, line: usize) -> i32 {
+        pane.y() + PADDING + (line as i32 + 2) * self.atlas.size() as i32
+    }
+
+    /// How many lines fit in a pane below its title
+    fn line_count(&self, pane: Rect) -> usize {
+        let lines = (pane.height() as i32 - PADDING * 2) / self.atlas.size() as i32 - 2;
+        ::std::cmp::max(lines, 0) as usize
+    }
+
+    /// Draws the code around the program counter, marking the current line
+    /// and the breakpoints in the same way as the list command
+    fn render_disassembly(&mut self, vm: &VirtualMachine, renderer: &mut Renderer, pane: Rect) {
+        let config = vm.console.config();
+        let x = pane.x() + PADDING;
+
+        for (index, line) in vm.disassembly_window(self.line_count(pane)).iter().enumerate() {
+            let y = self.line_y(pane, index);
+            if line.starts_with('>') {
+                renderer.set_draw_color(CURRENT_LINE_BACKGROUND);
+                renderer.fill_rect(Rect::new(pane.x(), y, pane.width(), self.atlas.size() as u32)).unwrap();
+                self.atlas.draw(renderer, line, x, y, config.get_color(Style::Highlight));
+            } else if line.len() > 8 && line.is_char_boundary(8) {
+                // A 4 character breakpoint marker, then the 4 digit address
+                let marker = if line.contains('*') { Style::Error } else { Style::Normal };
+                let mut pen = x;
+                pen += self.atlas.draw(renderer, &line[..4], pen, y, config.get_color(marker)) as i32;
+                pen += self.atlas.draw(renderer, &line[4..8], pen, y, config.get_color(Style::Dim)) as i32;
+                self.atlas.draw(renderer, &line[8..], pen, y, config.get_color(Style::Normal));
+            } else {
+                self.atlas.draw(renderer, line, x, y, config.get_color(Style::Normal));
+            }
+        }
+    }
+
+    fn render_registers(&mut self, vm: &VirtualMachine, renderer: &mut Renderer, pane: Rect) {
+        let registers = &vm.cpu.registers;
+        let flags = &vm.cpu.flags;
+        let values = [("A", format!("{:02X}", registers.A)),
+                      ("X", format!("{:02X}", registers.X)),
+                      ("Y", format!("{:02X}", registers.Y)),
+                      ("PC", format!("{:04X}", registers.PC)),
+                      ("S", format!("{:02X}", vm.cpu.stack.pointer)),
+                      ("", String::new()),
+                      ("Carry", flag(flags.carry)),
+                      ("Zero", flag(flags.zero)),
+                      ("Interrupt", flag(flags.interrupt_disabled)),
+                      ("Decimal", flag(flags.decimal)),
+                      ("Break", flag(flags.breakpoint)),
+                      ("Overflow", flag(flags.overflow)),
+                      ("Sign", flag(flags.sign))];
+
+        let config = vm.console.config();
+        let x = pane.x() + PADDING;
+        let value_x = x + self.atlas.width_of("Interrupt  ") as i32;
+        for (index, &(name, ref value)) in values.iter().take(self.line_count(pane)).enumerate() {
+            let y = self.line_y(pane, index);
+            self.atlas.draw(renderer, name, x, y, config.get_color(Style::Dim));
+            self.atlas.draw(renderer, value, value_x, y, config.get_color(Style::Normal));
+        }
+    }
+
+    /// Draws memory from the VM's memory view onwards, a row of bytes per line
+    fn render_memory(&mut self, vm: &VirtualMachine, renderer: &mut Renderer, pane: Rect) {
+        let config = vm.console.config();
+        let x = pane.x() + PADDING;
+
+        for (index, (label, bytes)) in memory_rows(vm, self.line_count(pane)).into_iter().enumerate() {
+            let y = self.line_y(pane, index);
+            let pen = x + self.atlas.draw(renderer, &label, x, y, config.get_color(Style::Dim)) as i32;
+            self.atlas.draw(renderer, &bytes, pen, y, config.get_color(Style::Normal));
+        }
+    }
+}
+
+/// Formats up to `count` rows of memory from the row the VM's memory view is
+/// in, as the address label and the bytes of each row
+fn memory_rows(vm: &VirtualMachine, count: usize) -> Vec<(String, String)> {
+    let start = vm.memory_view() - vm.memory_view() % MEMORY_ROW;
+    (0..count)
+        .map(|index| start + index * MEMORY_ROW)
+        .take_while(|&address| address < vm.cpu.memory.len())
+        .map(|address| {
+            let end = ::std::cmp::min(address + MEMORY_ROW, vm.cpu.memory.len());
+            let bytes = vm.cpu.memory[address..end]
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(" ");
+            (format!("{:04X}: ", address), bytes)
+        })
+        .collect()
+}
+
+fn flag(set: bool) -> String {
+    if set { "1".into() } else { "0".into() }
+}
+
+#[cfg(test)]
+mod tests {
+    use rs6502::CodeSegment;
+    use sdl2::rect::Rect;
+
+    use vm::VirtualMachine;
+    use super::{memory_rows, Panes};
+
+    #[test]
+    fn lays_the_panes_out_above_the_console() {
+        let panes = Panes::new((1000, 500));
+        assert_eq!(panes.disassembly, Rect::new(0, 0, 400, 300));
+        assert_eq!(panes.registers, Rect::new(400, 0, 200, 300));
+        assert_eq!(panes.memory, Rect::new(600, 0, 400, 300));
+        assert_eq!(panes.console, Rect::new(0, 300, 1000, 200));
+    }
+
+    #[test]
+    fn covers_the_window_without_gaps() {
+        let panes = Panes::new((1001, 601));
+        assert_eq!(panes.registers.x(), panes.disassembly.right());
+        assert_eq!(panes.memory.x(), panes.registers.right());
+        assert_eq!(panes.memory.right(), 1001);
+        assert_eq!(panes.console.y(), panes.memory.bottom());
+        assert_eq!(panes.console.bottom(), 601);
+    }
+
+    #[test]
+    fn shows_memory_from_the_row_of_the_view() {
+        let mut vm = VirtualMachine::for_tests();
+        vm.cpu.memory[0x0208] = 0xAB;
+        vm.set_memory_view(0x020A);
+        let rows = memory_rows(&vm, 2);
+        assert_eq!(rows,
+                   vec![("0208: ".to_string(), "AB 00 00 00 00 00 00 00".to_string()),
+                        ("0210: ".to_string(), "00 00 00 00 00 00 00 00".to_string())]);
+
+        // Nothing is shown past the end of memory
+        vm.set_memory_view(0xFFFF);
+        let rows = memory_rows(&vm, 4);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].0, "FFF8: ");
+    }
+
+    #[test]
+    fn keeps_the_program_counter_in_the_middle_of_the_disassembly() {
+        let mut vm = VirtualMachine::for_tests();
+        vm.load_code_segments(vec![CodeSegment {
+                                       address: 0xC000,
+                                       code: vec![0xEA; 10],
+                                   }]);
+        vm.cpu.registers.PC = 0xC005;
+        let lines = vm.disassembly_window(4);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[2].starts_with('>'));
+
+        // Near the end of the code, the window is filled from further up
+        vm.cpu.registers.PC = 0xC009;
+        let lines = vm.disassembly_window(4);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[3].starts_with('>'));
+        assert_eq!(vm.disassembly_window(20).len(), 10);
+    }
+}
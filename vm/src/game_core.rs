@@ -1,9 +1,14 @@
 
 use std::io::Write;
+use std::path::PathBuf;
 
 use vm::VirtualMachine;
 use command::{CommandSystem, UnblockEvent, CommandResult};
+use config::{self, Configuration};
+use console::{Console, ConsoleRenderer, Style};
+use debugger::DebuggerRenderer;
 
+use sdl2::clipboard::ClipboardUtil;
 use sdl2::render::Renderer;
 use sdl2::ttf::Sdl2TtfContext;
 use sdl2::event::Event;
@@ -12,38 +17,96 @@ use sdl2::keyboard::{Keycode, LCTRLMOD, RCTRLMOD};
 use rs6502::Cpu;
 
 pub struct GameCore<'a> {
-    pub vm: VirtualMachine<'a>,
+    pub vm: VirtualMachine,
     pub command_system: CommandSystem,
     unblock_event: Option<UnblockEvent>,
+    /// The command ENTER on an empty line runs again
+    repeat_command: Option<String>,
+    console_renderer: Option<ConsoleRenderer<'a>>,
+    /// Scripts run on the first update, after hakkarc
+    startup_scripts: Vec<PathBuf>,
+    debugger_renderer: Option<DebuggerRenderer<'a>>,
+    started: bool,
 }
 
+const STARTUP_SCRIPT: &'static str = "hakkarc";
+
 impl<'a> GameCore<'a> {
     pub fn new(ttf_context: &'a Sdl2TtfContext,
                mut renderer: &mut Renderer,
-               font_file: &'a str)
+               font_file: &'a str,
+               clipboard: ClipboardUtil,
+               config: Configuration)
                -> GameCore<'a>
    {
+        let mut core = GameCore::headless(config);
+        core.console_renderer = Some(ConsoleRenderer::new(ttf_context, &mut renderer, font_file, clipboard));
+        let font_size = core.vm.console.config().get_console_font_size();
+        core.debugger_renderer = Some(DebuggerRenderer::new(ttf_context, renderer, font_file, font_size));
+        core
+    }
+
+    /// Creates a GameCore without any SDL resources, for frontends that
+    /// present the console themselves
+    pub fn headless(config: Configuration) -> GameCore<'a> {
         let cpu = Cpu::new();
-        let vm = VirtualMachine::new(cpu, 150, &ttf_context, &mut renderer, font_file);
+        let clock_rate = config.get_clock_rate();
+        let console = Console::with_user_files(config);
+        let vm = VirtualMachine::new(cpu, clock_rate, console);
 
         GameCore {
             vm: vm,
             command_system: CommandSystem::new(),
             unblock_event: None,
+            repeat_command: None,
+            console_renderer: None,
+            startup_scripts: Vec::new(),
+            debugger_renderer: None,
+            started: false,
         }
     }
 
+    pub fn render(&mut self, renderer: &mut Renderer) {
+        // The debugger layout replaces the listing after each step, and
+        // takes the console away from its dock while execution is stopped
+        let layout = self.vm.is_debugging() && self.vm.console.config().get_debugger_layout();
+        let pane = match self.debugger_renderer {
+            Some(ref mut debugger_renderer) if layout => Some(debugger_renderer.render(&self.vm, renderer)),
+            _ => None,
+        };
+        self.vm.set_step_listing(pane.is_none());
+        self.vm.console.set_pane(pane);
+
+        if let Some(ref mut console_renderer) = self.console_renderer {
+            console_renderer.render(&mut self.vm.console, renderer);
+        }
+    }
+
+    /// Runs a console script when the game starts, after hakkarc
+    pub fn add_startup_script(&mut self, path: PathBuf) {
+        self.startup_scripts.push(path);
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        self.unblock_event.is_some()
+    }
+
+    /// Stops the currently blocking command, if there is one
+    pub fn unblock(&mut self) {
+        if let Some(ref unblock_event) = self.unblock_event {
+            unblock_event(&mut self.vm);
+        }
+        self.vm.console.input_blocked = false;
+        self.unblock_event = None;
+    }
+
     pub fn process_event(&mut self, event: &Event) {
         match *event {
             // Stop a blocking event
             Event::KeyDown { keycode, keymod, .. }
             if (keycode == Some(Keycode::C) && keymod.intersects(LCTRLMOD | RCTRLMOD) || keycode == Some(Keycode::Return)) &&
                self.unblock_event.is_some() => {
-                if let Some(ref unblock_event) = self.unblock_event {
-                    unblock_event(&mut self.vm);
-                }
-                self.vm.console.input_blocked = false;
-                self.unblock_event = None;
+                self.unblock();
             },
             // Let the console handle the event
             _ => self.vm.console.process(event)
@@ -51,22 +114,65 @@ impl<'a> GameCore<'a> {
     }
 
     pub fn update(&mut self) {
-        if let Some(cmd) = self.vm.console.get_next_command() {
-            let (result, unblock_event) = self.command_system.execute(cmd, &mut self.vm);
+        // The startup script runs on the first update rather than in the
+        // constructor, so that the level's code and symbols are loaded first
+        if !self.started {
+            self.started = true;
+            let script = config::user_config_path(STARTUP_SCRIPT);
+            let mut scripts = if script.exists() { vec![script] } else { Vec::new() };
+            scripts.extend(self.startup_scripts.drain(..));
 
-            if let CommandResult::NotFound = result {
-                writeln!(self.vm.console, "Command not recognized, type 'help' for a list of commands").unwrap();
+            for script in scripts {
+                // Only one command can block the console at a time
+                if self.is_blocked() {
+                    self.unblock();
+                }
+                let (_, unblock_event) = self.command_system.execute_script(script, &mut self.vm);
+                self.set_unblock_event(unblock_event);
             }
+        }
+
+        if self.vm.console.take_completion_request() {
+            let input = self.vm.console.input_to_cursor().to_string();
+            let (start, candidates) = self.command_system.complete(&input, &self.vm);
+            self.vm.console.complete(start, &candidates);
+        }
 
-            if unblock_event.is_some() {
-                self.unblock_event = unblock_event;
-                self.vm.console.input_blocked = true;
+        let command = match self.vm.console.get_next_command() {
+            Some(ref command) if command.is_empty() => self.repeat_command.clone(),
+            command => command,
+        };
+
+        if let Some(cmd) = command {
+            self.repeat_command = if self.command_system.is_repeatable(&cmd, &self.vm) {
+                Some(cmd.clone())
             } else {
-                self.unblock_event = None;
+                None
+            };
+
+            let (result, unblock_event) = self.command_system.execute(cmd, &mut self.vm);
+
+            if let CommandResult::NotFound = result {
+                writeln!(self.vm.console.styled(Style::Error), "Command not recognized, type 'help' for a list of commands").unwrap();
             }
+
+            self.set_unblock_event(unblock_event);
         }
 
         self.vm.cycle();
+
+        // The hex editor shows memory as the code changes it
+        if let Some(editor) = self.vm.console.hex_editor_mut() {
+            editor.sync(&mut self.vm.cpu.memory[..]);
+        }
     }
-}
 
+    fn set_unblock_event(&mut self, unblock_event: Option<UnblockEvent>) {
+        if unblock_event.is_some() {
+            self.unblock_event = unblock_event;
+            self.vm.console.input_blocked = true;
+        } else {
+            self.unblock_event = None;
+        }
+    }
+}
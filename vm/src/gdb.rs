@@ -0,0 +1,498 @@
+//! A minimal GDB remote serial protocol stub for the virtual machine.
+//!
+//! The server is polled once per VM cycle and never blocks, so the game keeps
+//! running while a debugger is attached. Registers are exchanged in the order
+//! A, X, Y, S, P (one byte each) followed by PC (two bytes, little endian),
+//! which GDB learns from the target description served by `qXfer`.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use vm::VirtualMachine;
+
+const INTERRUPT: u8 = 0x03;
+const REGISTER_COUNT: usize = 6;
+
+/// Describes the registers in the order `g` sends them, as GDB has no 6502
+/// architecture of its own
+const TARGET_XML: &'static str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.hakka.6502">
+    <flags id="status" size="1">
+      <field name="C" start="0" end="0"/>
+      <field name="Z" start="1" end="1"/>
+      <field name="I" start="2" end="2"/>
+      <field name="D" start="3" end="3"/>
+      <field name="B" start="4" end="4"/>
+      <field name="V" start="6" end="6"/>
+      <field name="N" start="7" end="7"/>
+    </flags>
+    <reg name="a" bitsize="8" type="uint8"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="p" bitsize="8" type="status"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    buffer: Vec<u8>,
+    /// Set once the client has resumed execution and is waiting to hear why it stopped
+    awaiting_stop: bool,
+}
+
+impl GdbServer {
+    /// Starts listening on the given localhost port
+    pub fn bind(port: u16) -> io::Result<GdbServer> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(GdbServer {
+            listener: listener,
+            client: None,
+            buffer: Vec::new(),
+            awaiting_stop: false,
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.listener.local_addr().map(|addr| addr.port()).unwrap_or(0)
+    }
+
+    /// Accepts a pending connection, handles any packets that have arrived and
+    /// reports to the client when a step or continue has come to a stop.
+    pub fn poll(&mut self, vm: &mut VirtualMachine) {
+        if self.client.is_none() {
+            if let Ok((stream, addr)) = self.listener.accept() {
+                if stream.set_nonblocking(true).is_ok() {
+                    writeln!(vm.console, "GDB client connected from {}", addr).unwrap();
+                    // GDB expects the target to be halted when it attaches
+                    vm.break_execution();
+                    self.client = Some(stream);
+                    self.buffer.clear();
+                    self.awaiting_stop = false;
+                }
+            }
+        }
+
+        if !self.receive() {
+            writeln!(vm.console, "GDB client disconnected").unwrap();
+            self.client = None;
+            return;
+        }
+
+        while let Some(packet) = self.next_packet() {
+            match packet {
+                Packet::Interrupt => {
+                    vm.break_execution();
+                }
+                // GDB sends the packet again when it's refused
+                Packet::Corrupt => {
+                    self.send_raw(b"-");
+                }
+                Packet::Command(command) => {
+                    self.send_raw(b"+");
+                    if let Some(reply) = self.handle(&command, vm) {
+                        self.send(&reply);
+                    }
+                }
+            }
+        }
+
+        if self.awaiting_stop && vm.is_debugging() && !vm.is_stepping() {
+            self.awaiting_stop = false;
+            self.send("S05");
+        }
+    }
+
+    /// Reads whatever is available from the client. Returns false if the
+    /// connection was closed.
+    fn receive(&mut self) -> bool {
+        let mut closed = false;
+        if let Some(ref mut client) = self.client {
+            let mut chunk = [0; 1024];
+            loop {
+                match client.read(&mut chunk) {
+                    Ok(0) => {
+                        closed = true;
+                        break;
+                    }
+                    Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(_) => {
+                        closed = true;
+                        break;
+                    }
+                }
+            }
+        }
+        !closed
+    }
+
+    fn next_packet(&mut self) -> Option<Packet> {
+        loop {
+            match self.buffer.first().cloned() {
+                None => return None,
+                Some(INTERRUPT) => {
+                    self.buffer.remove(0);
+                    return Some(Packet::Interrupt);
+                }
+                Some(b'$') => break,
+                // Acknowledgements and line noise
+                Some(_) => {
+                    self.buffer.remove(0);
+                }
+            }
+        }
+
+        // A packet is complete once we have the '#' and its two checksum digits
+        let end = match self.buffer.iter().position(|b| *b == b'#') {
+            Some(end) if end + 2 < self.buffer.len() => end,
+            _ => return None,
+        };
+
+        let packet = self.buffer.drain(..end + 3).collect::<Vec<_>>();
+        let expected = ::std::str::from_utf8(&packet[end + 1..]).ok().and_then(|sum| u8::from_str_radix(sum, 16).ok());
+        if expected != Some(checksum(&packet[1..end])) {
+            return Some(Packet::Corrupt);
+        }
+
+        let command = String::from_utf8_lossy(&packet[1..end]).into_owned();
+        Some(Packet::Command(command))
+    }
+
+    fn handle(&mut self, command: &str, vm: &mut VirtualMachine) -> Option<String> {
+        let (kind, body) = if command.is_empty() {
+            return Some(String::new());
+        } else {
+            command.split_at(1)
+        };
+
+        let reply = match kind {
+            "?" => "S05".into(),
+            "g" => encode(&read_registers(vm)),
+            "G" => {
+                match decode(body) {
+                    Some(ref bytes) if bytes.len() >= REGISTER_COUNT + 1 => {
+                        for (index, byte) in bytes.iter().take(REGISTER_COUNT - 1).enumerate() {
+                            write_register(vm, index, *byte as u16);
+                        }
+                        write_register(vm, REGISTER_COUNT - 1,
+                                       bytes[REGISTER_COUNT - 1] as u16 |
+                                       (bytes[REGISTER_COUNT] as u16) << 8);
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            "p" => {
+                match usize::from_str_radix(body, 16) {
+                    Ok(index) if index < REGISTER_COUNT - 1 => encode(&read_registers(vm)[index..index + 1]),
+                    Ok(index) if index == REGISTER_COUNT - 1 => encode(&read_registers(vm)[index..]),
+                    _ => "E01".into(),
+                }
+            }
+            "P" => {
+                let mut parts = body.splitn(2, '=');
+                let index = parts.next().and_then(|index| usize::from_str_radix(index, 16).ok());
+                let value = parts.next().and_then(decode);
+                match (index, value) {
+                    (Some(index), Some(ref value)) if index < REGISTER_COUNT && !value.is_empty() => {
+                        let high = value.get(1).cloned().unwrap_or(0) as u16;
+                        write_register(vm, index, value[0] as u16 | high << 8);
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            "m" => {
+                match parse_range(body) {
+                    // An empty reply would mean m isn't supported at all
+                    Some((_, 0)) => "OK".into(),
+                    Some((address, length)) => encode(&vm.cpu.memory[address..address + length]),
+                    None => "E01".into(),
+                }
+            }
+            "M" => {
+                let mut parts = body.splitn(2, ':');
+                let range = parts.next().and_then(parse_range);
+                let data = parts.next().and_then(decode);
+                match (range, data) {
+                    (Some((address, length)), Some(ref data)) if data.len() == length => {
+                        for (offset, byte) in data.iter().enumerate() {
+                            vm.cpu.memory[address + offset] = *byte;
+                        }
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            "Z" | "z" => {
+                // Only software breakpoints (type 0) are supported
+                let mut parts = body.split(',');
+                let breakpoint_type = parts.next();
+                let address = parts.next().and_then(|address| usize::from_str_radix(address, 16).ok());
+                match (breakpoint_type, address) {
+                    (Some("0"), Some(address)) if address <= u16::max_value() as usize => {
+                        if vm.is_breakpoint(address) != (kind == "Z") {
+                            vm.toggle_breakpoint(address);
+                        }
+                        "OK".into()
+                    }
+                    _ => String::new(),
+                }
+            }
+            "s" => {
+                vm.step_execution();
+                self.awaiting_stop = true;
+                return None;
+            }
+            "c" => {
+                vm.continue_execution();
+                self.awaiting_stop = true;
+                return None;
+            }
+            "D" => {
+                vm.continue_execution();
+                self.send("OK");
+                writeln!(vm.console, "GDB client detached").unwrap();
+                self.client = None;
+                return None;
+            }
+            "k" => {
+                self.client = None;
+                return None;
+            }
+            "H" => "OK".into(),
+            "q" => {
+                if body.starts_with("Supported") {
+                    "PacketSize=1000;qXfer:features:read+".into()
+                } else if body.starts_with("Xfer:features:read:") {
+                    read_features(&body["Xfer:features:read:".len()..])
+                } else if body.starts_with("Attached") {
+                    "1".into()
+                } else {
+                    String::new()
+                }
+            }
+            // An empty reply tells GDB the packet isn't supported
+            _ => String::new(),
+        };
+
+        Some(reply)
+    }
+
+    fn send(&mut self, data: &str) {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        self.send_raw(packet.as_bytes());
+    }
+
+    fn send_raw(&mut self, mut data: &[u8]) {
+        let mut failed = false;
+        if let Some(ref mut client) = self.client {
+            while !data.is_empty() {
+                match client.write(data) {
+                    Ok(n) => data = &data[n..],
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                    Err(_) => {
+                        failed = true;
+                        break;
+                    }
+                }
+            }
+        }
+        if failed {
+            self.client = None;
+        }
+    }
+}
+
+enum Packet {
+    Interrupt,
+    /// A packet whose checksum doesn't match its contents
+    Corrupt,
+    Command(String),
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// Answers a `qXfer:features:read:annex:offset,length` request with the part
+/// of the target description asked for. The reply starts with 'm' if there's
+/// more to come, or 'l' for the last part.
+fn read_features(request: &str) -> String {
+    let mut parts = request.splitn(2, ':');
+    let annex = parts.next().unwrap_or("");
+    let range = parts.next().and_then(|range| {
+        let mut parts = range.split(',');
+        let offset = parts.next().and_then(|offset| usize::from_str_radix(offset, 16).ok());
+        let length = parts.next().and_then(|length| usize::from_str_radix(length, 16).ok());
+        match (offset, length) {
+            (Some(offset), Some(length)) => Some((offset, length)),
+            _ => None,
+        }
+    });
+
+    match (annex, range) {
+        ("target.xml", Some((offset, length))) => {
+            let start = ::std::cmp::min(offset, TARGET_XML.len());
+            let end = ::std::cmp::min(start.saturating_add(length), TARGET_XML.len());
+            let kind = if end < TARGET_XML.len() { 'm' } else { 'l' };
+            format!("{}{}", kind, &TARGET_XML[start..end])
+        }
+        // E00 is an unknown annex, E01 a malformed request
+        ("target.xml", None) => "E01".into(),
+        _ => "E00".into(),
+    }
+}
+
+fn read_registers(vm: &VirtualMachine) -> Vec<u8> {
+    let registers = &vm.cpu.registers;
+    vec![registers.A,
+         registers.X,
+         registers.Y,
+         vm.cpu.stack.pointer as u8,
+         vm.cpu.flags.to_u8(),
+         registers.PC as u8,
+         (registers.PC >> 8) as u8]
+}
+
+fn write_register(vm: &mut VirtualMachine, index: usize, value: u16) {
+    match index {
+        0 => vm.cpu.registers.A = value as u8,
+        1 => vm.cpu.registers.X = value as u8,
+        2 => vm.cpu.registers.Y = value as u8,
+        3 => vm.cpu.stack.pointer = value as u8 as usize,
+        4 => vm.cpu.flags = (value as u8).into(),
+        5 => vm.cpu.registers.PC = value,
+        _ => (),
+    }
+}
+
+/// Parses an `addr,length` pair, clamping the length to the address space
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let mut parts = range.split(',');
+    let address = parts.next().and_then(|address| usize::from_str_radix(address, 16).ok());
+    let length = parts.next().and_then(|length| usize::from_str_radix(length, 16).ok());
+    match (address, length) {
+        (Some(address), Some(length)) if address <= u16::max_value() as usize => {
+            let length = ::std::cmp::min(length, 0x10000 - address);
+            Some((address, length))
+        }
+        _ => None,
+    }
+}
+
+fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| ::std::str::from_utf8(pair).ok().and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use vm::VirtualMachine;
+    use super::{checksum, read_features, GdbServer, Packet, TARGET_XML};
+
+    fn server(buffer: &[u8]) -> GdbServer {
+        let mut server = GdbServer::bind(0).unwrap();
+        server.buffer.extend_from_slice(buffer);
+        server
+    }
+
+    #[test]
+    fn reads_packets_with_a_valid_checksum() {
+        let mut server = server(b"+$g#67$m0,2#fb");
+        match server.next_packet() {
+            Some(Packet::Command(ref command)) if command == "g" => (),
+            _ => panic!("expected the g packet"),
+        }
+        match server.next_packet() {
+            Some(Packet::Command(ref command)) if command == "m0,2" => (),
+            _ => panic!("expected the m packet"),
+        }
+        assert!(server.next_packet().is_none());
+    }
+
+    #[test]
+    fn refuses_packets_with_a_bad_checksum() {
+        let mut server = server(b"$g#00$g#zz$g#67");
+        for _ in 0..2 {
+            match server.next_packet() {
+                Some(Packet::Corrupt) => (),
+                _ => panic!("expected a corrupt packet"),
+            }
+        }
+        match server.next_packet() {
+            Some(Packet::Command(ref command)) if command == "g" => (),
+            _ => panic!("expected the resent g packet"),
+        }
+    }
+
+    #[test]
+    fn waits_for_the_whole_packet() {
+        let mut server = server(b"$g#6");
+        assert!(server.next_packet().is_none());
+        server.buffer.push(b'7');
+        assert!(server.next_packet().is_some());
+    }
+
+    #[test]
+    fn serves_the_target_description_in_parts() {
+        let mut xml = String::new();
+        let mut offset = 0;
+        loop {
+            let reply = read_features(&format!("target.xml:{:x},40", offset));
+            xml.push_str(&reply[1..]);
+            offset += reply.len() - 1;
+            if reply.starts_with('l') {
+                break;
+            }
+            assert!(reply.starts_with('m'));
+        }
+        assert_eq!(xml, TARGET_XML);
+
+        // The description is sent as it is, so it mustn't need escaping
+        assert!(!TARGET_XML.contains(|c| c == '$' || c == '#' || c == '}' || c == '*'));
+        for register in &["\"a\"", "\"x\"", "\"y\"", "\"sp\"", "\"p\"", "\"pc\""] {
+            assert!(TARGET_XML.contains(register));
+        }
+    }
+
+    #[test]
+    fn reads_memory() {
+        let mut server = server(b"");
+        let mut vm = VirtualMachine::for_tests();
+        vm.cpu.memory[0x10] = 0xAB;
+        vm.cpu.memory[0x11] = 0x01;
+        assert_eq!(server.handle("m10,2", &mut vm), Some("ab01".into()));
+        assert_eq!(server.handle("mffff,10", &mut vm), Some("00".into()));
+        assert_eq!(server.handle("m10,0", &mut vm), Some("OK".into()));
+        assert_eq!(server.handle("m10000,1", &mut vm), Some("E01".into()));
+    }
+
+    #[test]
+    fn refuses_other_annexes() {
+        assert_eq!(read_features("other.xml:0,100"), "E00");
+        assert_eq!(read_features("target.xml:zz"), "E01");
+        assert_eq!(checksum(b"OK"), 0x9a);
+    }
+}
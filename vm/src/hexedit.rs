@@ -0,0 +1,391 @@
+use std::cell::Cell;
+
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, LCTRLMOD, RCTRLMOD};
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Renderer;
+
+use atlas::GlyphAtlas;
+use config::Configuration;
+use console::Style;
+
+const PADDING: i32 = 10;
+const COLUMNS: usize = 16;
+const CURSOR_COLOR: Color = Color::RGBA(255, 255, 255, 70);
+/// How many frames a changed byte stays highlighted
+const CHANGE_FRAMES: u8 = 60;
+
+/// A grid of memory that's edited in place, which takes over the console
+/// while it's open. It works on a copy of memory that `sync` keeps up to
+/// date, so it can be driven by the console without the VM.
+pub struct HexEditor {
+    cursor: usize,
+    /// The address of the first row on screen
+    top: usize,
+    /// How many rows were on screen when the grid was last drawn
+    rows: Cell<usize>,
+    /// The first digit of a byte being typed
+    nibble: Option<u8>,
+    /// The address typed so far at the goto prompt, while it's open
+    goto: Option<String>,
+    memory: Vec<u8>,
+    /// For each byte, how many more frames it's highlighted for
+    changed: Vec<u8>,
+    /// Bytes typed since the last sync, to be written to memory
+    writes: Vec<(usize, u8)>,
+}
+
+impl HexEditor {
+    /// Opens the editor with the cursor on the given address
+    pub fn new(address: usize, memory: &[u8]) -> HexEditor {
+        let address = ::std::cmp::min(address, memory.len().saturating_sub(1));
+        HexEditor {
+            cursor: address,
+            top: address - address % COLUMNS,
+            rows: Cell::new(COLUMNS),
+            nibble: None,
+            goto: None,
+            memory: memory.to_vec(),
+            changed: vec![0; memory.len()],
+            writes: Vec::new(),
+        }
+    }
+
+    /// Handles a key press, returning false once the editor is closed
+    pub fn process(&mut self, event: &Event) -> bool {
+        match *event {
+            Event::TextInput { ref text, .. } => {
+                for c in text.chars() {
+                    if !self.type_char(c) {
+                        return false;
+                    }
+                }
+            }
+            Event::KeyDown { keycode, keymod, .. } if self.goto.is_some() => {
+                match keycode {
+                    Some(Keycode::Return) => self.end_goto(),
+                    Some(Keycode::Escape) => self.goto = None,
+                    Some(Keycode::Backspace) => {
+                        self.goto.as_mut().map(|address| address.pop());
+                    }
+                    Some(Keycode::C) if keymod.intersects(LCTRLMOD | RCTRLMOD) => self.goto = None,
+                    _ => (),
+                }
+            }
+            Event::KeyDown { keycode, keymod, .. } => {
+                let ctrl = keymod.intersects(LCTRLMOD | RCTRLMOD);
+                let page = (self.rows.get() * COLUMNS) as isize;
+                let column = (self.cursor % COLUMNS) as isize;
+                match keycode {
+                    Some(Keycode::Escape) => return false,
+                    Some(Keycode::C) if ctrl => return false,
+                    Some(Keycode::Home) if ctrl => self.move_to(0),
+                    Some(Keycode::End) if ctrl => self.move_to(::std::usize::MAX),
+                    Some(Keycode::Left) | Some(Keycode::Backspace) => self.move_by(-1),
+                    Some(Keycode::Right) => self.move_by(1),
+                    Some(Keycode::Up) => self.move_by(-(COLUMNS as isize)),
+                    Some(Keycode::Down) => self.move_by(COLUMNS as isize),
+                    Some(Keycode::PageUp) => self.move_by(-page),
+                    Some(Keycode::PageDown) => self.move_by(page),
+                    Some(Keycode::Home) => self.move_by(-column),
+                    Some(Keycode::End) => self.move_by(COLUMNS as isize - 1 - column),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+
+        true
+    }
+
+    /// Writes the bytes typed since the last sync into memory, then takes a
+    /// new copy of it, marking the bytes that have changed
+    pub fn sync(&mut self, memory: &mut [u8]) {
+        for (address, value) in self.writes.drain(..) {
+            if address < memory.len() {
+                memory[address] = value;
+            }
+        }
+
+        for (address, &value) in memory.iter().enumerate().take(self.memory.len()) {
+            if self.memory[address] != value {
+                self.memory[address] = value;
+                self.changed[address] = CHANGE_FRAMES;
+            } else if self.changed[address] > 0 {
+                self.changed[address] -= 1;
+            }
+        }
+    }
+
+    /// Draws the grid, with an address column and the bytes as ASCII, and a
+    /// status line at the bottom of the area
+    pub fn render(&self, atlas: &mut GlyphAtlas, renderer: &mut Renderer, area: Rect, config: &Configuration) {
+        let font_size = atlas.size() as i32;
+        let rows = ::std::cmp::max((area.height() as i32 - PADDING * 2) / font_size - 2, 1) as usize;
+        self.rows.set(rows);
+        // The cursor stays on screen even if the console has just shrunk
+        let top = if self.cursor >= self.top + rows * COLUMNS {
+            self.cursor - self.cursor % COLUMNS - (rows - 1) * COLUMNS
+        } else {
+            self.top
+        };
+
+        let x = area.x() + PADDING;
+        let bytes_x = x + atlas.width_of("0000: ") as i32;
+        let cell = atlas.width_of("00 ") as i32;
+        let ascii_x = bytes_x + cell * COLUMNS as i32 + atlas.width_of(" ") as i32;
+        let char_width = atlas.width_of("0") as i32;
+        let dim = config.get_color(Style::Dim);
+
+        let header = (0..COLUMNS).map(|column| format!("{:02X}", column)).collect::<Vec<_>>().join(" ");
+        atlas.draw(renderer, &header, bytes_x, area.y() + PADDING, dim);
+
+        for row in 0..rows {
+            let address = top + row * COLUMNS;
+            if address >= self.memory.len() {
+                break;
+            }
+            let y = area.y() + PADDING + (row as i32 + 1) * font_size;
+            atlas.draw(renderer, &format!("{:04X}:", address), x, y, dim);
+
+            for column in 0..::std::cmp::min(COLUMNS, self.memory.len() - address) {
+                let index = address + column;
+                let value = self.memory[index];
+                let byte_x = bytes_x + cell * column as i32;
+                let char_x = ascii_x + char_width * column as i32;
+
+                if index == self.cursor {
+                    renderer.set_draw_color(CURSOR_COLOR);
+                    renderer.fill_rect(Rect::new(byte_x, y, (char_width * 2) as u32, font_size as u32)).unwrap();
+                    renderer.fill_rect(Rect::new(char_x, y, char_width as u32, font_size as u32)).unwrap();
+                }
+
+                let (text, style) = match self.nibble {
+                    Some(nibble) if index == self.cursor => (format!("{:X}_", nibble), Style::Input),
+                    _ if self.changed[index] > 0 => (format!("{:02X}", value), Style::Warning),
+                    _ => (format!("{:02X}", value), Style::Normal),
+                };
+                atlas.draw(renderer, &text, byte_x, y, config.get_color(style));
+
+                let c = if value >= 0x20 && value < 0x7F { value as char } else { '.' };
+                let style = if self.changed[index] > 0 { Style::Warning } else { Style::Dim };
+                atlas.draw(renderer, &c.to_string(), char_x, y, config.get_color(style));
+            }
+        }
+
+        // The status line sits where the input line would be
+        let y = area.bottom() - font_size - PADDING;
+        match self.goto {
+            Some(ref address) => {
+                let prompt = format!("Go to address: {}_", address);
+                atlas.draw(renderer, &prompt, x, y, config.get_color(Style::Input));
+            }
+            None => {
+                let value = self.memory[self.cursor];
+                let status = format!("{:04X} = {:02X} ({})   0-F: write  G: go to  q: quit",
+                                     self.cursor,
+                                     value,
+                                     value);
+                atlas.draw(renderer, &status, x, y, config.get_color(Style::Highlight));
+            }
+        }
+    }
+
+    /// Handles a typed character, returning false if it closes the editor
+    fn type_char(&mut self, c: char) -> bool {
+        if let Some(ref mut address) = self.goto {
+            if c.is_digit(16) && address.len() < 4 {
+                address.push(c.to_ascii_uppercase());
+            }
+            return true;
+        }
+
+        match c {
+            'q' | 'Q' => return false,
+            'g' | 'G' => {
+                self.nibble = None;
+                self.goto = Some(String::new());
+            }
+            c if c.is_digit(16) => {
+                let digit = c.to_digit(16).unwrap() as u8;
+                match self.nibble.take() {
+                    Some(high) => self.write(high << 4 | digit),
+                    None => self.nibble = Some(digit),
+                }
+            }
+            _ => (),
+        }
+
+        true
+    }
+
+    /// Overwrites the byte under the cursor, then moves on to the next one
+    fn write(&mut self, value: u8) {
+        self.writes.push((self.cursor, value));
+        self.memory[self.cursor] = value;
+        self.changed[self.cursor] = CHANGE_FRAMES;
+        self.move_by(1);
+    }
+
+    fn end_goto(&mut self) {
+        let address = self.goto.take().and_then(|address| usize::from_str_radix(&address, 16).ok());
+        if let Some(address) = address {
+            self.move_to(address);
+            // The address goes at the top of the screen
+            self.top = self.cursor - self.cursor % COLUMNS;
+        }
+    }
+
+    fn move_by(&mut self, offset: isize) {
+        let cursor = ::std::cmp::max(self.cursor as isize + offset, 0) as usize;
+        self.move_to(cursor);
+    }
+
+    /// Moves the cursor, scrolling to keep it on screen. A byte half typed
+    /// is dropped.
+    fn move_to(&mut self, address: usize) {
+        self.nibble = None;
+        self.cursor = ::std::cmp::min(address, self.memory.len().saturating_sub(1));
+
+        let row = self.cursor - self.cursor % COLUMNS;
+        let rows = self.rows.get();
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + rows * COLUMNS {
+            self.top = row - (rows - 1) * COLUMNS;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::event::Event;
+    use sdl2::keyboard::{Keycode, Mod, LCTRLMOD, NOMOD};
+
+    use super::{HexEditor, CHANGE_FRAMES, COLUMNS};
+
+    fn text(editor: &mut HexEditor, text: &str) -> bool {
+        editor.process(&Event::TextInput {
+            timestamp: 0,
+            window_id: 0,
+            text: text.into(),
+        })
+    }
+
+    fn key(editor: &mut HexEditor, keycode: Keycode, keymod: Mod) -> bool {
+        editor.process(&Event::KeyDown {
+            timestamp: 0,
+            window_id: 0,
+            keycode: Some(keycode),
+            scancode: None,
+            keymod: keymod,
+            repeat: false,
+        })
+    }
+
+    #[test]
+    fn writes_typed_bytes_on_sync() {
+        let mut memory = vec![0; 0x100];
+        let mut editor = HexEditor::new(0x10, &memory);
+
+        assert!(text(&mut editor, "a"));
+        assert_eq!(editor.nibble, Some(0xA));
+        assert!(text(&mut editor, "9f"));
+        assert_eq!(editor.cursor, 0x11);
+        assert_eq!(editor.nibble, Some(0xF));
+        assert!(text(&mut editor, "F"));
+        assert_eq!(editor.cursor, 0x12);
+        // Nothing is written until the editor is synced
+        assert_eq!(memory[0x10], 0);
+
+        editor.sync(&mut memory);
+        assert_eq!(&memory[0x10..0x12], &[0xA9, 0xFF]);
+        assert!(editor.writes.is_empty());
+    }
+
+    #[test]
+    fn marks_bytes_changed_elsewhere() {
+        let mut memory = vec![0; 0x100];
+        let mut editor = HexEditor::new(0, &memory);
+        memory[0x20] = 0x42;
+        editor.sync(&mut memory);
+        assert_eq!(editor.memory[0x20], 0x42);
+        assert_eq!(editor.changed[0x20], CHANGE_FRAMES);
+
+        // The highlight fades a frame at a time
+        editor.sync(&mut memory);
+        assert_eq!(editor.changed[0x20], CHANGE_FRAMES - 1);
+    }
+
+    #[test]
+    fn moves_the_cursor_within_memory() {
+        let memory = vec![0; 0x100];
+        let mut editor = HexEditor::new(0x1000, &memory);
+        assert_eq!(editor.cursor, 0xFF);
+
+        key(&mut editor, Keycode::Home, NOMOD);
+        assert_eq!(editor.cursor, 0xF0);
+        key(&mut editor, Keycode::Up, NOMOD);
+        assert_eq!(editor.cursor, 0xF0 - COLUMNS);
+        key(&mut editor, Keycode::Right, NOMOD);
+        key(&mut editor, Keycode::End, NOMOD);
+        assert_eq!(editor.cursor, 0xEF);
+        key(&mut editor, Keycode::Home, LCTRLMOD);
+        assert_eq!(editor.cursor, 0);
+        key(&mut editor, Keycode::Left, NOMOD);
+        assert_eq!(editor.cursor, 0);
+
+        // Moving drops a byte that's half typed
+        text(&mut editor, "1");
+        key(&mut editor, Keycode::Down, NOMOD);
+        assert_eq!(editor.cursor, COLUMNS);
+        assert_eq!(editor.nibble, None);
+    }
+
+    #[test]
+    fn scrolls_to_keep_the_cursor_on_screen() {
+        let memory = vec![0; 0x1000];
+        let mut editor = HexEditor::new(0, &memory);
+        editor.rows.set(4);
+
+        key(&mut editor, Keycode::PageDown, NOMOD);
+        assert_eq!(editor.cursor, 4 * COLUMNS);
+        assert_eq!(editor.top, COLUMNS);
+        key(&mut editor, Keycode::End, LCTRLMOD);
+        assert_eq!(editor.cursor, 0xFFF);
+        assert_eq!(editor.top, 0x1000 - 4 * COLUMNS);
+        key(&mut editor, Keycode::PageUp, NOMOD);
+        assert_eq!(editor.top, 0x1000 - 5 * COLUMNS);
+    }
+
+    #[test]
+    fn goes_to_a_typed_address() {
+        let memory = vec![0; 0x10000];
+        let mut editor = HexEditor::new(0, &memory);
+
+        text(&mut editor, "g");
+        text(&mut editor, "c0x0q12");
+        assert_eq!(editor.goto, Some("C001".into()));
+        key(&mut editor, Keycode::Backspace, NOMOD);
+        key(&mut editor, Keycode::Return, NOMOD);
+        assert_eq!(editor.goto, None);
+        assert_eq!(editor.cursor, 0xC00);
+        assert_eq!(editor.top, 0xC00);
+
+        // Escape leaves the cursor where it was
+        text(&mut editor, "G1234");
+        key(&mut editor, Keycode::Escape, NOMOD);
+        assert_eq!(editor.cursor, 0xC00);
+        assert!(text(&mut editor, "1"));
+    }
+
+    #[test]
+    fn closes_on_q_escape_or_ctrl_c() {
+        let memory = vec![0; 0x100];
+        assert!(!text(&mut HexEditor::new(0, &memory), "q"));
+        assert!(!key(&mut HexEditor::new(0, &memory), Keycode::Escape, NOMOD));
+        assert!(!key(&mut HexEditor::new(0, &memory), Keycode::C, LCTRLMOD));
+        assert!(key(&mut HexEditor::new(0, &memory), Keycode::C, NOMOD));
+    }
+}
@@ -5,16 +5,31 @@ extern crate rustc_serialize;
 extern crate app_dirs;
 extern crate sdl2;
 
+mod atlas;
+mod cli;
 mod console;
+mod debugger;
+mod hexedit;
 mod position;
 mod text;
 mod config;
 mod command;
+mod symbols;
+mod transcript;
+mod gdb;
+mod dap;
 mod vm;
 mod game_core;
 
+pub use self::atlas::GlyphAtlas;
+pub use self::cli::Options;
+pub use self::config::{Configuration, KeyAction, KEY_ACTION_NAMES};
+pub use self::console::{Console, ConsoleRenderer, Style};
+pub use self::debugger::{DebuggerRenderer, Panes};
+pub use self::hexedit::HexEditor;
 pub use self::position::Position;
 pub use self::text::Text;
 pub use self::vm::VirtualMachine;
-pub use self::command::{CommandSystem, Command};
+pub use self::command::{CommandSystem, Command, CommandResult, Completion, Args, ArgKind, ArgSpec};
 pub use self::game_core::GameCore;
+pub use self::symbols::SymbolTable;
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use rs6502::{CodeSegment, Disassembler, OpCode};
+
+/// Maps the labels and constants declared in a level's assembly source to
+/// their addresses, and source lines to the instructions assembled from them.
+pub struct SymbolTable {
+    symbols: HashMap<String, u16>,
+    labels: HashMap<u16, String>,
+    /// (line, address) pairs for every instruction, in source order. Lines start at 1.
+    lines: Vec<(usize, u16)>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            symbols: HashMap::new(),
+            labels: HashMap::new(),
+            lines: Vec::new(),
+        }
+    }
+
+    /// Builds a symbol table from assembly source and the code segments that
+    /// were assembled from it. Constants (`KEY = $04`) are read straight from
+    /// the source. Labels take the address of the next instruction. That
+    /// address comes from walking the source instructions in step with the
+    /// disassembled segments.
+    pub fn from_source(source: &str, segments: &[CodeSegment]) -> SymbolTable {
+        let mut table = SymbolTable::new();
+
+        let mut addresses = Vec::new();
+        for segment in segments {
+            let disassembler = Disassembler::with_offset(segment.address);
+            for (_, offset) in disassembler.disassemble_with_addresses(&segment.code) {
+                addresses.push(segment.address + offset);
+            }
+        }
+        let mut addresses = addresses.into_iter();
+
+        let mut pending_labels = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() || line.starts_with('.') {
+                continue;
+            }
+
+            if let Some(equals) = line.find('=') {
+                let name = line[..equals].trim();
+                let value = line[equals + 1..].trim();
+                if let Some(value) = table.parse_value(value) {
+                    table.insert(name, value);
+                }
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let first = words.next().unwrap();
+            let has_instruction = if is_opcode(first) {
+                true
+            } else {
+                pending_labels.push(first.trim_end_matches(':').to_string());
+                words.next().map(is_opcode).unwrap_or(false)
+            };
+
+            if has_instruction {
+                match addresses.next() {
+                    Some(address) => {
+                        for label in pending_labels.drain(..) {
+                            table.insert_label(label, address);
+                        }
+                        table.lines.push((index + 1, address));
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        // Labels after the final instruction point just past the end of the code
+        if let Some(segment) = segments.last() {
+            let end = segment.address + segment.code.len() as u16;
+            for label in pending_labels {
+                table.insert_label(label, end);
+            }
+        }
+
+        table
+    }
+
+    pub fn insert<S>(&mut self, name: S, address: u16)
+        where S: Into<String>
+    {
+        self.symbols.insert(name.into(), address);
+    }
+
+    pub fn get(&self, name: &str) -> Option<u16> {
+        self.symbols.get(name).cloned()
+    }
+
+    /// Returns the label that marks the given address, if there is one
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(|label| &label[..])
+    }
+
+    /// Returns the address of the first instruction on or after the given line
+    pub fn address_of_line(&self, line: usize) -> Option<(usize, u16)> {
+        self.lines.iter().find(|&&(l, _)| l >= line).cloned()
+    }
+
+    /// Returns the source line of the instruction at the given address
+    pub fn line_of_address(&self, address: u16) -> Option<usize> {
+        self.lines.iter().find(|&&(_, a)| a == address).map(|&(line, _)| line)
+    }
+
+    fn insert_label(&mut self, name: String, address: u16) {
+        self.labels.entry(address).or_insert(name.clone());
+        self.insert(name, address);
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self.symbols.keys().map(|name| &name[..]).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    fn parse_value(&self, value: &str) -> Option<u16> {
+        if value.starts_with('$') {
+            u16::from_str_radix(&value[1..], 16).ok()
+        } else if value.starts_with('%') {
+            u16::from_str_radix(&value[1..], 2).ok()
+        } else if let Ok(value) = value.parse::<u16>() {
+            Some(value)
+        } else {
+            self.get(value)
+        }
+    }
+}
+
+fn is_opcode(word: &str) -> bool {
+    OpCode::from_mnemonic(word.to_uppercase()).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use rs6502::CodeSegment;
+
+    use super::SymbolTable;
+
+    const SOURCE: &'static str = "; Constants
+KEY = $04
+SPEED = %101
+LIMIT = 300
+ALIAS = KEY
+
+.ORG $C000
+Start:
+Begin:
+LDA KEY ; A comment = not a constant
+Loop
+  INX
+  BNE Loop
+RTS
+End:
+";
+
+    /// What SOURCE assembles to
+    fn segments() -> Vec<CodeSegment> {
+        vec![CodeSegment {
+                 address: 0xC000,
+                 code: vec![0xA5, 0x04, 0xE8, 0xD0, 0xFD, 0x60],
+             }]
+    }
+
+    #[test]
+    fn reads_constants() {
+        let table = SymbolTable::from_source(SOURCE, &segments());
+        assert_eq!(table.get("KEY"), Some(0x04));
+        assert_eq!(table.get("SPEED"), Some(5));
+        assert_eq!(table.get("LIMIT"), Some(300));
+        assert_eq!(table.get("ALIAS"), Some(0x04));
+        assert_eq!(table.get("NOTHING"), None);
+    }
+
+    #[test]
+    fn gives_labels_the_address_of_the_next_instruction() {
+        let table = SymbolTable::from_source(SOURCE, &segments());
+        assert_eq!(table.get("Start"), Some(0xC000));
+        assert_eq!(table.get("Begin"), Some(0xC000));
+        assert_eq!(table.get("Loop"), Some(0xC002));
+        // A label after the last instruction points just past the code
+        assert_eq!(table.get("End"), Some(0xC006));
+
+        // The first label at an address is the one shown for it
+        assert_eq!(table.label_at(0xC000), Some("Start"));
+        assert_eq!(table.label_at(0xC002), Some("Loop"));
+        assert_eq!(table.label_at(0xC001), None);
+    }
+
+    #[test]
+    fn maps_lines_to_instructions() {
+        let table = SymbolTable::from_source(SOURCE, &segments());
+        assert_eq!(table.address_of_line(1), Some((10, 0xC000)));
+        assert_eq!(table.address_of_line(11), Some((12, 0xC002)));
+        assert_eq!(table.address_of_line(13), Some((13, 0xC003)));
+        assert_eq!(table.address_of_line(15), None);
+
+        assert_eq!(table.line_of_address(0xC005), Some(14));
+        assert_eq!(table.line_of_address(0xC001), None);
+    }
+
+    #[test]
+    fn reads_constants_without_code() {
+        let table = SymbolTable::from_source(SOURCE, &[]);
+        assert_eq!(table.get("KEY"), Some(0x04));
+        assert_eq!(table.get("Start"), None);
+        assert_eq!(table.address_of_line(1), None);
+        assert_eq!(table.names(), vec!["ALIAS", "KEY", "LIMIT", "SPEED"]);
+    }
+}
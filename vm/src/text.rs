@@ -1,41 +1,23 @@
-use std::path::Path;
-
 use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::{Renderer, Texture, TextureQuery};
-use sdl2::ttf::{Sdl2TtfContext, STYLE_BOLD};
+use sdl2::render::Renderer;
 
+use atlas::GlyphAtlas;
 use position::Position;
 
+/// A line of text, drawn from a glyph atlas
 pub struct Text {
+    text: String,
     x: i32,
     y: i32,
-    width: u32,
-    height: u32,
-    texture: Texture,
+    color: Color,
 }
 
 impl Text {
-    pub fn new<S, P>(ttf_context: &Sdl2TtfContext,
-                     renderer: &mut Renderer,
-                     text: S,
-                     position: Position,
-                     font_size: u16,
-                     color: Color,
-                     path: P)
-                     -> Text
-        where S: Into<String>,
-              P: AsRef<Path>
+    pub fn new<S>(atlas: &GlyphAtlas, text: S, position: Position, color: Color) -> Text
+        where S: Into<String>
     {
-        let mut font = ttf_context.load_font(path.as_ref(), font_size).unwrap();
-        font.set_style(STYLE_BOLD);
-        let surface = font.render(&text.into())
-            .blended(color)
-            .unwrap();
-        let texture = renderer.create_texture_from_surface(&surface)
-            .unwrap();
-
-        let TextureQuery { width, height, .. } = texture.query();
+        let text = text.into();
+        let width = atlas.width_of(&text);
 
         let (x, y) = match position {
             Position::HorizontalCenter(x, y) => (x - width as i32 / 2, y),
@@ -43,18 +25,14 @@ impl Text {
         };
 
         Text {
+            text: text,
             x: x,
             y: y,
-            width: width,
-            height: height,
-            texture: texture,
+            color: color,
         }
     }
 
-    pub fn render(&self, renderer: &mut Renderer) {
-        renderer.copy(&self.texture,
-                  None,
-                  Some(Rect::new(self.x, self.y, self.width, self.height)))
-            .unwrap();
+    pub fn render(&self, atlas: &mut GlyphAtlas, renderer: &mut Renderer) {
+        atlas.draw(renderer, &self.text, self.x, self.y, self.color);
     }
-}
\ No newline at end of file
+}
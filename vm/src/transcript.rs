@@ -0,0 +1,144 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use app_dirs::*;
+
+use config::APP_INFO;
+
+const LOG_DIRECTORY: &'static str = "logs";
+
+/// A copy of everything written to the console, kept in a file
+pub struct Transcript {
+    file: File,
+    path: PathBuf,
+}
+
+impl Transcript {
+    /// Starts a transcript in a new file named after the current time, in
+    /// the logs folder of the user's hakka data directory
+    pub fn create() -> io::Result<Transcript> {
+        let directory = app_dir(AppDataType::UserData, &APP_INFO, LOG_DIRECTORY)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("{}", err)))?;
+        let (date, hour, minute, second) = now();
+        let path = directory.join(format!("session-{}-{:02}{:02}{:02}.log", date, hour, minute, second));
+        Transcript::open(path)
+    }
+
+    /// Starts a transcript at the end of the given file
+    pub fn open<P>(path: P) -> io::Result<Transcript>
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let (date, hour, minute, second) = now();
+        writeln!(file, "# hakka session started {} {:02}:{:02}:{:02} UTC", date, hour, minute, second)?;
+
+        Ok(Transcript {
+            file: file,
+            path: path,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Write for Transcript {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Returns the current UTC date, e.g. "2017-03-04", and the hour, minute
+/// and second of the day
+fn now() -> (String, u64, u64, u64) {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let time = seconds % 86400;
+
+    (format!("{:04}-{:02}-{:02}", year, month, day), time / 3600, time / 60 % 60, time % 60)
+}
+
+/// Converts a count of days since 1970-01-01 to a (year, month, day) date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = (if days >= 0 { days } else { days - 146096 }) / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = (if month_index < 10 { month_index + 3 } else { month_index - 9 }) as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use std::path::Path;
+
+    use config::Configuration;
+    use console::Console;
+    use super::{civil_from_days, Transcript};
+
+    fn read(path: &Path) -> String {
+        let mut contents = String::new();
+        File::open(path).unwrap().read_to_string(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn converts_days_to_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(17229), (2017, 3, 4));
+    }
+
+    #[test]
+    fn appends_to_an_existing_file() {
+        let path = env::temp_dir().join("hakka-transcript-appends.log");
+        File::create(&path).unwrap().write_all(b"earlier\n").unwrap();
+
+        let mut transcript = Transcript::open(&path).unwrap();
+        write!(transcript, "reg\n").unwrap();
+        assert_eq!(transcript.path(), path.as_path());
+
+        let lines = read(&path).lines().map(String::from).collect::<Vec<_>>();
+        assert_eq!(lines[0], "earlier");
+        assert!(lines[1].starts_with("# hakka session started "));
+        assert!(lines[1].ends_with(" UTC"));
+        assert_eq!(lines[2], "reg");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn copies_console_output_until_stopped() {
+        let path = env::temp_dir().join("hakka-transcript-console.log");
+        let _ = fs::remove_file(&path);
+        let mut console = Console::new(Configuration::default());
+
+        writeln!(console, "before").unwrap();
+        assert_eq!(console.start_transcript(path.to_str()).unwrap(), path);
+        assert_eq!(console.transcript_path(), Some(path.as_path()));
+        writeln!(console, "during").unwrap();
+        assert_eq!(console.stop_transcript(), Some(path.clone()));
+        writeln!(console, "after").unwrap();
+
+        let contents = read(&path);
+        assert!(contents.ends_with("UTC\nduring\n"));
+        assert_eq!(console.stop_transcript(), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
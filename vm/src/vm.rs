@@ -1,10 +1,16 @@
 
 use rs6502::{CodeSegment, Cpu, Disassembler};
-use sdl2::render::Renderer;
-use sdl2::ttf::Sdl2TtfContext;
-use console::Console;
-use std::io::Write;
+use console::{Console, Style};
+use dap::DapServer;
+use gdb::GdbServer;
+use symbols::SymbolTable;
+use std::fs::File;
+use std::io::{self, Read, Write};
 use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
 
 #[derive(Debug)]
 pub struct MemoryMonitor {
@@ -13,34 +19,53 @@ pub struct MemoryMonitor {
     end_addr: usize,
 }
 
-pub struct VirtualMachine<'a> {
+/// A subroutine call tracked by the VM's shadow stack
+#[derive(Debug, Clone, Copy)]
+pub struct CallFrame {
+    /// The address of the JSR instruction
+    pub call_site: u16,
+    /// The address of the subroutine that was called
+    pub target: u16,
+}
+
+pub struct VirtualMachine {
     pub cpu: Cpu,
     pub monitor: MemoryMonitor,
-    pub console: Console<'a>,
+    pub console: Console,
+    pub symbols: SymbolTable,
+    gdb_server: Option<GdbServer>,
+    dap_server: Option<DapServer>,
+    source_path: Option<PathBuf>,
+    call_stack: Vec<CallFrame>,
     segments: Vec<CodeSegment>,
     clock_rate: Option<u32>,
     breakpoints: [u8; 64 * 1024],
     broken: bool,
     step: bool,
+    /// While stepping over or out of a subroutine, execution stops once the
+    /// shadow call stack is back down to this depth
+    return_depth: Option<usize>,
+    /// Whether each step is followed by a listing in the console
+    step_listing: bool,
+    /// The first address shown in the debugger layout's memory pane
+    memory_view: usize,
 }
 
-impl<'a> VirtualMachine<'a> {
-    pub fn new<CR>(cpu: Cpu,
-                   clock_rate: CR,
-                   ttf_context: &'a Sdl2TtfContext,
-                   mut renderer: &mut Renderer,
-                   font_file: &'a str)
-                   -> VirtualMachine<'a>
+impl VirtualMachine {
+    pub fn new<CR>(cpu: Cpu, clock_rate: CR, mut console: Console) -> VirtualMachine
         where CR: Into<Option<u32>>
     {
-        let mut console = Console::new(ttf_context, renderer, font_file);
-
         writeln!(console, "Welcome to hakka. Type 'help' for instructions").unwrap();
         writeln!(console, "").unwrap();
 
         VirtualMachine {
             cpu: cpu,
             console: console,
+            symbols: SymbolTable::new(),
+            gdb_server: None,
+            dap_server: None,
+            source_path: None,
+            call_stack: Vec::new(),
             segments: Vec::new(),
             clock_rate: clock_rate.into(),
             monitor: MemoryMonitor {
@@ -51,11 +76,21 @@ impl<'a> VirtualMachine<'a> {
             breakpoints: [0; 64 * 1024],
             broken: false,
             step: false,
+            return_depth: None,
+            step_listing: true,
+            memory_view: 0,
         }
     }
 
-    pub fn render(&mut self, mut renderer: &mut Renderer) {
-        self.console.render(renderer);
+    /// A VM with a console that never touches the disk, running one
+    /// instruction per cycle, with the level symbols `Y_0` at $0200 and
+    /// `BEEF` at $0300
+    #[cfg(test)]
+    pub fn for_tests() -> VirtualMachine {
+        let mut vm = VirtualMachine::new(Cpu::new(), None, Console::new(::config::Configuration::default()));
+        vm.symbols.insert("Y_0", 0x0200);
+        vm.symbols.insert("BEEF", 0x0300);
+        vm
     }
 
     pub fn load_code_segments(&mut self, segments: Vec<CodeSegment>) {
@@ -70,16 +105,51 @@ impl<'a> VirtualMachine<'a> {
         self.cpu.registers.PC = self.segments[0].address;
     }
 
+    /// Loads the labels and constants from the assembly source that the
+    /// currently loaded code segments were assembled from
+    pub fn load_symbols<P>(&mut self, path: P) -> io::Result<()>
+        where P: AsRef<Path>
+    {
+        let mut file = File::open(path.as_ref())?;
+        let mut source = String::new();
+        file.read_to_string(&mut source)?;
+        self.symbols = SymbolTable::from_source(&source, &self.segments);
+        self.source_path = Some(path.as_ref().to_path_buf());
+        Ok(())
+    }
+
+    /// The assembly source the symbols were loaded from
+    pub fn source_path(&self) -> Option<&Path> {
+        self.source_path.as_ref().map(|path| path.as_path())
+    }
+
+    /// The subroutine calls made so far that haven't returned, innermost last
+    pub fn call_stack(&self) -> &[CallFrame] {
+        &self.call_stack
+    }
+
     /// Cycles the Virtual Machine CPU according to the clock rate
     pub fn cycle(&mut self) {
+        // The debug servers need the whole VM while they handle requests
+        if let Some(mut server) = self.gdb_server.take() {
+            server.poll(self);
+            self.gdb_server = Some(server);
+        }
+        if let Some(mut server) = self.dap_server.take() {
+            server.poll(self);
+            self.dap_server = Some(server);
+        }
+
         if let Some(clock_rate) = self.clock_rate {
             let mut n = 0;
             while (n < clock_rate && !self.broken) || self.step {
-                n += self.cpu.step().expect("SEGFAULT") as u32;
+                n += self.step_cpu();
+                self.check_return_depth();
                 if self.breakpoints[self.cpu.registers.PC as usize] > 0 {
                     self.broken = true;
+                    self.return_depth = None;
                     writeln!(self.console, "").unwrap();
-                    writeln!(self.console, "BREAKPOINT hit at {:04x}", self.cpu.registers.PC).unwrap();
+                    writeln!(self.console.styled(Style::Warning), "BREAKPOINT hit at {:04x}", self.cpu.registers.PC).unwrap();
                     // We are supposed to pass the current timestamp to prevent the keys which are
                     // used to toggle the console from inputing text into the console. As no key
                     // is pressed to open the console in this instance, passing the time is not
@@ -87,26 +157,63 @@ impl<'a> VirtualMachine<'a> {
                     self.console.toggle(0);
                 }
                 // If we stepped, dump the local disassembly
-                if self.step {
+                if self.step && self.step_listing {
                     self.dump_local_disassembly();
                 }
                 self.step = false;
             }
         } else {
-            self.cpu.step().expect("SEGFAULT");
-            if self.step {
+            self.step_cpu();
+            self.check_return_depth();
+            if self.step && self.step_listing {
                 self.dump_local_disassembly();
             }
             self.step = false;
             if self.breakpoints[self.cpu.registers.PC as usize] > 0 {
                 self.broken = true;
+                self.return_depth = None;
                 writeln!(self.console, "").unwrap();
-                writeln!(self.console, "BREAKPOINT hit at {:04x}", self.cpu.registers.PC).unwrap();
+                writeln!(self.console.styled(Style::Warning), "BREAKPOINT hit at {:04x}", self.cpu.registers.PC).unwrap();
                 self.console.toggle(0);
             }
         }
     }
 
+    /// Executes a single instruction, keeping the shadow call stack up to date
+    fn step_cpu(&mut self) -> u32 {
+        let pc = self.cpu.registers.PC;
+        let opcode = self.cpu.memory[pc as usize];
+        let cycles = self.cpu.step().expect("SEGFAULT") as u32;
+
+        match opcode {
+            JSR => {
+                self.call_stack.push(CallFrame {
+                    call_site: pc,
+                    target: self.cpu.registers.PC,
+                })
+            }
+            RTS => {
+                self.call_stack.pop();
+            }
+            _ => (),
+        }
+
+        cycles
+    }
+
+    /// Breaks once a step over or step out has returned from its subroutine
+    fn check_return_depth(&mut self) {
+        if let Some(depth) = self.return_depth {
+            if self.call_stack.len() <= depth {
+                self.return_depth = None;
+                self.broken = true;
+                if self.step_listing {
+                    self.dump_local_disassembly();
+                }
+            }
+        }
+    }
+
     pub fn enable_memory_monitor(&mut self, range: Range<usize>)  { 
         self.monitor.start_addr = range.start;
         self.monitor.end_addr = range.end;
@@ -119,19 +226,87 @@ impl<'a> VirtualMachine<'a> {
         self.monitor.enabled = false;
     }
 
+    /// Starts a GDB remote protocol server on the given localhost port
+    pub fn start_gdb_server(&mut self, port: u16) -> io::Result<u16> {
+        let server = GdbServer::bind(port)?;
+        let port = server.port();
+        self.gdb_server = Some(server);
+        Ok(port)
+    }
+    pub fn stop_gdb_server(&mut self) -> bool {
+        self.gdb_server.take().is_some()
+    }
+
+    /// Starts a Debug Adapter Protocol server on the given localhost port
+    pub fn start_dap_server(&mut self, port: u16) -> io::Result<u16> {
+        let server = DapServer::bind(port)?;
+        let port = server.port();
+        self.dap_server = Some(server);
+        Ok(port)
+    }
+    pub fn stop_dap_server(&mut self) -> bool {
+        self.dap_server.take().is_some()
+    }
+
     pub fn is_debugging(&self) -> bool {
         self.broken
     }
+    pub fn is_stepping(&self) -> bool {
+        self.step
+    }
 
     pub fn break_execution(&mut self) {
         self.broken = true;
+        self.return_depth = None;
     }
     pub fn continue_execution(&mut self) {
         self.broken = false;
+        self.return_depth = None;
     }
     pub fn step_execution(&mut self) {
         self.broken = true;
         self.step = true;
+        self.return_depth = None;
+    }
+    /// Steps a single instruction, unless it's a JSR, in which case the whole
+    /// subroutine runs and execution breaks once it has returned
+    pub fn step_over_execution(&mut self) {
+        if self.cpu.memory[self.cpu.registers.PC as usize] == JSR {
+            self.broken = false;
+            self.return_depth = Some(self.call_stack.len());
+        } else {
+            self.step_execution();
+        }
+    }
+    /// Runs until the RTS that returns from the current subroutine. Returns
+    /// false if no subroutine has been called.
+    pub fn step_out_execution(&mut self) -> bool {
+        if self.call_stack.is_empty() {
+            return false;
+        }
+        self.broken = false;
+        self.return_depth = Some(self.call_stack.len() - 1);
+        true
+    }
+    /// Stops the disassembly around the program counter being written to the
+    /// console after each step, e.g. while the debugger layout shows it
+    pub fn set_step_listing(&mut self, step_listing: bool) {
+        self.step_listing = step_listing;
+    }
+
+    /// Changes how many cycles the CPU runs each frame
+    pub fn set_clock_rate(&mut self, clock_rate: u32) {
+        self.clock_rate = Some(clock_rate);
+    }
+
+    pub fn memory_view(&self) -> usize {
+        self.memory_view
+    }
+    pub fn set_memory_view(&mut self, address: usize) {
+        self.memory_view = address & 0xFFFF;
+    }
+    pub fn is_breakpoint(&self, address: usize) -> bool {
+        self.breakpoints[address] > 0
     }
     pub fn toggle_breakpoint(&mut self, address: usize) -> bool {
         if self.breakpoints[address] > 0 {
@@ -154,34 +329,58 @@ impl<'a> VirtualMachine<'a> {
                                              pairs,
                                              segment.address,
                                              false);
-            for line in lines {
-                write!(self.console, "{}", line).unwrap();
-            }
+            write_disassembly(&mut self.console, lines);
         }
 
         writeln!(self.console, " ").unwrap();
     }
 
+    /// Returns the disassembled lines surrounding the program counter
+    pub fn local_disassembly(&self) -> Vec<String> {
+        if self.segments.is_empty() {
+            return Vec::new();
+        }
+
+        let pc = self.cpu.registers.PC as usize;
+        let local_segment = self.get_local_segment(pc);
+        let disassembler = Disassembler::with_offset(local_segment.address);
+        let pairs = disassembler.disassemble_with_addresses(&local_segment.code);
+        self.highlight_lines(pc, pairs, local_segment.address, true)
+    }
+
+    /// Returns up to `count` disassembled lines of the segment the program
+    /// counter is in, with the line at the program counter in the middle
+    /// where possible
+    pub fn disassembly_window(&self, count: usize) -> Vec<String> {
+        if self.segments.is_empty() {
+            return Vec::new();
+        }
+
+        let pc = self.cpu.registers.PC as usize;
+        let local_segment = self.get_local_segment(pc);
+        let disassembler = Disassembler::with_offset(local_segment.address);
+        let pairs = disassembler.disassemble_with_addresses(&local_segment.code);
+        let mut lines = self.highlight_lines(pc, pairs, local_segment.address, false);
+
+        let current = lines.iter().position(|line| line.starts_with('>')).unwrap_or(0);
+        let start = ::std::cmp::min(current.saturating_sub(count / 2), lines.len().saturating_sub(count));
+        lines.truncate(start + count);
+        lines.drain(..start);
+        lines
+    }
+
     pub fn dump_local_disassembly(&mut self) {
         writeln!(self.console, " ").unwrap();
 
-        let result = {
-            let pc = self.cpu.registers.PC as usize;
-            let local_segment = self.get_local_segment(pc);
-            let disassembler = Disassembler::with_offset(local_segment.address);
-            let pairs = disassembler.disassemble_with_addresses(&local_segment.code);
-            self.highlight_lines(pc, pairs, local_segment.address, true)
-        };
-        for line in result {
-            write!(self.console, "{}", line).unwrap();
-        }
+        let result = self.local_disassembly();
+        write_disassembly(&mut self.console, result);
         writeln!(self.console, "").unwrap();
     }
 
     pub fn dump_memory_page(&mut self, page: usize) {
         let mut addr = page * 0x100;
         for chunk in self.cpu.memory[page * 0x100..(page * 0x100) + 0x100].chunks(8) {
-            write!(self.console, "{:04X}: ", addr).unwrap();
+            write!(self.console.styled(Style::Dim), "{:04X}: ", addr).unwrap();
             for b in chunk {
                 write!(self.console, "{:02X} ", *b).unwrap();
             }
@@ -279,3 +478,94 @@ impl<'a> VirtualMachine<'a> {
         result
     }
 }
+
+/// Writes disassembled lines from `highlight_lines`, highlighting the line at
+/// the program counter and dimming the addresses of the others
+fn write_disassembly(console: &mut Console, lines: Vec<String>) {
+    for line in lines {
+        if line.starts_with('>') {
+            write!(console.styled(Style::Highlight), "{}", line).unwrap();
+        } else if line.len() > 8 && line.is_char_boundary(8) {
+            // A 4 character breakpoint marker, then the 4 digit address
+            write!(console, "{}", &line[..4]).unwrap();
+            write!(console.styled(Style::Dim), "{}", &line[4..8]).unwrap();
+            write!(console, "{}", &line[8..]).unwrap();
+        } else {
+            write!(console, "{}", line).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rs6502::CodeSegment;
+
+    use super::VirtualMachine;
+
+    /// A JSR to a subroutine of two NOPs, followed by NOPs
+    fn vm() -> VirtualMachine {
+        let mut vm = VirtualMachine::for_tests();
+        vm.set_clock_rate(100);
+        vm.set_step_listing(false);
+        vm.load_code_segments(vec![CodeSegment {
+                                       address: 0xC000,
+                                       code: vec![0x20, 0x08, 0xC0, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0x60],
+                                   }]);
+        vm.break_execution();
+        vm
+    }
+
+    #[test]
+    fn steps_into_a_subroutine() {
+        let mut vm = vm();
+        vm.step_execution();
+        vm.cycle();
+        assert_eq!(vm.cpu.registers.PC, 0xC008);
+        assert_eq!(vm.call_stack().len(), 1);
+        assert!(vm.is_debugging());
+    }
+
+    #[test]
+    fn steps_over_a_subroutine() {
+        let mut vm = vm();
+        vm.step_over_execution();
+        vm.cycle();
+        assert_eq!(vm.cpu.registers.PC, 0xC003);
+        assert!(vm.call_stack().is_empty());
+        assert!(vm.is_debugging());
+
+        // Anything other than a JSR is a single step
+        vm.step_over_execution();
+        vm.cycle();
+        assert_eq!(vm.cpu.registers.PC, 0xC004);
+    }
+
+    #[test]
+    fn steps_out_of_a_subroutine() {
+        let mut vm = vm();
+        assert!(!vm.step_out_execution());
+
+        vm.step_execution();
+        vm.cycle();
+        vm.step_execution();
+        vm.cycle();
+        assert_eq!(vm.cpu.registers.PC, 0xC009);
+
+        assert!(vm.step_out_execution());
+        vm.cycle();
+        assert_eq!(vm.cpu.registers.PC, 0xC003);
+        assert!(vm.call_stack().is_empty());
+        assert!(vm.is_debugging());
+    }
+
+    #[test]
+    fn stops_stepping_over_at_a_breakpoint() {
+        let mut vm = vm();
+        vm.toggle_breakpoint(0xC009);
+        vm.step_over_execution();
+        vm.cycle();
+        assert_eq!(vm.cpu.registers.PC, 0xC009);
+        assert_eq!(vm.call_stack().len(), 1);
+        assert!(vm.is_debugging());
+    }
+}